@@ -0,0 +1,259 @@
+// Roms kept in zip and gzip archives.
+//
+// A gzip file holds a single rom. A zip archive can hold many, along with
+// other files such as instructions. When it holds a single rom that is the one
+// loaded, otherwise one has to be picked by its name inside the archive, with
+// a path like `games.zip:inner/name.ch8`. Archives are recognised by their
+// contents rather than their extension.
+
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// The extensions of the roms looked for inside zip archives.
+pub const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+// Splits a path like `games.zip:inner/name.ch8` into the archive's path and
+// the name of the rom inside it, at the first colon that follows the path of
+// a file, or `-` for standard input. Paths to files, and paths with no file
+// before a colon, are returned whole.
+pub fn split_path(path: &str) -> (&str, Option<&str>) {
+    if Path::new(path).is_file() {
+        return (path, None);
+    }
+
+    path.match_indices(':')
+        .map(|(index, _)| (&path[..index], &path[index + 1..]))
+        .find(|(file, _)| *file == "-" || Path::new(file).is_file())
+        .map_or((path, None), |(file, name)| (file, Some(name)))
+}
+
+// Takes a rom out of an archive, or returns the bytes unchanged when they
+// aren't one.
+pub fn extract(bytes: &[u8], name: Option<&str>) -> Result<Vec<u8>, Error> {
+    if bytes.starts_with(&ZIP_MAGIC) {
+        return extract_zip(bytes, name);
+    }
+
+    if name.is_some() {
+        return Err(Error::NotAnArchive);
+    }
+
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut rom = vec![];
+        GzDecoder::new(bytes).read_to_end(&mut rom)?;
+        return Ok(rom);
+    }
+
+    Ok(bytes.to_vec())
+}
+
+// The names of the roms in a zip archive, sorted.
+pub fn roms(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    let archive = ZipArchive::new(Cursor::new(bytes))?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| is_rom(name))
+        .map(|name| name.to_string())
+        .collect();
+    names.sort();
+
+    Ok(names)
+}
+
+pub fn is_rom(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((_, extension)) => ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+fn extract_zip(bytes: &[u8], name: Option<&str>) -> Result<Vec<u8>, Error> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => {
+            let mut roms = roms(bytes)?;
+            match roms.len() {
+                0 => return Err(Error::NoRoms),
+                1 => roms.remove(0),
+                _ => return Err(Error::Ambiguous(roms)),
+            }
+        }
+    };
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
+    let mut file = match archive.by_name(&name) {
+        Err(ZipError::FileNotFound) => return Err(Error::NotFound(name)),
+        file => file?,
+    };
+
+    let mut rom = vec![];
+    file.read_to_end(&mut rom)?;
+
+    Ok(rom)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Zip(ZipError),
+    // The zip archive has no roms in it.
+    NoRoms,
+    // The zip archive has several roms and none was picked. Holds their names.
+    Ambiguous(Vec<String>),
+    // The rom picked isn't in the archive.
+    NotFound(String),
+    // A rom inside the file was picked, but it isn't a zip archive.
+    NotAnArchive,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Zip(err) => write!(f, "{}", err),
+            Error::NoRoms => write!(
+                f,
+                "there are no roms in the archive, looked for .{}",
+                ROM_EXTENSIONS.join(", .")
+            ),
+            Error::Ambiguous(names) => write!(
+                f,
+                "the archive has {} roms, pick one with ARCHIVE:NAME: {}",
+                names.len(),
+                names.join(", ")
+            ),
+            Error::NotFound(name) => write!(f, "{} isn't in the archive", name),
+            Error::NotAnArchive => write!(f, "only roms in zip archives can be picked by name"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Zip(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(error: ZipError) -> Self {
+        Error::Zip(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, bytes) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(bytes).unwrap();
+        gzip.finish().unwrap()
+    }
+
+    #[test]
+    fn extracts_the_only_rom() {
+        let archive = zip(&[
+            ("README.txt", b"Press 5"),
+            ("games/pong.CH8", &[0x00, 0xE0]),
+        ]);
+
+        assert_eq!(extract(&archive, None).unwrap(), [0x00, 0xE0]);
+        assert!(matches!(
+            extract(&zip(&[("README.txt", b"")]), None),
+            Err(Error::NoRoms)
+        ));
+    }
+
+    #[test]
+    fn picks_roms_by_their_nested_path() {
+        let archive = zip(&[
+            ("schip/blinky.sc8", &[1]),
+            ("chip8/pong.ch8", &[2]),
+            ("chip8/README.txt", &[3]),
+        ]);
+
+        // With several roms, one has to be picked.
+        match extract(&archive, None) {
+            Err(Error::Ambiguous(names)) => {
+                assert_eq!(names, ["chip8/pong.ch8", "schip/blinky.sc8"])
+            }
+            result => panic!("expected the roms to choose from, got {:?}", result),
+        }
+        assert_eq!(extract(&archive, Some("schip/blinky.sc8")).unwrap(), [1]);
+        // Any file can be picked by name, not only roms.
+        assert_eq!(extract(&archive, Some("chip8/README.txt")).unwrap(), [3]);
+        assert!(matches!(
+            extract(&archive, Some("blinky.sc8")),
+            Err(Error::NotFound(name)) if name == "blinky.sc8"
+        ));
+    }
+
+    #[test]
+    fn decompresses_gzip() {
+        let rom = [0x12, 0x00];
+
+        assert_eq!(extract(&gzip(&rom), None).unwrap(), rom);
+        assert!(matches!(
+            extract(&gzip(&rom), Some("rom.ch8")),
+            Err(Error::NotAnArchive)
+        ));
+        // Roms that aren't in an archive are left as they are.
+        assert_eq!(extract(&rom, None).unwrap(), rom);
+    }
+
+    #[test]
+    fn splits_paths_after_the_archive() {
+        let dir = std::env::temp_dir().join(format!("crab-8-{}-split", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("games.rom-pack");
+        fs::write(&archive, zip(&[])).unwrap();
+        let archive = archive.to_str().unwrap();
+
+        // Archives don't need a .zip extension, and names can be nested.
+        let path = format!("{}:schip/blinky.sc8", archive);
+        assert_eq!(split_path(&path), (archive, Some("schip/blinky.sc8")));
+        assert_eq!(split_path("-:pong.ch8"), ("-", Some("pong.ch8")));
+        assert_eq!(split_path(archive), (archive, None));
+
+        let missing = format!("{}/missing.zip:pong.ch8", dir.to_str().unwrap());
+        assert_eq!(split_path(&missing), (missing.as_str(), None));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
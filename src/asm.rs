@@ -0,0 +1,734 @@
+// Assembler for the mnemonic syntax used by the disassembler and the debug
+// output, e.g.
+//
+//         LD I, sprite        ; labels can be used anywhere an address can
+//     loop:
+//         DRW V0, V1, HEIGHT
+//         JP loop
+//     HEIGHT = 5
+//     sprite:
+//         db 0xF0, 0x90, 0x90, 0x90, 0xF0
+//
+// Mnemonics and registers are case insensitive, labels and constants are not.
+// Numbers may be decimal, hex (0x1F or #1F) or binary (0b0101 or %0101), and
+// operands may add or subtract any mix of numbers, labels and constants.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::chip8::{CLASSIC_MEMORY_SIZE, MEMORY_SIZE};
+use crate::instruction::Instruction;
+
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, Error> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        values: RefCell::new(HashMap::new()),
+        evaluating: RefCell::new(HashSet::new()),
+    };
+
+    // The first pass works out where every label lives, so that the second
+    // pass can resolve references to labels that haven't been seen yet.
+    let mut statements = vec![];
+    let mut addr = origin as usize;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (labels, statement) = parse_line(text, line)?;
+
+        for label in labels {
+            assembler.define(label, Symbol::Label(addr as u16), line)?;
+        }
+
+        match statement {
+            Some(Statement::Constant(name, expr)) => {
+                assembler.define(name, Symbol::Constant(expr.to_string(), line), line)?;
+            }
+            Some(statement) => {
+                addr += statement.size();
+                statements.push((line, statement));
+            }
+            None => {}
+        }
+    }
+
+    // Only XO-CHIP programs can address past 4KB, and `LD I, LONG` is the
+    // only way they can get there.
+    let memory_size = if statements.iter().any(|(_, statement)| statement.is_long()) {
+        MEMORY_SIZE
+    } else {
+        CLASSIC_MEMORY_SIZE
+    };
+
+    if addr > memory_size {
+        // Point at the first line that doesn't fit.
+        let mut end = origin as usize;
+        let line = statements
+            .iter()
+            .find_map(|(line, statement)| {
+                end += statement.size();
+                (end > memory_size).then_some(*line)
+            })
+            .unwrap_or(source.lines().count());
+
+        return Err(Error {
+            line,
+            message: format!(
+                "the program is {} bytes too big for memory",
+                addr - memory_size
+            ),
+        });
+    }
+
+    let mut bytes = vec![];
+
+    for (line, statement) in statements {
+        match statement {
+            Statement::Db(values) => {
+                for value in values {
+                    bytes.push(assembler.byte(value, line)?);
+                }
+            }
+            Statement::Dw(values) => {
+                for value in values {
+                    let word = assembler.word(value, line)?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            Statement::Instruction(mnemonic, operands) => {
+                let instruction = assembler.instruction(mnemonic, &operands, line)?;
+                bytes.extend_from_slice(&instruction.to_bytes());
+            }
+            Statement::Constant(..) => {}
+        }
+    }
+
+    Ok(bytes)
+}
+
+enum Statement<'a> {
+    Constant(&'a str, &'a str),
+    Db(Vec<&'a str>),
+    Dw(Vec<&'a str>),
+    Instruction(&'a str, Vec<&'a str>),
+}
+
+impl<'a> Statement<'a> {
+    fn size(&self) -> usize {
+        match self {
+            Statement::Constant(..) => 0,
+            Statement::Db(values) => values.len(),
+            Statement::Dw(values) => values.len() * 2,
+            // XO-CHIP's `LD I, LONG addr` is the only 4 byte instruction.
+            Statement::Instruction(..) if self.is_long() => 4,
+            Statement::Instruction(..) => 2,
+        }
+    }
+
+    fn is_long(&self) -> bool {
+        match self {
+            Statement::Instruction(_, operands) => operands
+                .iter()
+                .any(|text| matches!(Operand::parse(text), Operand::Long(_))),
+            _ => false,
+        }
+    }
+}
+
+fn parse_line(text: &str, line: usize) -> Result<(Vec<&str>, Option<Statement<'_>>), Error> {
+    let mut text = match text.find(';') {
+        Some(index) => &text[..index],
+        None => text,
+    }
+    .trim();
+
+    let mut labels = vec![];
+
+    // Any number of labels can precede the statement on a line.
+    while let Some(index) = text.find(':') {
+        let label = text[..index].trim();
+        if !is_identifier(label) {
+            break;
+        }
+
+        labels.push(label);
+        text = text[index + 1..].trim();
+    }
+
+    if text.is_empty() {
+        return Ok((labels, None));
+    }
+
+    if let Some(index) = text.find('=') {
+        let name = text[..index].trim();
+        let expr = text[index + 1..].trim();
+
+        if !is_identifier(name) {
+            return Err(Error {
+                line,
+                message: format!("`{}` is not a valid constant name", name),
+            });
+        }
+
+        if expr.is_empty() {
+            return Err(Error {
+                line,
+                message: format!("constant `{}` has no value", name),
+            });
+        }
+
+        return Ok((labels, Some(Statement::Constant(name, expr))));
+    }
+
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, ""),
+    };
+
+    let operands: Vec<&str> = if rest.is_empty() {
+        vec![]
+    } else {
+        rest.split(',').map(|operand| operand.trim()).collect()
+    };
+
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err(Error {
+            line,
+            message: format!("missing operand in `{}`", text),
+        });
+    }
+
+    let statement = match mnemonic.to_ascii_lowercase().as_str() {
+        "db" => Statement::Db(operands),
+        "dw" => Statement::Dw(operands),
+        _ => Statement::Instruction(mnemonic, operands),
+    };
+
+    if statement.size() == 0 {
+        return Err(Error {
+            line,
+            message: format!("`{}` needs at least one value", mnemonic),
+        });
+    }
+
+    Ok((labels, Some(statement)))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+enum Symbol {
+    Label(u16),
+    // Constants are kept as text and evaluated when they are first used, so
+    // they can refer to labels defined later in the file.
+    Constant(String, usize),
+}
+
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    // The values of constants evaluated so far. Without these a constant
+    // that refers to another twice takes exponential time to evaluate.
+    values: RefCell<HashMap<String, i64>>,
+    // The constants currently being evaluated, to catch ones that refer to
+    // themselves.
+    evaluating: RefCell<HashSet<String>>,
+}
+
+impl Assembler {
+    fn define(&mut self, name: &str, symbol: Symbol, line: usize) -> Result<(), Error> {
+        if parse_register(name).is_some() || Operand::parse(name).is_keyword() {
+            return Err(Error {
+                line,
+                message: format!("`{}` is reserved and can't be used as a name", name),
+            });
+        }
+
+        if self.symbols.contains_key(name) {
+            return Err(Error {
+                line,
+                message: format!("`{}` is defined more than once", name),
+            });
+        }
+
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn evaluate(&self, expr: &str, line: usize) -> Result<i64, Error> {
+        let mut total: i64 = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+
+        // Terms are separated by + and -. A trailing sentinel flushes the last
+        // term.
+        for c in expr.chars().chain(std::iter::once('+')) {
+            match c {
+                '+' | '-' => {
+                    let text = term.trim();
+                    if text.is_empty() {
+                        // A leading or doubled sign, e.g. `-1`.
+                        if c == '-' {
+                            sign = -sign;
+                        }
+                        continue;
+                    }
+
+                    total = self
+                        .term(text, line)?
+                        .checked_mul(sign)
+                        .and_then(|value| total.checked_add(value))
+                        .ok_or_else(|| Error {
+                            line,
+                            message: format!("`{}` is too big", expr.trim()),
+                        })?;
+                    sign = if c == '-' { -1 } else { 1 };
+                    term.clear();
+                }
+                _ => term.push(c),
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn term(&self, text: &str, line: usize) -> Result<i64, Error> {
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+
+        match self.symbols.get(text) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant(expr, defined)) => self.constant(text, expr, *defined),
+            None if is_identifier(text) => Err(Error {
+                line,
+                message: format!("`{}` is not defined", text),
+            }),
+            None => Err(Error {
+                line,
+                message: format!("`{}` is not a number or a name", text),
+            }),
+        }
+    }
+
+    fn constant(&self, name: &str, expr: &str, line: usize) -> Result<i64, Error> {
+        if let Some(value) = self.values.borrow().get(name) {
+            return Ok(*value);
+        }
+
+        if !self.evaluating.borrow_mut().insert(name.to_string()) {
+            return Err(Error {
+                line,
+                message: format!("`{}` is defined in terms of itself", name),
+            });
+        }
+
+        let value = self.evaluate(expr, line)?;
+        self.evaluating.borrow_mut().remove(name);
+        self.values.borrow_mut().insert(name.to_string(), value);
+        Ok(value)
+    }
+
+    fn ranged(
+        &self,
+        expr: &str,
+        line: usize,
+        min: i64,
+        max: i64,
+        kind: &str,
+    ) -> Result<i64, Error> {
+        let value = self.evaluate(expr, line)?;
+
+        if value < min || value > max {
+            let message = match parse_number(expr) {
+                Some(_) => format!("{} doesn't fit in {}", expr, kind),
+                None => format!("`{}` is {}, which doesn't fit in {}", expr, value, kind),
+            };
+
+            return Err(Error { line, message });
+        }
+
+        Ok(value)
+    }
+
+    fn byte(&self, expr: &str, line: usize) -> Result<u8, Error> {
+        // Negative bytes are allowed and stored as two's complement, which is
+        // handy for `ADD Vx, -1`.
+        Ok(self.ranged(expr, line, -128, 0xFF, "a byte")? as u8)
+    }
+
+    fn word(&self, expr: &str, line: usize) -> Result<u16, Error> {
+        Ok(self.ranged(expr, line, -0x8000, 0xFFFF, "a word")? as u16)
+    }
+
+    fn addr(&self, expr: &str, line: usize) -> Result<u16, Error> {
+        Ok(self.ranged(expr, line, 0, 0xFFF, "a 12-bit address")? as u16)
+    }
+
+    fn long_addr(&self, expr: &str, line: usize) -> Result<u16, Error> {
+        Ok(self.ranged(expr, line, 0, 0xFFFF, "a 16-bit address")? as u16)
+    }
+
+    fn nibble(&self, expr: &str, line: usize) -> Result<u8, Error> {
+        Ok(self.ranged(expr, line, 0, 0xF, "a nibble")? as u8)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[&str],
+        line: usize,
+    ) -> Result<Instruction, Error> {
+        use Operand::*;
+
+        let parsed: Vec<Operand> = operands.iter().map(|text| Operand::parse(text)).collect();
+        let upper = mnemonic.to_ascii_uppercase();
+
+        let instruction = match (upper.as_str(), parsed.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n)]) => Instruction::Scd(self.nibble(n, line)?),
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("PLANE", [Value(n)]) => Instruction::Plane(self.nibble(n, line)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+            ("SYS", [Value(addr)]) => Instruction::Sys(self.addr(addr, line)?),
+            ("JP", [Value(addr)]) => Instruction::Jp(self.addr(addr, line)?),
+            ("JP", [Register(0), Value(addr)]) => Instruction::JpV0(self.addr(addr, line)?),
+            ("CALL", [Value(addr)]) => Instruction::Call(self.addr(addr, line)?),
+            ("SE", [Register(x), Register(y)]) => Instruction::SeReg(*x, *y),
+            ("SE", [Register(x), Value(kk)]) => Instruction::SeByte(*x, self.byte(kk, line)?),
+            ("SNE", [Register(x), Register(y)]) => Instruction::SneReg(*x, *y),
+            ("SNE", [Register(x), Value(kk)]) => Instruction::SneByte(*x, self.byte(kk, line)?),
+            ("LD", [Register(x), Register(y)]) => Instruction::LdReg(*x, *y),
+            ("LD", [Register(x), Value(kk)]) => Instruction::LdByte(*x, self.byte(kk, line)?),
+            ("LD", [I, Value(addr)]) => Instruction::LdI(self.addr(addr, line)?),
+            ("LD", [I, Long(addr)]) => Instruction::LdILong(self.long_addr(addr, line)?),
+            ("LD", [IndirectI, Range(x, y)]) => Instruction::LdIRange(*x, *y),
+            ("LD", [Range(x, y), IndirectI]) => Instruction::LdRangeI(*x, *y),
+            ("LD", [Register(x), Dt]) => Instruction::LdVxDt(*x),
+            ("LD", [Register(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [Dt, Register(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [St, Register(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, Register(x)]) => Instruction::LdF(*x),
+            ("LD", [Hf, Register(x)]) => Instruction::LdHf(*x),
+            ("LD", [B, Register(x)]) => Instruction::LdB(*x),
+            ("LD", [IndirectI, Register(x)]) => Instruction::LdIVx(*x),
+            ("LD", [Register(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("LD", [R, Register(x)]) => Instruction::LdRVx(*x),
+            ("LD", [Register(x), R]) => Instruction::LdVxR(*x),
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [Register(x), Value(kk)]) => Instruction::AddByte(*x, self.byte(kk, line)?),
+            ("ADD", [I, Register(x)]) => Instruction::AddI(*x),
+            ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+            ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn(*x, *y),
+            // With a single operand Vy is set to Vx, so the shift behaves the
+            // same whether or not the interpreter uses Vy.
+            ("SHR", [Register(x)]) => Instruction::Shr(*x, *x),
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr(*x, *y),
+            ("SHL", [Register(x)]) => Instruction::Shl(*x, *x),
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl(*x, *y),
+            ("RND", [Register(x), Value(kk)]) => Instruction::Rnd(*x, self.byte(kk, line)?),
+            ("DRW", [Register(x), Register(y), Value(n)]) => {
+                Instruction::Drw(*x, *y, self.nibble(n, line)?)
+            }
+            ("SKP", [Register(x)]) => Instruction::Skp(*x),
+            ("SKNP", [Register(x)]) => Instruction::Sknp(*x),
+            (
+                "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+                | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "SCD"
+                | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "PLANE" | "AUDIO" | "PITCH",
+                _,
+            ) => {
+                return Err(Error {
+                    line,
+                    message: format!(
+                        "`{}` can't take the operands `{}`",
+                        upper,
+                        operands.join(", ")
+                    ),
+                });
+            }
+            _ => {
+                return Err(Error {
+                    line,
+                    message: format!("`{}` is not an instruction", mnemonic),
+                });
+            }
+        };
+
+        Ok(instruction)
+    }
+}
+
+enum Operand<'a> {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    // XO-CHIP's `LONG addr` and `Vx-Vy`.
+    Long(&'a str),
+    Range(u8, u8),
+    Value(&'a str),
+}
+
+impl<'a> Operand<'a> {
+    fn parse(text: &'a str) -> Self {
+        if let Some(register) = parse_register(text) {
+            return Operand::Register(register);
+        }
+
+        if let Some((x, y)) = text.split_once('-') {
+            if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+                return Operand::Range(x, y);
+            }
+        }
+
+        match text.get(..5) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("long ") => {
+                return Operand::Long(text[5..].trim());
+            }
+            _ => {}
+        }
+
+        match text.to_ascii_uppercase().as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "R" => Operand::R,
+            _ => Operand::Value(text),
+        }
+    }
+
+    fn is_keyword(&self) -> bool {
+        !matches!(
+            self,
+            Operand::Value(_) | Operand::Long(_) | Operand::Range(..)
+        )
+    }
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V' | 'v'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    fn error(source: &str) -> Error {
+        assemble(source, 0x200).expect_err("the source shouldn't assemble")
+    }
+
+    // Disassembling a rom and assembling the listing gives back the same
+    // bytes.
+    fn round_trip(rom: &[u8], origin: u16) {
+        let listing = disasm::disassemble(rom, origin).to_string();
+        let bytes =
+            assemble(&listing, origin).unwrap_or_else(|err| panic!("{} in\n{}", err, listing));
+        assert_eq!(bytes, rom, "listing:\n{}", listing);
+    }
+
+    #[test]
+    fn round_trips_code_and_data() {
+        round_trip(
+            &[
+                0x00, 0xE0, 0xA2, 0x0A, 0x22, 0x10, 0x30, 0x00, 0x12, 0x06, 0xF0, 0x90, 0x90, 0x90,
+                0xF0, 0x00, 0xD0, 0x15, 0x00, 0xEE,
+            ],
+            0x200,
+        );
+    }
+
+    #[test]
+    fn round_trips_targets_outside_the_rom() {
+        // JP 000, CALL FFF, LD I, 100 and JP V0, 300 all point outside it.
+        round_trip(&[0x12, 0x04, 0x00, 0xE0, 0x10, 0x00], 0x200);
+        round_trip(&[0x2F, 0xFF, 0xA1, 0x00, 0xB3, 0x00], 0x200);
+        round_trip(&[0x16, 0x02, 0xF0, 0x00, 0x80, 0x00], 0x600);
+    }
+
+    #[test]
+    fn round_trips_random_roms() {
+        // A fixed xorshift, so failures can be reproduced.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..300 {
+            let length = 1 + next() as usize % 400;
+            let rom: Vec<u8> = (0..length).map(|_| next() as u8).collect();
+            round_trip(&rom, 0x200);
+            round_trip(&rom, 0x600);
+        }
+    }
+
+    #[test]
+    fn resolves_labels_before_and_after_use() {
+        let source = "
+            start:
+                JP end
+            loop: JP loop
+            end: JP start
+        ";
+
+        assert_eq!(
+            assemble(source, 0x200).unwrap(),
+            [0x12, 0x04, 0x12, 0x02, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn evaluates_constants_and_sums() {
+        let source = "
+            HEIGHT = WIDTH - 3
+            WIDTH = 8
+                LD V0, HEIGHT + 0x10
+                ADD V1, -1
+                LD I, sprite + 2
+                DRW V0, V1, HEIGHT
+            sprite:
+        ";
+
+        assert_eq!(
+            assemble(source, 0x200).unwrap(),
+            [0x60, 0x15, 0x71, 0xFF, 0xA2, 0x0A, 0xD0, 0x15]
+        );
+    }
+
+    #[test]
+    fn stores_data_big_endian() {
+        let source = "db 1, 0x2, #3, 0b100, %101\ndw 0x1234, -1";
+
+        assert_eq!(
+            assemble(source, 0x200).unwrap(),
+            [1, 2, 3, 4, 5, 0x12, 0x34, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let err = error("CLS\n\nJP nowhere");
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "`nowhere` is not defined");
+
+        let err = error("CLS\nLD V0, 256");
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "256 doesn't fit in a byte");
+
+        let err = error("x: CLS\nx: RET");
+        assert_eq!(err.line, 2);
+
+        let err = error("FROB V0");
+        assert_eq!(err.message, "`FROB` is not an instruction");
+    }
+
+    #[test]
+    fn reports_constants_defined_in_terms_of_themselves() {
+        let err = error("FOO = BAR\nBAR = FOO + 1\nLD V0, FOO");
+        assert!(err.message.ends_with("is defined in terms of itself"));
+    }
+
+    #[test]
+    fn evaluates_each_constant_once() {
+        // Evaluating A60 without remembering values would take 2^60 steps.
+        let mut source = String::from("A0 = 1\n");
+        for n in 1..=60 {
+            source += &format!("A{} = A{} + A{}\n", n, n - 1, n - 1);
+        }
+        source += "LD V0, A60 - A60 + A3";
+
+        assert_eq!(assemble(&source, 0x200).unwrap(), [0x60, 0x08]);
+    }
+
+    #[test]
+    fn reports_the_line_that_overflows_memory() {
+        let source = "CLS\n".repeat(0x701) + &"RET\n".repeat(5);
+
+        let err = error(&source);
+        assert_eq!(err.line, 0x701);
+        assert_eq!(err.message, "the program is 12 bytes too big for memory");
+    }
+
+    #[test]
+    fn only_long_programs_go_past_4kb() {
+        let data = format!("db {}", vec!["0"; 0xE01].join(", "));
+        let err = error(&data);
+        assert_eq!(err.message, "the program is 1 bytes too big for memory");
+
+        let long = format!("LD I, LONG 0x1000\n{}", data);
+        assert_eq!(assemble(&long, 0x200).unwrap().len(), 0xE05);
+
+        let err = error(&format!("LD I, LONG 0x1000\n{}", "CLS\n".repeat(0x7F00)));
+        assert_eq!(err.line, 0x7F00);
+        assert_eq!(err.message, "the program is 4 bytes too big for memory");
+    }
+
+    #[test]
+    fn reports_sums_that_overflow() {
+        let err = error("LD V0, 0x7FFFFFFFFFFFFFFF + 1");
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "`0x7FFFFFFFFFFFFFFF + 1` is too big");
+    }
+}
@@ -4,12 +4,42 @@ pub struct SquareWave {
     pub phase_inc: f32,
     pub phase: f32,
     pub volume: f32,
+
+    // XO-CHIP programs can replace the tone with a pattern of 128 1-bit
+    // samples, which loops at a rate set by the pitch register.
+    pub pattern: Option<[u8; 16]>,
+    pub pattern_inc: f32,
+    pub pattern_phase: f32,
+    pub freq: f32,
+}
+
+impl SquareWave {
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.pattern = pattern.copied();
+
+        // The pattern plays at 4000 samples per second at the default pitch
+        // of 64, doubling for every 48 the pitch goes up.
+        let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+        self.pattern_inc = rate / self.freq;
+    }
 }
 
 impl AudioCallback for SquareWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
+        if let Some(pattern) = self.pattern {
+            for x in out.iter_mut() {
+                let bit = self.pattern_phase as usize;
+                let on = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+                *x = if on { self.volume } else { -self.volume };
+                self.pattern_phase = (self.pattern_phase + self.pattern_inc) % 128.0;
+            }
+
+            return;
+        }
+
         // Generate a square wave
         for x in out.iter_mut() {
             *x = if self.phase <= 0.5 {
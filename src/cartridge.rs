@@ -0,0 +1,321 @@
+// Octo cartridges: GIF images of a cartridge with the game's label on it,
+// which carry the game's source code and settings in their pixels.
+// Reference: https://github.com/JohnEarnest/Octo
+//
+// Each pixel holds two bits of the payload in the low bits of its palette
+// index, four pixels to a byte with the highest bits first, through every
+// frame of the GIF in turn. The payload starts with its length as a 32 bit
+// big-endian number, followed by JSON like
+//
+//     {"program": ": main ...", "options": {"tickrate": 20, ...}}
+//
+// where the program is Octo source, and the options are those of Octo's
+// options panel.
+
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::asm;
+use crate::database::{is_color, Settings};
+use crate::gif;
+use crate::octo;
+use crate::quirks::Platform;
+
+pub struct Cartridge {
+    // The program's Octo source.
+    pub program: String,
+    // The options, as the settings `Machine::apply` and `Renderer::set_colors`
+    // take. Cartridges don't have a title or key bindings.
+    pub settings: Settings,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Options {
+    tickrate: Option<u32>,
+    background_color: Option<String>,
+    fill_color: Option<String>,
+    fill_color2: Option<String>,
+    blend_color: Option<String>,
+    shift_quirks: bool,
+    load_store_quirks: bool,
+    clip_quirks: bool,
+    jump_quirks: bool,
+    v_blank_quirks: bool,
+    logic_quirks: bool,
+}
+
+// Whether the bytes are a GIF image, and so maybe a cartridge.
+pub fn is_cartridge(bytes: &[u8]) -> bool {
+    gif::is_gif(bytes)
+}
+
+impl Cartridge {
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let gif = gif::decode(bytes)?;
+
+        let mut payload = vec![];
+        for pixels in gif.frames.iter().flat_map(|frame| frame.pixels.chunks(4)) {
+            payload.push(
+                pixels
+                    .iter()
+                    .fold(0, |byte, pixel| byte << 2 | pixel & 0b11),
+            );
+        }
+
+        let (length, json) = match payload.split_first_chunk::<4>() {
+            Some((length, json)) => (u32::from_be_bytes(*length) as usize, json),
+            None => return Err(Error::Payload("the image is too small".to_string())),
+        };
+        let json = json.get(..length).ok_or_else(|| {
+            Error::Payload(format!(
+                "the payload is {} bytes, but the image only holds {}",
+                length,
+                json.len()
+            ))
+        })?;
+
+        let payload: Payload = serde_json::from_slice(json)?;
+
+        Ok(Self {
+            program: payload.program,
+            settings: payload.options.settings(),
+        })
+    }
+
+    pub fn compile(&self) -> Result<Vec<u8>, Error> {
+        Ok(octo::compile(&self.program)?)
+    }
+}
+
+impl Options {
+    fn settings(&self) -> Settings {
+        // Octo runs XO-CHIP programs with every quirk off, and each option
+        // switches on one of SUPER-CHIP's or the VIP's behaviours.
+        // NOTE: vfOrderQuirks isn't read, since VF is always set after the
+        //       result here.
+        let platform = Platform::Xochip;
+        let default = platform.quirks();
+        let quirks = [
+            ("shifting", default.shifting, self.shift_quirks),
+            ("memory", default.memory, !self.load_store_quirks),
+            ("clipping", default.clipping, self.clip_quirks),
+            ("jumping", default.jumping, self.jump_quirks),
+            ("display-wait", default.display_wait, self.v_blank_quirks),
+            ("vf-reset", default.vf_reset, self.logic_quirks),
+        ];
+
+        let color = |color: &Option<String>| color.clone().filter(|color| is_color(color));
+
+        Settings {
+            platform: Some(platform),
+            quirks: quirks
+                .into_iter()
+                .filter(|(_, default, on)| default != on)
+                .map(|(name, _, on)| (name.to_string(), on))
+                .collect(),
+            instructions_per_frame: self.tickrate.filter(|tickrate| *tickrate > 0),
+            colors: [
+                color(&self.background_color),
+                color(&self.fill_color),
+                color(&self.fill_color2),
+                color(&self.blend_color),
+            ],
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Gif(gif::Error),
+    // The image doesn't hold a whole payload.
+    Payload(String),
+    Json(serde_json::Error),
+    // The program couldn't be compiled.
+    Compile(asm::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Gif(err) => write!(f, "bad GIF image: {}", err),
+            Error::Payload(message) => write!(f, "not an Octo cartridge: {}", message),
+            Error::Json(err) => write!(f, "not an Octo cartridge: {}", err),
+            Error::Compile(err) => write!(f, "the cartridge's program, {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Gif(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Compile(err) => Some(err),
+            Error::Payload(_) => None,
+        }
+    }
+}
+
+impl From<gif::Error> for Error {
+    fn from(error: gif::Error) -> Self {
+        Error::Gif(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+impl From<asm::Error> for Error {
+    fn from(error: asm::Error) -> Self {
+        Error::Compile(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACE: &[u8] = include_bytes!("../tests/cartridges/face.gif");
+    const FACE_INTERLACED: &[u8] = include_bytes!("../tests/cartridges/face-interlaced.gif");
+    const LARGE: &[u8] = include_bytes!("../tests/cartridges/large.gif");
+
+    const PROGRAM: &str = "\
+: face 0x3C 0x42 0xA5 0x81 0xA5 0x99 0x42 0x3C
+: main
+  v0 := 10
+  v1 := 8
+  i := face
+  sprite v0 v1 8
+  loop again
+";
+
+    // A GIF of one image 4 pixels wide holding just `payload`, with the high
+    // bits of each pixel set like a cartridge's label.
+    fn cartridge(payload: &[u8]) -> Vec<u8> {
+        let pixels: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| {
+                [byte >> 6, byte >> 4, byte >> 2, *byte].map(|bits| bits & 0b11 | 0b100)
+            })
+            .collect();
+        let height = payload.len() as u8;
+
+        // Uncompressed, with a clear code before every pixel so the code size
+        // never grows.
+        let mut data = vec![];
+        let mut bits = 0u32;
+        let mut count = 0;
+        for code in pixels
+            .iter()
+            .flat_map(|pixel| [8, *pixel as u32])
+            .chain([9])
+        {
+            bits |= code << count;
+            count += 4;
+            if count == 8 {
+                data.push(bits as u8);
+                bits = 0;
+                count = 0;
+            }
+        }
+        if count > 0 {
+            data.push(bits as u8);
+        }
+
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&[4, 0, height, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0x2C, 0, 0, 0, 0, 4, 0, height, 0, 0, 3]);
+        bytes.push(data.len() as u8);
+        bytes.extend_from_slice(&data);
+        bytes.extend_from_slice(b"\x00\x3B");
+        bytes
+    }
+
+    #[test]
+    fn decodes_the_program() {
+        for bytes in [FACE, FACE_INTERLACED, LARGE] {
+            let cartridge = Cartridge::decode(bytes).unwrap();
+            assert_eq!(cartridge.program, PROGRAM);
+            assert!(!cartridge.compile().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn reads_the_options() {
+        let settings = Cartridge::decode(FACE).unwrap().settings;
+
+        assert_eq!(settings.platform, Some(Platform::Xochip));
+        assert_eq!(settings.instructions_per_frame, Some(20));
+        assert_eq!(
+            settings.quirks,
+            [
+                ("shifting".to_string(), true),
+                ("memory".to_string(), false),
+                ("vf-reset".to_string(), true),
+            ]
+        );
+
+        let quirks = settings.quirks();
+        assert!(quirks.shifting && quirks.vf_reset);
+        assert!(!quirks.memory && !quirks.clipping && !quirks.jumping);
+
+        // fillColor2 isn't a colour, so it is left to the default.
+        assert_eq!(
+            settings.colors,
+            [
+                Some("#102030".to_string()),
+                Some("#FF00FF".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_a_payload_as_long_as_it_says() {
+        // The image holds more than the payload needs.
+        let cartridge = Cartridge::decode(&cartridge(b"\0\0\0\x0E{\"program\":\"\"}...")).unwrap();
+        assert_eq!(cartridge.program, "");
+        assert_eq!(cartridge.settings.platform, Some(Platform::Xochip));
+        assert!(cartridge.settings.quirks.is_empty());
+        assert_eq!(cartridge.settings.instructions_per_frame, None);
+    }
+
+    #[test]
+    fn rejects_bad_cartridges() {
+        let err = Cartridge::decode(&cartridge(b"\0\0")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "not an Octo cartridge: the image is too small"
+        );
+
+        let err = Cartridge::decode(&cartridge(b"\0\0\0\x10{}"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "not an Octo cartridge: the payload is 16 bytes, but the image only holds 2"
+        );
+
+        let err = Cartridge::decode(&cartridge(b"\0\0\0\x02{}"))
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::Json(_)));
+
+        let err = Cartridge::decode(&FACE[..FACE.len() - 1]).err().unwrap();
+        assert!(matches!(err, Error::Gif(_)));
+    }
+}
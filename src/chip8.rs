@@ -1,29 +1,73 @@
 // Reference: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 
-use rand::Rng;
-use std::{fs, time::SystemTime};
-
-use crate::display::{Collision, Display, Sprite};
-use crate::keymap::KeyMap;
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+use std::{fmt, ops::Range};
+
+use crate::display::{Display, Sprite};
+use crate::input::Input;
+use crate::instruction::{decode_long, DecodeError, Instruction};
+use crate::quirks::{Platform, Quirks};
+use crate::rng::{Algorithm, Rng};
+use crate::rom::{self, Rom};
+use crate::state::{self, Reader, Writer};
+use crate::trace::Tracer;
 
 // 2.1 - Memory
 // Most Chip-8 programs start at location 0x200 (512), but some begin at
 // 0x600 (1536). Programs beginning at 0x600 are intended for the ETI 660
 // computer.
-const NORMAL_START_INDEX: usize = 512;
-const ETI_660_START_INDEX: usize = 1526;
+pub const NORMAL_START_INDEX: usize = 512;
+pub const ETI_660_START_INDEX: usize = 1536;
+
+// XO-CHIP extends memory to 64KB, which is how much `ram` always holds.
+// Programs for the other platforms can only address the first 4KB, and the
+// platform says how much a program can use.
+pub const MEMORY_SIZE: usize = 0x10000;
+pub const CLASSIC_MEMORY_SIZE: usize = 0x1000;
+
+// The small hexadecimal font takes the first 80 bytes of memory, and the
+// SUPER-CHIP large font follows it.
+const LARGE_FONT_INDEX: usize = 0x50;
+
+// Where and how `Chip8::load_rom` loads a rom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    // The address the rom is loaded at, and where execution starts.
+    pub address: usize,
+    // The platform the rom was written for. When it is given, it replaces the
+    // current platform and its quirks replace the current ones.
+    pub platform: Option<Platform>,
+}
+
+impl LoadOptions {
+    pub fn new() -> Self {
+        Self {
+            address: NORMAL_START_INDEX,
+            platform: None,
+        }
+    }
+
+    // Loads the rom at 0x600, for the ETI 660.
+    pub fn eti_660() -> Self {
+        Self {
+            address: ETI_660_START_INDEX,
+            ..Self::new()
+        }
+    }
+}
 
-// 2.2 - Regissters
-// Chip-8 also has two special purpose 8-bit registers, for the delay and sound
-// timers. When these registers are non-zero, they are automatically decremented
-// at a rate of 60Hz.
-const CLOCK_CYCLE: f64 = 1.0 / 60.0;
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub struct Chip8 {
     // 2.1 - Memory
     // The Chip-8 language is capable of accessing up to 4KB (4,096 bytes) of
     // RAM, from location 0x000 (0) to 0xFFF (4095).
-    ram: [u8; 4096],
+    ram: Vec<u8>,
 
     // 2.2 - Registers
     registers: Registers,
@@ -48,338 +92,655 @@ pub struct Chip8 {
 
     pub display: Display,
 
+    // SUPER-CHIP can save registers to the HP-48's RPL user flags, which
+    // survive the program exiting. SUPER-CHIP has 8 of them, XO-CHIP 16.
+    rpl: [u8; 16],
+
+    // The 128 1-bit samples loaded by XO-CHIP's F002 - AUDIO, which the buzzer
+    // plays instead of its tone once set.
+    audio_pattern: Option<[u8; 16]>,
+
+    // The XO-CHIP pitch register, which sets the playback rate of the audio
+    // pattern.
+    pitch: u8,
+
+    rng: Rng,
+
+    // Set by 00FD - EXIT, after which the interpreter does nothing.
+    exited: bool,
+
     debug_output: bool,
 
-    start_time: Option<SystemTime>,
+    // The platform decides which instructions the rom can use, and quirks
+    // how the ambiguous ones behave.
+    platform: Platform,
+    quirks: Quirks,
+
+    // Set by each 60Hz timer tick and cleared by drawing, for the display wait
+    // quirk.
+    vblank: bool,
+
+    // How many instructions `run_frame` runs before ticking the timers.
+    instructions_per_frame: u32,
+
+    // How many instructions of the current frame have run. A frame can be
+    // interrupted by a breakpoint or an error, and picks up where it left off.
+    frame_position: u32,
+
+    // Addresses at which `run_frame` stops before executing the instruction.
+    breakpoints: BTreeSet<usize>,
+
+    watchpoints: Vec<Watchpoint>,
+    // The first watched access made by the instruction being executed.
+    watched: Option<Watched>,
+    // Set by `step` when the instruction it ran hit a watchpoint.
+    watch_hit: Option<WatchHit>,
+
+    // Set whenever the display is drawn to, and taken by `run_frame`.
+    display_changed: bool,
+
+    // Set while Fx0A - LD Vx, K is waiting for a key.
+    waiting_for_key: bool,
+
+    // Set while Dxyn - DRW is waiting for the display to refresh.
+    waiting_for_display: bool,
+
+    // Records every instruction executed, for `--trace`.
+    tracer: Option<Tracer>,
+}
+
+// What happened during a call to `Chip8::run_frame`.
+#[derive(Debug)]
+pub struct FrameResult {
+    // Whether anything was drawn to the display, so it needs redrawing.
+    pub display_changed: bool,
+    pub sound_on: bool,
+    // Whether the program is blocked on Fx0A - LD Vx, K until a key is pressed.
+    pub waiting_for_key: bool,
+    // The breakpoint, watchpoint or error that interrupted the frame, if
+    // any. The timers aren't ticked for an interrupted frame, and the next
+    // call to `run_frame` finishes it.
+    pub breakpoint: Option<usize>,
+    pub watch: Option<WatchHit>,
+    pub error: Option<Error>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn includes(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+// Watchpoints stop execution after the instruction that hit them.
+// NOTE: Instruction fetches don't count as reads, and a register watchpoint
+//       only fires when an instruction changes the register, so the timers
+//       counting down don't trigger DT and ST watchpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    Memory {
+        addr: usize,
+        length: usize,
+        access: Access,
+    },
+    Register(Register),
+}
+
+// What a watchpoint saw. A read has the same old and new value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watched {
+    Memory {
+        addr: usize,
+        access: Access,
+        old: u8,
+        new: u8,
+    },
+    Register {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+}
+
+// A watchpoint being hit, and the instruction that hit it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: usize,
+    pub instruction: Instruction,
+    pub watched: Watched,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.watched {
+            Watched::Memory {
+                addr,
+                access: Access::Read,
+                new,
+                ..
+            } => write!(f, "{:04X} read as {:02X}", addr, new)?,
+            Watched::Memory { addr, old, new, .. } => {
+                write!(f, "{:04X} written, {:02X} -> {:02X}", addr, old, new)?
+            }
+            Watched::Register { register, old, new } => {
+                write!(f, "{} changed, {:02X} -> {:02X}", register, old, new)?
+            }
+        }
+
+        write!(f, " by {} at {:04X}", self.instruction, self.pc)
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Self {
         let mut new = Self {
-            ram: [0; 4096],
+            ram: vec![0; MEMORY_SIZE],
             registers: Registers::new(),
             pc: 0,
             sp: 0,
             stack: [0; 16],
             display: Display::new(),
+            rpl: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
+            rng: Rng::from_entropy(Algorithm::default()),
+            exited: false,
             debug_output: false,
-            start_time: None,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            vblank: false,
+            instructions_per_frame: 10,
+            frame_position: 0,
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            watched: None,
+            watch_hit: None,
+            display_changed: false,
+            waiting_for_key: false,
+            waiting_for_display: false,
+            tracer: None,
         };
 
         new.load_hexadecimal_display_bytes();
         new
     }
 
-    pub fn load_rom(&mut self, path: &str, eti_mode: bool) {
-        let bytes = fs::read(path).expect("Could not open file.");
+    // Reads a rom the way `Rom::read` does, then loads it. Octo cartridges
+    // also set the platform, quirks and speed they were saved with, unless
+    // the options give a platform.
+    pub fn load_rom(&mut self, path: &str, options: LoadOptions) -> Chip8Result {
+        let rom = Rom::read(path)?;
+
+        if let Some(settings) = &rom.settings {
+            self.platform = settings.platform.unwrap_or_default();
+            self.quirks = settings.quirks();
+            if let Some(instructions_per_frame) = settings.instructions_per_frame {
+                self.instructions_per_frame = instructions_per_frame;
+            }
+        }
+
+        self.load_rom_bytes(&rom.bytes, options)
+    }
+
+    pub fn load_rom_reader(&mut self, mut reader: impl Read, options: LoadOptions) -> Chip8Result {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        self.load_rom_bytes(&bytes, options)
+    }
+
+    pub fn load_rom_bytes(&mut self, bytes: &[u8], options: LoadOptions) -> Chip8Result {
+        let start_index = options.address;
 
-        let mut start_index = NORMAL_START_INDEX;
+        // The platform goes first, since it decides how much memory the rom
+        // can fill.
+        if let Some(platform) = options.platform {
+            self.platform = platform;
+            self.quirks = platform.quirks();
+        }
 
-        if eti_mode {
-            start_index = ETI_660_START_INDEX;
+        let size = self.platform.memory_size();
+        if start_index >= size {
+            return Err(Error::MemoryOutOfBounds { addr: start_index });
         }
 
-        for (index, byte) in bytes.iter().enumerate() {
-            self.ram[start_index + index] = *byte;
+        let max = size - start_index;
+        if bytes.len() > max {
+            return Err(Error::RomTooLarge {
+                size: bytes.len(),
+                max,
+            });
         }
 
+        self.ram[start_index..start_index + bytes.len()].copy_from_slice(bytes);
+
         self.pc = start_index;
 
-        eprintln!("bytes loaded: {}", bytes.len());
+        Ok(())
     }
 
-    pub fn step(&mut self, keymap: &KeyMap) -> Chip8Result {
-        if self.start_time.is_none() {
-            self.start_time = Some(SystemTime::now());
+    pub fn step(&mut self, input: &Input) -> Chip8Result {
+        if self.exited {
+            return Ok(());
         }
 
-        self.run_timers();
+        let instruction = self.current_instruction()?;
+        let pc = self.pc;
 
-        let high_byte = self.high_byte();
-        let low_byte = self.low_byte();
+        if !self.platform.supports(&instruction) {
+            return Err(DecodeError::UnrecognisedInstruction(self.instruction()).into());
+        }
 
-        match high(high_byte) {
-            0x0 => {
-                if *low_byte == 0xE0 {
-                    self.pc = self.clear();
-                } else if *low_byte == 0xEE {
-                    self.pc = self.ret();
-                } else {
-                    // 0nnn SYS opcodes are ignored on modern systems
-                    self.pc += 2
-                }
-            }
-            0x1 => {
-                self.pc = self.jump();
-            }
-            0x2 => {
-                self.pc = self.call();
-            }
-            0x3 => {
-                self.pc = self.skip_eq();
-            }
-            0x4 => {
-                self.pc = self.skip_neq();
-            }
-            0x5 => {
-                self.pc = self.skip_eq_reg();
-            }
-            0x6 => {
-                self.pc = self.load_vx();
-            }
-            0x7 => {
-                self.pc = self.add_vx();
-            }
-            0x8 => match low(low_byte) {
-                0 => {
-                    self.pc = self.set_vx_to_vy();
-                }
-                1 => {
-                    self.pc = self.vx_or_vy();
-                }
-                2 => {
-                    self.pc = self.vx_and_vy();
-                }
-                3 => {
-                    self.pc = self.vx_xor_vy();
-                }
-                4 => {
-                    self.pc = self.add_vx_and_vy();
-                }
-                5 => {
-                    self.pc = self.sub_vx_and_vy();
-                }
-                6 => {
-                    self.pc = self.vx_shr();
-                }
-                7 => {
-                    self.pc = self.vx_subn_vy();
-                }
-                0xE => {
-                    self.pc = self.vx_shl();
-                }
-                _ => {
-                    return Err(Error::UnrecognisedInstruction(*high_byte, *low_byte));
-                }
-            },
-            0x9 => {
-                self.pc = self.skip_vx_neq_vy();
-            }
-            0xA => {
-                self.pc = self.load_i();
+        // Watchpoints on registers that don't exist never fire.
+        let registers: Vec<(Register, u16)> = self
+            .watchpoints
+            .iter()
+            .filter_map(|watchpoint| match watchpoint {
+                Watchpoint::Register(register) => Some((*register, self.register(*register).ok()?)),
+                _ => None,
+            })
+            .collect();
+
+        let result = self.execute(instruction, input);
+        let watched = self.watched.take();
+        if let Some(mut tracer) = self.tracer.take() {
+            let traced = if result.is_ok() {
+                tracer.record(self, pc, instruction)
+            } else {
+                tracer.discard();
+                Ok(())
+            };
+            self.tracer = Some(tracer);
+            traced?;
+        }
+        result?;
+
+        let watched = watched.or_else(|| {
+            registers.into_iter().find_map(|(register, old)| {
+                let new = self.register(register).ok()?;
+                (new != old).then_some(Watched::Register { register, old, new })
+            })
+        });
+        self.watch_hit = watched.map(|watched| WatchHit {
+            pc,
+            instruction,
+            watched,
+        });
+
+        Ok(())
+    }
+
+    // Runs one 60Hz frame: `instructions_per_frame` instructions, then a tick
+    // of the timers. The frame ends early if the program exits, starts
+    // waiting for a key or waits for the display to refresh, since nothing
+    // more can happen until the next frame.
+    pub fn run_frame(&mut self, input: &Input) -> FrameResult {
+        self.run(input, false)
+    }
+
+    // Runs a single instruction of the current frame, ignoring breakpoints,
+    // and ticks the timers if that finishes the frame. This is how the
+    // debugger steps without changing the program's timing.
+    pub fn run_instruction(&mut self, input: &Input) -> FrameResult {
+        self.run(input, true)
+    }
+
+    fn run(&mut self, input: &Input, single: bool) -> FrameResult {
+        let mut breakpoint = None;
+        let mut watch = None;
+        let mut error = None;
+
+        loop {
+            if self.frame_position >= self.instructions_per_frame {
+                self.end_frame();
+                break;
             }
-            0xB => {
-                self.pc = self.jump_plus_v0();
+
+            if !single && self.breakpoints.contains(&self.pc) {
+                breakpoint = Some(self.pc);
+                break;
             }
-            0xC => {
-                self.pc = self.rand();
+
+            if let Err(err) = self.step(input) {
+                error = Some(err);
+                break;
             }
-            0xD => {
-                self.pc = self.draw();
+            self.frame_position += 1;
+            watch = self.watch_hit.take();
+
+            let finished = single && self.frame_position >= self.instructions_per_frame;
+            if self.exited || self.waiting_for_key || self.waiting_for_display || finished {
+                self.end_frame();
+                break;
             }
-            0xE => {
-                match low_byte {
-                    0x9E => {
-                        self.skip_pressed(keymap);
-                    }
-                    0xA1 => {
-                        self.skip_not_pressed(keymap);
-                    }
-                    _ => return Err(Error::UnrecognisedInstruction(*high_byte, *low_byte)),
-                };
+
+            if single || watch.is_some() {
+                break;
             }
-            0xF => match low_byte {
-                0x07 => {
-                    self.pc = self.set_vx_delay_timer();
-                }
-                0x0A => {
-                    self.pc = self.wait_and_load_key_press(keymap);
-                }
-                0x15 => {
-                    self.pc = self.set_delay_timer();
-                }
-                0x18 => {
-                    self.pc = self.set_sound_timer();
-                }
-                0x1E => {
-                    self.pc = self.add();
-                }
-                0x29 => {
-                    self.pc = self.set_i_to_sprite_vx();
-                }
-                0x33 => {
-                    self.pc = self.store_bcd();
-                }
-                0x55 => {
-                    self.pc = self.store_array();
-                }
-                0x65 => {
-                    self.pc = self.load_array();
-                }
-                _ => {
-                    return Err(Error::UnrecognisedInstruction(*high_byte, *low_byte));
-                }
-            },
-            _ => return Err(Error::UnrecognisedInstruction(*high_byte, *low_byte)),
         }
 
-        Ok(())
+        FrameResult {
+            display_changed: std::mem::take(&mut self.display_changed),
+            sound_on: self.sound_on(),
+            waiting_for_key: self.waiting_for_key,
+            breakpoint,
+            watch,
+            error,
+        }
     }
 
-    fn run_timers(&mut self) {
-        match self.start_time {
-            Some(ref time) => {
-                if !(time.elapsed().unwrap().as_secs_f64() % CLOCK_CYCLE <= 0.01) {
-                    return;
-                }
+    fn end_frame(&mut self) {
+        self.tick_timers();
+        self.frame_position = 0;
+    }
 
-                if self.registers.dt > 0 {
-                    self.registers.dt -= 1;
-                }
-                if self.registers.st > 0 {
-                    self.registers.st -= 1;
-                }
-            }
-            None => {}
+    pub fn execute(&mut self, instruction: Instruction, input: &Input) -> Chip8Result {
+        self.disassemble(&instruction);
+
+        self.pc = match instruction {
+            // 0nnn SYS opcodes are ignored on modern systems
+            Instruction::Sys(_) => self.pc + 2,
+            Instruction::Scd(n) => self.scroll_down(n),
+            Instruction::Cls => self.clear(),
+            Instruction::Ret => self.ret()?,
+            Instruction::Scr => self.scroll_right(),
+            Instruction::Scl => self.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::Low => self.set_resolution(false),
+            Instruction::High => self.set_resolution(true),
+            Instruction::Jp(addr) => self.jump(addr),
+            Instruction::Call(addr) => self.call(addr)?,
+            Instruction::SeByte(x, kk) => self.skip_eq(x, kk),
+            Instruction::SneByte(x, kk) => self.skip_neq(x, kk),
+            Instruction::SeReg(x, y) => self.skip_eq_reg(x, y),
+            Instruction::LdIRange(x, y) => self.store_range(x, y)?,
+            Instruction::LdRangeI(x, y) => self.load_range(x, y)?,
+            Instruction::LdByte(x, kk) => self.load_vx(x, kk),
+            Instruction::AddByte(x, kk) => self.add_vx(x, kk),
+            Instruction::LdReg(x, y) => self.set_vx_to_vy(x, y),
+            Instruction::Or(x, y) => self.vx_or_vy(x, y),
+            Instruction::And(x, y) => self.vx_and_vy(x, y),
+            Instruction::Xor(x, y) => self.vx_xor_vy(x, y),
+            Instruction::AddReg(x, y) => self.add_vx_and_vy(x, y),
+            Instruction::Sub(x, y) => self.sub_vx_and_vy(x, y),
+            Instruction::Shr(x, y) => self.vx_shr(x, y),
+            Instruction::Subn(x, y) => self.vx_subn_vy(x, y),
+            Instruction::Shl(x, y) => self.vx_shl(x, y),
+            Instruction::SneReg(x, y) => self.skip_vx_neq_vy(x, y),
+            Instruction::LdI(addr) => self.load_i(addr),
+            Instruction::JpV0(addr) => self.jump_plus_v0(addr),
+            Instruction::Rnd(x, kk) => self.rand(x, kk),
+            Instruction::Drw(x, y, n) => self.draw(x, y, n)?,
+            Instruction::Skp(x) => self.skip_pressed(x, input),
+            Instruction::Sknp(x) => self.skip_not_pressed(x, input),
+            Instruction::LdILong(addr) => self.load_i_long(addr),
+            Instruction::Plane(n) => self.select_planes(n),
+            Instruction::Audio => self.load_audio_pattern()?,
+            Instruction::LdVxDt(x) => self.set_vx_delay_timer(x),
+            Instruction::LdVxK(x) => self.wait_and_load_key_press(x, input),
+            Instruction::LdDtVx(x) => self.set_delay_timer(x),
+            Instruction::LdStVx(x) => self.set_sound_timer(x),
+            Instruction::AddI(x) => self.add(x),
+            Instruction::LdF(x) => self.set_i_to_sprite_vx(x),
+            Instruction::LdHf(x) => self.set_i_to_large_sprite_vx(x),
+            Instruction::LdB(x) => self.store_bcd(x)?,
+            Instruction::Pitch(x) => self.set_pitch(x),
+            Instruction::LdIVx(x) => self.store_array(x)?,
+            Instruction::LdVxI(x) => self.load_array(x)?,
+            Instruction::LdRVx(x) => self.store_flags(x),
+            Instruction::LdVxR(x) => self.load_flags(x),
+        };
+
+        Ok(())
+    }
+
+    // 2.2 - Registers
+    // Chip-8 also has two special purpose 8-bit registers, for the delay and
+    // sound timers. When these registers are non-zero, they are automatically
+    // decremented at a rate of 60Hz.
+    // NOTE: This is called once per emulated frame by `run_frame`, after
+    //       that frame's instructions, rather than the timers following the
+    //       host's clock. That way a run behaves the same however fast the
+    //       host is.
+    pub fn tick_timers(&mut self) {
+        if self.registers.dt > 0 {
+            self.registers.dt -= 1;
+        }
+        if self.registers.st > 0 {
+            self.registers.st -= 1;
         }
+
+        self.vblank = true;
+        self.rng.tick();
     }
 
     pub fn sound_on(&self) -> bool {
         self.registers.st > 0
     }
 
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // The program counter after skipping the next instruction.
+    // NOTE: XO-CHIP's F000 nnnn is 4 bytes long, and is skipped as a whole.
+    //       Other platforms don't have it, so always skip 2 bytes.
+    fn skip(&self) -> usize {
+        let next = self.pc + 2;
+
+        if self.platform == Platform::Xochip && self.word(next) == 0xF000 {
+            next + 4
+        } else {
+            next + 2
+        }
+    }
+
+    // 00Cn - SCD nibble (SUPER-CHIP)
+    fn scroll_down(&mut self, n: u8) -> usize {
+        // Scroll the display down by n pixels.
+        // NOTE: Like Octo, we scroll by pixels of the current resolution.
+        //       The original SUPER-CHIP always scrolled by high resolution
+        //       pixels, so half as far in low resolution.
+        self.display.scroll_down(n.into());
+        self.display_changed = true;
+
+        self.pc + 2
+    }
+
     // 00E0 - CLS
     fn clear(&mut self) -> usize {
-        self.disassemble("CLS");
-
         // Clear the display.
         self.display.clear();
+        self.display_changed = true;
 
         self.pc + 2
     }
 
     // 00EE - RET
-    fn ret(&mut self) -> usize {
-        self.disassemble("RET");
-
+    fn ret(&mut self) -> Result<usize, Error> {
         // The interpreter sets the program counter to the address at the top of
         // the stack, then subtracts 1 from the stack pointer.
 
         // NOTE: We do this in reverse order, as our stack pointer always points
         //       the next available space in the stack
 
+        if self.sp == 0 {
+            return Err(Error::StackUnderflow);
+        }
         self.sp -= 1;
 
         // NOTE: We add two here counter-intuitively, as the we want to execute
         //       the next instruction after the return point.
-        self.stack[self.sp] + 2
+        Ok(self.stack[self.sp] + 2)
     }
 
-    // 1nnn - JP addr
-    fn jump(&mut self) -> usize {
-        let addr = self.addr();
-        self.disassemble(format!("JP {}", addr).as_str());
+    // 00FB - SCR (SUPER-CHIP)
+    fn scroll_right(&mut self) -> usize {
+        // Scroll the display right by 4 pixels.
+        self.display.scroll_right(4);
+        self.display_changed = true;
+
+        self.pc + 2
+    }
+
+    // 00FC - SCL (SUPER-CHIP)
+    fn scroll_left(&mut self) -> usize {
+        // Scroll the display left by 4 pixels.
+        self.display.scroll_left(4);
+        self.display_changed = true;
 
+        self.pc + 2
+    }
+
+    // 00FD - EXIT (SUPER-CHIP)
+    fn exit(&mut self) -> usize {
+        // Exit the interpreter.
+        self.exited = true;
+
+        self.pc
+    }
+
+    // 00FE - LOW, 00FF - HIGH (SUPER-CHIP)
+    fn set_resolution(&mut self, hires: bool) -> usize {
+        // Switch between the 64x32 and 128x64 display modes.
+        self.display.set_hires(hires);
+        self.display_changed = true;
+
+        self.pc + 2
+    }
+
+    // 1nnn - JP addr
+    fn jump(&mut self, addr: u16) -> usize {
         // The interpreter sets the program counter to nnn.
         // As we always return the new program counter, we just return the addr
         addr.into()
     }
 
     // 2nnn - CALL addr
-    fn call(&mut self) -> usize {
-        let addr = self.addr();
-        self.disassemble(format!("CALL {}", addr).as_str());
-
+    fn call(&mut self, addr: u16) -> Result<usize, Error> {
         // The interpreter increments the stack pointer, then puts the current
         // PC on the top of the stack. The PC is then set to nnn.
 
         // NOTE: We do this action in reverse order, so the stack pointer always
         //       points to the next available space on stack
 
+        if self.sp == self.stack.len() {
+            return Err(Error::StackOverflow);
+        }
         self.stack[self.sp] = self.pc;
         self.sp += 1;
 
         // As we always return the new program counter, we just return the addr
-        addr.into()
+        Ok(addr.into())
     }
 
     // 3xkk - SE Vx, byte
-    fn skip_eq(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SE V{:x}, {}", x, self.low_byte()).as_str());
-
+    fn skip_eq(&mut self, x: u8, kk: u8) -> usize {
         // The interpreter compares register Vx to kk
         let contents = self.registers.get(x);
 
         // and if they are equal, increments the program counter by 2.
-        if contents == *self.low_byte() {
-            self.pc + 4
+        if contents == kk {
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
     // 4xkk - SNE Vx, byte
-    fn skip_neq(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SNE V{:x}, {}", x, self.low_byte()).as_str());
-
+    fn skip_neq(&mut self, x: u8, kk: u8) -> usize {
         // The interpreter compares register Vx to kk
         let contents = self.registers.get(x);
 
         // and if they are not equal, increments the program counter by 2.
-        if contents != *self.low_byte() {
-            self.pc + 4
+        if contents != kk {
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
     // 5xy0 - SE Vx, Vy
-    fn skip_eq_reg(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("SE V{:x}, V{:x}", x, y).as_str());
-
+    fn skip_eq_reg(&mut self, x: u8, y: u8) -> usize {
         // The interpreter compares register Vx to register Vy, and if they are
         // equal, increments the program counter by 2.
         if self.registers.get(x) == self.registers.get(y) {
-            self.pc + 4
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
-    // 6xkk - LD Vx, byte
-    fn load_vx(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD V{:x}, {}", x, self.low_byte()).as_str());
+    // 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+    fn store_range(&mut self, x: u8, y: u8) -> Result<usize, Error> {
+        // Store registers Vx through Vy in memory starting at location I,
+        // without changing I. If x is greater than y, they are stored in
+        // reverse order.
+        let i = self.registers.i as usize;
+        let values: Vec<u8> = Self::range(x, y).map(|n| self.registers.get(n)).collect();
+
+        self.write(i, &values)?;
+
+        Ok(self.pc + 2)
+    }
+
+    // 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+    fn load_range(&mut self, x: u8, y: u8) -> Result<usize, Error> {
+        // Read registers Vx through Vy from memory starting at location I,
+        // without changing I.
+        let i = self.registers.i as usize;
+        let length = Self::range(x, y).count();
+        let values = self.read(i, length)?;
+
+        for (n, value) in Self::range(x, y).zip(values) {
+            self.registers.put(n, value);
+        }
+
+        Ok(self.pc + 2)
+    }
+
+    fn range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
 
+    // 6xkk - LD Vx, byte
+    fn load_vx(&mut self, x: u8, kk: u8) -> usize {
         // The interpreter puts the value kk into register Vx.
-        self.registers.put(x, *self.low_byte());
+        self.registers.put(x, kk);
 
         self.pc + 2
     }
 
     // 7xkk - ADD Vx, byte
-    fn add_vx(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("ADD V{:x}, {}", x, self.low_byte()).as_str());
-
+    fn add_vx(&mut self, x: u8, kk: u8) -> usize {
         // Adds the value kk to the value of register Vx, then stores the result
         // in Vx.
-        let (result, _) = self.registers.get(x).overflowing_add(*self.low_byte());
+        let (result, _) = self.registers.get(x).overflowing_add(kk);
         self.registers.put(x, result);
 
         self.pc + 2
     }
 
     // 8xy0 - LD Vx, Vy
-    fn set_vx_to_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("LD V{:x}, V{:x}", x, y).as_str());
-
+    fn set_vx_to_vy(&mut self, x: u8, y: u8) -> usize {
         // Stores the value of register Vy in register Vx.
         self.registers.put(x, self.registers.get(y));
 
@@ -387,56 +748,57 @@ impl Chip8 {
     }
 
     // 8xy1 - OR Vx, Vy
-    fn vx_or_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("OR V{:x}, V{:x}", x, y).as_str());
-
+    fn vx_or_vy(&mut self, x: u8, y: u8) -> usize {
         // Performs a bitwise OR on the values of Vx and Vy, then stores the
         // result in Vx.
         self.registers
             .put(x, self.registers.get(y) | self.registers.get(x));
+        self.reset_vf();
 
         self.pc + 2
     }
 
     // 8xy2 - AND Vx, Vy
-    fn vx_and_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("AND V{:x}, V{:x}", x, y).as_str());
-
+    fn vx_and_vy(&mut self, x: u8, y: u8) -> usize {
         // Performs a bitwise AND on the values of Vx and Vy, then stores the
         // result in Vx.
         self.registers
             .put(x, self.registers.get(y) & self.registers.get(x));
+        self.reset_vf();
 
         self.pc + 2
     }
 
     // 8xy3 - XOR Vx, Vy
-    fn vx_xor_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("XOR V{:x}, V{:x}", x, y).as_str());
-
+    fn vx_xor_vy(&mut self, x: u8, y: u8) -> usize {
         // Performs a bitwise exclusive OR on the values of Vx and Vy, then
         // stores the result in Vx.
         self.registers
             .put(x, self.registers.get(y) ^ self.registers.get(x));
+        self.reset_vf();
 
         self.pc + 2
     }
 
-    // 8xy4 - ADD Vx, Vy
-    fn add_vx_and_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("ADD V{:x}, V{:x}", x, y).as_str());
+    // The VIP interpreter ran the logic ops through a routine that clobbered
+    // VF as a side effect.
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers.v_f = 0;
+        }
+    }
 
+    // 8xy4 - ADD Vx, Vy
+    fn add_vx_and_vy(&mut self, x: u8, y: u8) -> usize {
         // The values of Vx and Vy are added together.
         let (result, carry) = self.registers.get(x).overflowing_add(self.registers.get(y));
 
+        // Only the lowest 8 bits of the result are kept, and stored in Vx.
+        self.registers.put(x, result);
+
+        // NOTE: The flag is written after the result, so when Vx is VF the
+        //       flag is what remains in VF, as it is on the original hardware.
+
         // If the result is greater than 8 bits (i.e., > 255,) VF is set to 1,
         if carry {
             self.registers.v_f = 1;
@@ -445,46 +807,41 @@ impl Chip8 {
             self.registers.v_f = 0;
         }
 
-        // Only the lowest 8 bits of the result are kept, and stored in Vx.
-        self.registers.put(x, result);
-
         self.pc + 2
     }
 
     // 8xy5 - SUB Vx, Vy
-    fn sub_vx_and_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("SUB V{:x}, V{:x}", x, y).as_str());
-
+    fn sub_vx_and_vy(&mut self, x: u8, y: u8) -> usize {
         let vx = self.registers.get(x);
         let vy = self.registers.get(y);
 
+        // Then Vy is subtracted from Vx, and the results stored in Vx.
+        let (result, _) = vx.overflowing_sub(vy);
+        self.registers.put(x, result);
+
         // If Vx > Vy, then VF is set to 1, otherwise 0.
-        if vx > vy {
+        // NOTE: VF is really the inverse of the borrow, so it is also set when
+        //       Vx and Vy are equal.
+        if vx >= vy {
             self.registers.v_f = 1
         } else {
             self.registers.v_f = 0
         }
 
-        // Then Vy is subtracted from Vx, and the results stored in Vx.
-        let (result, _) = vx.overflowing_sub(vy);
-        self.registers.put(x, result);
-
         self.pc + 2
     }
 
     // 8xy6 - SHR Vx {, Vy}
-    fn vx_shr(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SHR V{:x}", x).as_str());
-
+    fn vx_shr(&mut self, x: u8, y: u8) -> usize {
         // If the least-significant bit of Vx is 1, then VF is set to 1,
         // otherwise 0. Then Vx is divided by 2.
 
-        let (result, carry) = self.registers.get(x).overflowing_shr(1);
+        // NOTE: The original interpreter shifted Vy and stored the result in
+        //       Vx, which is what the shifting quirk turns off.
+        let vx = self.registers.get(self.shift_source(x, y));
+        let carry = vx & 0x01 == 0x01;
 
-        self.registers.put(x, result);
+        self.registers.put(x, vx >> 1);
 
         if carry {
             self.registers.v_f = 1
@@ -496,40 +853,36 @@ impl Chip8 {
     }
 
     // 8xy7 - SUBN Vx, Vy
-    fn vx_subn_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = low(self.low_byte());
-        self.disassemble(format!("SUBN V{:x}, V{:x}", x, y).as_str());
-
+    fn vx_subn_vy(&mut self, x: u8, y: u8) -> usize {
         let vx = self.registers.get(x);
         let vy = self.registers.get(y);
 
+        // Then Vx is subtracted from Vy, and the results stored in Vx.
+        let (result, _) = vy.overflowing_sub(vx);
+        self.registers.put(x, result);
+
         // If Vy > Vx, then VF is set to 1,
-        if vy > vx {
+        // NOTE: As with SUB, this is the inverse of the borrow so includes
+        //       Vy being equal to Vx.
+        if vy >= vx {
             self.registers.v_f = 1
         } else {
             // otherwise 0.
             self.registers.v_f = 0
         }
 
-        // Then Vx is subtracted from Vy, and the results stored in Vx.
-        let (result, _) = vy.overflowing_sub(vx);
-        self.registers.put(x, result);
-
         self.pc + 2
     }
 
     // 8xyE - SHL Vx {, Vy}
-    fn vx_shl(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SHL V{:x}", x).as_str());
-
+    fn vx_shl(&mut self, x: u8, y: u8) -> usize {
         // If the most-significant bit of Vx is 1, then VF is set to 1,
         // otherwise to 0. Then Vx is multiplied by 2.
 
-        let (result, carry) = self.registers.get(x).overflowing_shl(1);
+        let vx = self.registers.get(self.shift_source(x, y));
+        let carry = vx & 0x80 == 0x80;
 
-        self.registers.put(x, result);
+        self.registers.put(x, vx << 1);
 
         if carry {
             self.registers.v_f = 1
@@ -540,55 +893,88 @@ impl Chip8 {
         self.pc + 2
     }
 
-    // 9xy0 - SNE Vx, Vy
-    fn skip_vx_neq_vy(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        self.disassemble(format!("SNE V{:x}, V{:x}", x, y).as_str());
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shifting {
+            x
+        } else {
+            y
+        }
+    }
 
+    // 9xy0 - SNE Vx, Vy
+    fn skip_vx_neq_vy(&mut self, x: u8, y: u8) -> usize {
         // The values of Vx and Vy are compared,
         if self.registers.get(x) != self.registers.get(y) {
             // and if they are not equal, the program counter is increased by 2.
-            self.pc + 4
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
     // Annn - LD I, addr
-    fn load_i(&mut self) -> usize {
-        let addr = self.addr();
-        self.disassemble(format!("LD I, {:x}", addr).as_str());
-
+    fn load_i(&mut self, addr: u16) -> usize {
         // The value of register I is set to nnn
         self.registers.i = addr;
 
         self.pc + 2
     }
 
-    // Bnnn - JP V0, addr
-    fn jump_plus_v0(&mut self) -> usize {
-        let addr = self.addr();
-        self.disassemble(format!("JP V0, {:x}", addr).as_str());
+    // F000 nnnn - LD I, LONG addr (XO-CHIP)
+    fn load_i_long(&mut self, addr: u16) -> usize {
+        // The value of register I is set to the 16-bit address in the word
+        // following the instruction.
+        self.registers.i = addr;
 
+        self.pc + 4
+    }
+
+    // Fn01 - PLANE n (XO-CHIP)
+    fn select_planes(&mut self, n: u8) -> usize {
+        // Select the bitplanes that drawing, clearing and scrolling affect,
+        // as a bit mask from 0 to 3.
+        self.display.select_planes(n);
+
+        self.pc + 2
+    }
+
+    // F002 - AUDIO (XO-CHIP)
+    fn load_audio_pattern(&mut self) -> Result<usize, Error> {
+        // Load the 16 bytes starting at I into the audio pattern buffer.
+        let i = self.registers.i as usize;
+        let mut pattern = [0; 16];
+
+        pattern.copy_from_slice(&self.read(i, 16)?);
+
+        self.audio_pattern = Some(pattern);
+
+        Ok(self.pc + 2)
+    }
+
+    // Bnnn - JP V0, addr
+    fn jump_plus_v0(&mut self, addr: u16) -> usize {
         // The program counter is set to nnn plus the value of V0.
 
+        // NOTE: With the jumping quirk, this is read as Bxnn - JP Vx, addr,
+        //       and Vx is added instead.
+        let offset = if self.quirks.jumping {
+            self.registers.get((addr >> 8) as u8)
+        } else {
+            self.registers.v_0
+        };
+
         // As we always return the new program counter, we return the sum of
-        // addr and v0
-        addr as usize + self.registers.v_0 as usize
+        // addr and the offset
+        addr as usize + offset as usize
     }
 
     // Cxkk - RND Vx, byte
-    fn rand(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("RND V{:x}, {:x}", x, self.low_byte()).as_str());
-
+    fn rand(&mut self, x: u8, kk: u8) -> usize {
         // The interpreter generates a random number from 0 to 255
-        let mut rng = rand::thread_rng();
-        let random_number: u8 = rng.gen();
+        let random_number = self.rng.next(&self.ram);
 
         // which is then ANDed with the value kk.
-        let random_number = random_number & self.low_byte();
+        let random_number = random_number & kk;
 
         // The results are stored in Vx.
         self.registers.put(x, random_number);
@@ -597,74 +983,86 @@ impl Chip8 {
     }
 
     // Dxyn - DRW Vx, Vy, nibble
-    fn draw(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let y = high(self.low_byte());
-        let n = low(self.low_byte());
-        self.disassemble(format!("DRW V{:x}, V{:x}, {}", x, y, n).as_str());
+    fn draw(&mut self, x: u8, y: u8, n: u8) -> Result<usize, Error> {
+        // NOTE: With the display wait quirk, drawing waits for the next
+        //       display refresh. Like Fx0A, we wait by leaving the program
+        //       counter where it is.
+        if self.quirks.display_wait {
+            self.waiting_for_display = !self.vblank;
+            if self.waiting_for_display {
+                return Ok(self.pc);
+            }
+            self.vblank = false;
+        }
 
         // The interpreter reads n bytes from memory, starting at the address
         // stored in I.
-        let address = self.registers.i;
-        let bytes = self
-            .ram
-            .get(address as usize..(address + n as u16) as usize)
-            .expect("Bytes to draw out of range");
+        // NOTE: SUPER-CHIP reads Dxy0 as a 16x16 sprite, 32 bytes long. The
+        //       original only did so in high resolution, but like Octo we
+        //       draw them in either mode.
+        // NOTE: XO-CHIP reads another sprite's worth of bytes for each
+        //       selected plane.
+        let large = n == 0 && self.platform.has_schip();
+        let planes = self.display.planes().count_ones() as usize;
+        let length = if large { 32 } else { n as usize } * planes;
+        let address = self.registers.i as usize;
+        let bytes = self.read(address, length)?;
 
         // These bytes are then displayed as sprites on screen at
         // coordinates (Vx, Vy).
         let x = self.registers.get(x);
         let y = self.registers.get(y);
-        let sprite = Sprite::new(bytes);
+        let sprite = if large {
+            Sprite::large(&bytes)
+        } else {
+            Sprite::new(&bytes)
+        };
 
-        let collision = sprite.draw(x.into(), y.into(), &mut self.display);
+        let collision = sprite.draw(x.into(), y.into(), self.quirks.clipping, &mut self.display);
+        self.display_changed = true;
 
         // If this causes any pixels to be erased, VF is set to 1
-        if collision == Collision::True {
+        if self.platform == Platform::Schip && self.display.is_hires() {
+            // NOTE: In high resolution SUPER-CHIP sets VF to the number of
+            //       rows that collided or were clipped off the bottom. XO-CHIP
+            //       went back to 0 or 1, as in low resolution.
+            self.registers.v_f = collision.rows + collision.clipped;
+        } else if collision.any() {
             self.registers.v_f = 1;
         } else {
             // otherwise it is set to 0.
             self.registers.v_f = 0;
         }
 
-        self.pc + 2
+        Ok(self.pc + 2)
     }
 
     // Ex9E - SKP Vx
-    fn skip_pressed(&mut self, keymap: &KeyMap) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SKP V{:x}", x).as_str());
-
+    fn skip_pressed(&mut self, x: u8, input: &Input) -> usize {
         // Skip next instruction if key with the value of Vx is pressed.
         let vx = self.registers.get(x);
 
-        if keymap.is_key_pressed(vx) {
-            self.pc + 4
+        if input.is_key_pressed(vx) {
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
     // ExA1 - SKNP Vx
-    fn skip_not_pressed(&mut self, keymap: &KeyMap) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SKNP V{:x}", x).as_str());
-
+    fn skip_not_pressed(&mut self, x: u8, input: &Input) -> usize {
         // Skip next instruction if key with the value of Vx is not pressed.
         let vx = self.registers.get(x);
 
-        if !keymap.is_key_pressed(vx) {
-            self.pc + 4
+        if !input.is_key_pressed(vx) {
+            self.skip()
         } else {
             self.pc + 2
         }
     }
 
     // Fx07 - LD Vx, DT
-    fn set_vx_delay_timer(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD V{:x}, DT", x).as_str());
-
+    fn set_vx_delay_timer(&mut self, x: u8) -> usize {
         // The value of DT is placed into Vx.
         self.registers.put(x, self.registers.dt);
 
@@ -672,14 +1070,12 @@ impl Chip8 {
     }
 
     // Fx0A - LD Vx, K
-    fn wait_and_load_key_press(&mut self, keymap: &KeyMap) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD V{:x}, K", x).as_str());
-
-        match keymap.most_recent_key() {
+    fn wait_and_load_key_press(&mut self, x: u8, input: &Input) -> usize {
+        match input.most_recent_key() {
             Some(key) => {
                 //then the value of that key is stored in Vx.
                 self.registers.put(x, *key);
+                self.waiting_for_key = false;
                 self.pc + 2
             }
             None => {
@@ -687,16 +1083,14 @@ impl Chip8 {
                 // Rather than setting some state varaible on the cpu, we can
                 // leave the program counter where it is and return to the
                 // execution and render loop.
+                self.waiting_for_key = true;
                 self.pc
             }
         }
     }
 
     // Fx15 - LD DT, Vx
-    fn set_delay_timer(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD DT, V{:x}", x).as_str());
-
+    fn set_delay_timer(&mut self, x: u8) -> usize {
         // DT is set equal to the value of Vx.
         self.registers.dt = self.registers.get(x);
 
@@ -704,10 +1098,7 @@ impl Chip8 {
     }
 
     // Fx18 - LD ST, Vx
-    fn set_sound_timer(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("SD DT, V{:x}", x).as_str());
-
+    fn set_sound_timer(&mut self, x: u8) -> usize {
         // ST is set equal to the value of Vx.
         self.registers.st = self.registers.get(x);
 
@@ -715,10 +1106,7 @@ impl Chip8 {
     }
 
     // Fx1E - ADD I, Vx
-    fn add(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("ADD I, V{:x}", x).as_str());
-
+    fn add(&mut self, x: u8) -> usize {
         // The values of I and Vx are added, and the results are stored in I.
         let (result, _) = self
             .registers
@@ -731,10 +1119,7 @@ impl Chip8 {
     }
 
     // Fx29 - LD F, Vx
-    fn set_i_to_sprite_vx(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD F, V{:x}", x).as_str());
-
+    fn set_i_to_sprite_vx(&mut self, x: u8) -> usize {
         // The value of I is set to the location for the hexadecimal sprite
         // corresponding to the value of Vx.
         let vx = self.registers.get(x);
@@ -750,77 +1135,189 @@ impl Chip8 {
         self.pc + 2
     }
 
-    // Fx33 - LD B, Vx
-    fn store_bcd(&mut self) -> usize {
-        let x = low(self.high_byte());
-        self.disassemble(format!("LD B, V{:x}", x).as_str());
+    // Fx30 - LD HF, Vx (SUPER-CHIP)
+    fn set_i_to_large_sprite_vx(&mut self, x: u8) -> usize {
+        // The value of I is set to the location for the 8x10 hexadecimal
+        // sprite corresponding to the value of Vx.
+        let vx = self.registers.get(x);
 
+        // NOTE: SUPER-CHIP only has the digits 0 to 9, we also include the
+        //       letters A to F from Octo.
+        if vx <= 0xF {
+            self.registers.i = (LARGE_FONT_INDEX + vx as usize * 10) as u16;
+        }
+
+        self.pc + 2
+    }
+
+    // Fx33 - LD B, Vx
+    fn store_bcd(&mut self, x: u8) -> Result<usize, Error> {
         // The interpreter takes the decimal value of Vx, and places the
         let vx = self.registers.get(x);
-
-        // hundreds digit in memory at location in I,
         let i = self.registers.i as usize;
-        self.ram[i] = vx / 100;
 
-        // the tens digit at location I+1,
-        self.ram[i + 1] = vx / 10 % 10;
+        let bcd = [
+            // hundreds digit in memory at location in I,
+            vx / 100,
+            // the tens digit at location I+1,
+            vx / 10 % 10,
+            // and the ones digit at location I+2.
+            vx % 10,
+        ];
+        self.write(i, &bcd)?;
+
+        Ok(self.pc + 2)
+    }
 
-        // and the ones digit at location I+2.
-        self.ram[i + 2] = vx % 10;
+    // Fx3A - PITCH Vx (XO-CHIP)
+    fn set_pitch(&mut self, x: u8) -> usize {
+        // The pitch register is set equal to the value of Vx.
+        self.pitch = self.registers.get(x);
 
         self.pc + 2
     }
 
     // Fx55 - LD [I], Vx
-    fn store_array(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let i = self.registers.i;
-        self.disassemble(format!("LD [{:x}], V{:x}", i, x).as_str());
+    fn store_array(&mut self, x: u8) -> Result<usize, Error> {
+        let i = self.registers.i as usize;
 
         // The interpreter copies the values of registers V0 through Vx into
         // memory, starting at the address in I.
+        let values: Vec<u8> = (0..=x).map(|n| self.registers.get(n)).collect();
+        self.write(i, &values)?;
 
-        for n in 0..=x {
-            self.ram[i as usize + n as usize] = self.registers.get(n);
-        }
+        self.increment_i(x);
 
-        self.pc + 2
+        Ok(self.pc + 2)
     }
 
     // Fx65 - LD Vx, [I]
-    fn load_array(&mut self) -> usize {
-        let x = low(self.high_byte());
-        let i = self.registers.i;
-        self.disassemble(format!("LD V{:x}, [{:x}]", x, i).as_str());
+    fn load_array(&mut self, x: u8) -> Result<usize, Error> {
+        let i = self.registers.i as usize;
 
         // The interpreter reads values from memory starting at location I
         // into registers V0 through Vx.
+        let values = self.read(i, x as usize + 1)?;
+        for (n, value) in (0..=x).zip(values) {
+            self.registers.put(n, value);
+        }
+
+        self.increment_i(x);
+
+        Ok(self.pc + 2)
+    }
+
+    // Fx75 - LD R, Vx (SUPER-CHIP)
+    fn store_flags(&mut self, x: u8) -> usize {
+        // Store V0 through Vx in the RPL user flags.
         for n in 0..=x {
-            self.registers.put(n, self.ram[i as usize + n as usize]);
+            self.rpl[n as usize] = self.registers.get(n);
         }
 
         self.pc + 2
     }
 
+    // Fx85 - LD Vx, R (SUPER-CHIP)
+    fn load_flags(&mut self, x: u8) -> usize {
+        // Read V0 through Vx from the RPL user flags.
+        for n in 0..=x {
+            self.registers.put(n, self.rpl[n as usize]);
+        }
+
+        self.pc + 2
+    }
+
+    // The original interpreter advanced I as it went, leaving it one past the
+    // last register stored or loaded. CHIP-48 stopped one short of that.
+    fn increment_i(&mut self, x: u8) {
+        if self.quirks.memory && self.quirks.memory_by_x {
+            self.registers.i = self.registers.i.wrapping_add(x as u16);
+        } else if self.quirks.memory {
+            self.registers.i = self.registers.i.wrapping_add(x as u16 + 1);
+        }
+    }
+
     // 3.0 - Chip-8 Instrutions
     // All instructions are 2 bytes long and are stored
     // most-significant-byte first. In memory, the first byte of each
     // instruction should be located at an even addresses.
-    fn high_byte(&self) -> &u8 {
-        &self.ram[self.pc]
+    fn instruction(&self) -> u16 {
+        self.word(self.pc)
     }
 
-    fn low_byte(&self) -> &u8 {
-        &self.ram[self.pc + 1]
+    // The `length` bytes of memory starting at `addr`, or an error if they run
+    // past the end of the memory the platform can address.
+    fn ram(&self, addr: usize, length: usize) -> Result<&[u8], Error> {
+        let range = self.addressable(addr, length)?;
+        Ok(&self.ram[range])
     }
 
-    fn addr(&self) -> u16 {
-        let mask = (1 << 12) - 1;
-        self.instruction() & mask
+    fn ram_mut(&mut self, addr: usize, length: usize) -> Result<&mut [u8], Error> {
+        let range = self.addressable(addr, length)?;
+        Ok(&mut self.ram[range])
     }
 
-    fn instruction(&self) -> u16 {
-        ((*self.high_byte() as u16) << 8) | *self.low_byte() as u16
+    fn addressable(&self, addr: usize, length: usize) -> Result<Range<usize>, Error> {
+        match addr.checked_add(length) {
+            Some(end) if end <= self.platform.memory_size() => Ok(addr..end),
+            _ => Err(Error::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    // Memory reads and writes made by instructions, which watchpoints see.
+    fn read(&mut self, addr: usize, length: usize) -> Result<Vec<u8>, Error> {
+        let bytes = self.ram(addr, length)?.to_vec();
+        self.watch_memory(addr, Access::Read, &bytes, &bytes);
+
+        Ok(bytes)
+    }
+
+    fn write(&mut self, addr: usize, bytes: &[u8]) -> Chip8Result {
+        let old = self.ram(addr, bytes.len())?.to_vec();
+        self.ram_mut(addr, bytes.len())?.copy_from_slice(bytes);
+        self.watch_memory(addr, Access::Write, &old, bytes);
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.write(addr, bytes);
+        }
+
+        Ok(())
+    }
+
+    fn watch_memory(&mut self, addr: usize, access: Access, old: &[u8], new: &[u8]) {
+        if self.watched.is_some() {
+            return;
+        }
+
+        let watched = |addr: usize| {
+            self.watchpoints.iter().any(|watchpoint| match *watchpoint {
+                Watchpoint::Memory {
+                    addr: start,
+                    length,
+                    access: watching,
+                } => {
+                    // Watchpoints can be added with any length, so this
+                    // can't add it to the start.
+                    addr.checked_sub(start)
+                        .is_some_and(|offset| offset < length)
+                        && watching.includes(access)
+                }
+                Watchpoint::Register(_) => false,
+            })
+        };
+
+        if let Some(offset) = (0..old.len()).find(|offset| watched(addr + offset)) {
+            self.watched = Some(Watched::Memory {
+                addr: addr + offset,
+                access,
+                old: old[offset],
+                new: new[offset],
+            });
+        }
+    }
+
+    fn word(&self, addr: usize) -> u16 {
+        let size = self.platform.memory_size();
+        ((self.ram[addr % size] as u16) << 8) | self.ram[(addr + 1) % size] as u16
     }
 
     fn load_hexadecimal_display_bytes(&mut self) {
@@ -850,35 +1347,382 @@ impl Chip8 {
         for (i, byte) in bytes.iter().enumerate() {
             self.ram[i] = *byte;
         }
+
+        // SUPER-CHIP adds a large font of 8x10 sprites, for the digits 0
+        // through 9. These letters A through F come from Octo.
+        let large_bytes = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // "0"
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // "1"
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // "2"
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // "3"
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // "4"
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // "5"
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // "6"
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // "7"
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // "8"
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // "9"
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // "A"
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // "B"
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // "C"
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // "D"
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // "E"
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // "F"
+        ];
+
+        for (i, byte) in large_bytes.iter().enumerate() {
+            self.ram[LARGE_FONT_INDEX + i] = *byte;
+        }
     }
-    fn disassemble(&self, note: &str) {
+
+    fn disassemble(&self, instruction: &Instruction) {
         if !self.debug_output {
             return;
         }
 
-        println!(
-            "[{}] {:02x}{:02x} - {}",
-            self.pc,
-            self.high_byte(),
-            self.low_byte(),
-            note
-        );
+        println!("[{}] {:04x} - {}", self.pc, self.instruction(), instruction);
     }
 
     pub fn set_debug_output(&mut self, value: bool) {
         self.debug_output = value;
     }
 
+    // The platform says how much memory there is, so set it before loading a
+    // rom.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+    }
+
+    // Everything a program can observe, in the layout described in state.rs.
+    // The platform, quirks and debug output are settings of the frontend
+    // rather than state of the program, so they aren't saved. Only the memory
+    // the platform can address is.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = Writer::new(&self.display);
+
+        let memory_size = self.platform.memory_size();
+        writer.u32(memory_size as u32);
+        writer.bytes(&self.ram[..memory_size]);
+
+        for n in 0..=0xF {
+            writer.u8(self.registers.get(n));
+        }
+        writer.u16(self.registers.i);
+        writer.u8(self.registers.dt);
+        writer.u8(self.registers.st);
+
+        writer.u16(self.pc as u16);
+        writer.u8(self.sp as u8);
+        for addr in self.stack {
+            writer.u16(addr as u16);
+        }
+
+        self.display.save_state(&mut writer);
+
+        writer.bytes(&self.rpl);
+        writer.bool(self.exited);
+        writer.bool(self.vblank);
+
+        writer.bool(self.audio_pattern.is_some());
+        writer.bytes(&self.audio_pattern.unwrap_or_default());
+        writer.u8(self.pitch);
+
+        let (algorithm, rng_state, r9) = self.rng.to_parts();
+        writer.u8(algorithm as u8);
+        writer.u64(rng_state);
+        writer.u16(r9);
+
+        writer.finish()
+    }
+
+    // Restores a state from `save_state`. Nothing is changed unless the whole
+    // state is valid.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), state::Error> {
+        let mut reader = Reader::new(bytes);
+        let version = reader.header()?;
+        reader.thumbnail()?;
+
+        // A state from another platform has the wrong amount of memory.
+        let memory_size = reader.u32()? as usize;
+        if memory_size != self.platform.memory_size() {
+            return Err(state::Error::Invalid("memory size"));
+        }
+
+        let mut ram = vec![0; MEMORY_SIZE];
+        ram[..memory_size].copy_from_slice(reader.bytes(memory_size)?);
+
+        let mut registers = Registers::new();
+        for n in 0..=0xF {
+            registers.put(n, reader.u8()?);
+        }
+        registers.i = reader.u16()?;
+        registers.dt = reader.u8()?;
+        registers.st = reader.u8()?;
+
+        let pc = reader.u16()? as usize;
+        let sp = reader.u8()? as usize;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()? as usize;
+        }
+
+        if sp > stack.len() {
+            return Err(state::Error::Invalid("stack pointer"));
+        }
+
+        if pc + 1 >= memory_size {
+            return Err(state::Error::Invalid("program counter"));
+        }
+
+        let display = Display::load_state(&mut reader)?;
+
+        let mut rpl = [0; 16];
+        rpl.copy_from_slice(reader.bytes(16)?);
+        let exited = reader.bool()?;
+        let vblank = reader.bool()?;
+
+        let has_pattern = reader.bool()?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(reader.bytes(16)?);
+        let pitch = reader.u8()?;
+
+        // Version 1 states don't have the generator, so they keep the
+        // current one.
+        let rng = if version >= 2 {
+            let algorithm = match reader.u8()? {
+                0 => Algorithm::Xorshift,
+                1 => Algorithm::Vip,
+                _ => return Err(state::Error::Invalid("random number algorithm")),
+            };
+            let rng_state = reader.u64()?;
+            let r9 = reader.u16()?;
+
+            Rng::from_parts(algorithm, rng_state, r9)
+                .ok_or(state::Error::Invalid("random number state"))?
+        } else {
+            self.rng
+        };
+
+        reader.finish()?;
+
+        self.ram = ram;
+        self.registers = registers;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack = stack;
+        self.display = display;
+        self.rpl = rpl;
+        self.exited = exited;
+        self.vblank = vblank;
+        self.audio_pattern = has_pattern.then_some(pattern);
+        self.pitch = pitch;
+        self.rng = rng;
+
+        // The whole display may be different, and the program may no longer
+        // be waiting for a key or the display. That is found out again on its
+        // next step.
+        // States don't record how far through a frame they were taken, so
+        // they resume at the start of one.
+        self.display_changed = true;
+        self.waiting_for_key = false;
+        self.waiting_for_display = false;
+        self.frame_position = 0;
+        self.watch_hit = None;
+
+        Ok(())
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    // The return addresses on the stack, oldest first. Each is the address of
+    // the CALL, so execution resumes after it.
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    // Replaces the stack, oldest address first. It holds up to 16 addresses.
+    pub fn set_stack(&mut self, stack: &[usize]) -> Chip8Result {
+        if stack.len() > self.stack.len() {
+            return Err(Error::StackOverflow);
+        }
+
+        self.stack[..stack.len()].copy_from_slice(stack);
+        self.sp = stack.len();
+
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    // Vx, for x from 0x0 to 0xF.
+    pub fn v(&self, x: u8) -> Result<u8, Error> {
+        match x {
+            0x0..=0xF => Ok(self.registers.get(x)),
+            _ => Err(Error::NoSuchRegister(x)),
+        }
+    }
+
+    pub fn set_v(&mut self, x: u8, value: u8) -> Chip8Result {
+        if x > 0xF {
+            return Err(Error::NoSuchRegister(x));
+        }
+        self.registers.put(x, value);
+
+        Ok(())
+    }
+
+    pub fn i(&self) -> u16 {
+        self.registers.i
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.registers.i = value;
+    }
+
+    // All the memory the platform can address, including the interpreter area
+    // below the rom.
+    pub fn memory(&self) -> &[u8] {
+        &self.ram[..self.platform.memory_size()]
+    }
+
+    // A copy of the machine's state, for inspecting or comparing it later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            v: std::array::from_fn(|x| self.registers.get(x as u8)),
+            i: self.registers.i,
+            dt: self.registers.dt,
+            st: self.registers.st,
+            pc: self.pc,
+            stack: self.stack().to_vec(),
+            memory: self.memory().to_vec(),
+            hires: self.display.is_hires(),
+            display: self.display.memory[..self.display.width() * self.display.height()].to_vec(),
+            rpl: self.rpl,
+            exited: self.exited,
+            waiting_for_key: self.waiting_for_key,
+        }
+    }
+
+    pub fn register(&self, register: Register) -> Result<u16, Error> {
+        Ok(match register {
+            Register::V(x) => self.v(x)?.into(),
+            Register::I => self.registers.i,
+            Register::Pc => self.pc as u16,
+            Register::Sp => self.sp as u16,
+            Register::Dt => self.registers.dt.into(),
+            Register::St => self.registers.st.into(),
+        })
+    }
+
+    // Sets a register for a debugger. Values are truncated to the register's
+    // size, and the stack pointer is limited to the depth of the stack.
+    pub fn set_register(&mut self, register: Register, value: u16) -> Chip8Result {
+        match register {
+            Register::V(x) => self.set_v(x, value as u8)?,
+            Register::I => self.registers.i = value,
+            Register::Pc => self.pc = value.into(),
+            Register::Sp => self.sp = (value as usize).min(self.stack.len()),
+            Register::Dt => self.registers.dt = value as u8,
+            Register::St => self.registers.st = value as u8,
+        }
+
+        Ok(())
+    }
+
+    // The instruction at the program counter.
+    pub fn current_instruction(&self) -> Result<Instruction, Error> {
+        Ok(decode_long(self.instruction(), self.word(self.pc + 2))?)
+    }
+
+    pub fn peek(&self, addr: usize, length: usize) -> Result<&[u8], Error> {
+        self.ram(addr, length)
+    }
+
+    pub fn poke(&mut self, addr: usize, bytes: &[u8]) -> Chip8Result {
+        self.ram_mut(addr, bytes.len())?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    // Returns whether there was a breakpoint at addr.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    // Returns whether the watchpoint was set.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|existing| *existing != watchpoint);
+
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions;
+    }
+
+    // Starts writing a record of every instruction executed to `tracer`.
+    pub fn set_tracer(&mut self, mut tracer: Tracer) {
+        tracer.start(self);
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
     pub fn dump_to_stdout(&self) {
         println!("=== MEMORY ===");
         for line in self.ram.chunks(64) {
             for instruction in line.chunks(2) {
                 print!("{:02X}{:02X} ", instruction[0], instruction[1]);
             }
-            print!("\n");
+            println!();
         }
 
         println!();
+        self.dump_cpu_to_stdout();
+
+        println!();
+        println!("=== SCREEN ===");
+        self.display.dump_to_stdout();
+    }
+
+    // The registers, timers and stack, without memory or the screen.
+    pub fn dump_cpu_to_stdout(&self) {
         println!("=== REGISTERS ===");
         self.registers.dump_to_stdout();
 
@@ -886,22 +1730,80 @@ impl Chip8 {
         println!("=== CPU STATE ===");
         println!("pc: {:04X}", self.pc);
         println!("sp: {:04X}", self.sp);
-        println!("stack: {:?}", self.stack);
+        println!("stack: {:04X?}", self.stack());
+    }
+}
 
-        println!();
-        println!("=== SCREEN ===");
-        self.display.dump_to_stdout();
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn high(byte: &u8) -> u8 {
-    let mask = (1 << 4) - 1;
-    (byte & mask << 4) >> 4
+// The state returned by `Chip8::snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+    pub pc: usize,
+    // Oldest address first, like `Chip8::stack`.
+    pub stack: Vec<usize>,
+    pub memory: Vec<u8>,
+    pub hires: bool,
+    // The pixels row by row, with a bit for each plane they are on in, like
+    // `Display::memory`.
+    pub display: Vec<u8>,
+    // SUPER-CHIP's RPL user flags.
+    pub rpl: [u8; 16],
+    pub exited: bool,
+    pub waiting_for_key: bool,
+}
+
+// Every register a debugger can read or write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
 }
 
-fn low(byte: &u8) -> u8 {
-    let mask = (1 << 4) - 1;
-    byte & mask
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+impl std::str::FromStr for Register {
+    type Err = ();
+
+    // Register names as they are written in assembly, in either case.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "SP" => Ok(Register::Sp),
+            "DT" => Ok(Register::Dt),
+            "ST" => Ok(Register::St),
+            name => match name.strip_prefix('V') {
+                Some(x) if x.len() == 1 => {
+                    u8::from_str_radix(x, 16).map(Register::V).map_err(|_| ())
+                }
+                _ => Err(()),
+            },
+        }
+    }
 }
 
 // 2.2 - Registers
@@ -1017,6 +1919,18 @@ impl Registers {
         }
     }
 
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
     pub fn dump_to_stdout(&self) {
         print!("v_0: {:02X} ", self.v_0);
         print!("v_1: {:02X} ", self.v_1);
@@ -1041,8 +1955,881 @@ impl Registers {
     }
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub type Chip8Result = Result<(), Error>;
 
+#[derive(Debug)]
 pub enum Error {
     UnrecognisedInstruction(u8, u8),
+    // 2nnn - CALL with all 16 levels of the stack in use.
+    StackOverflow,
+    // 00EE - RET with nothing on the stack.
+    StackUnderflow,
+    // An instruction read or wrote memory past the end of `ram`, starting at
+    // `addr`.
+    MemoryOutOfBounds { addr: usize },
+    // The rom doesn't fit in memory after its start address.
+    RomTooLarge { size: usize, max: usize },
+    // Vx was asked for with an x past 0xF.
+    NoSuchRegister(u8),
+    // The rom couldn't be read.
+    Rom(rom::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnrecognisedInstruction(high, low) => {
+                write!(f, "unrecognised instruction {:02X}{:02X}", high, low)
+            }
+            Error::StackOverflow => write!(f, "stack overflow, subroutines nested too deeply"),
+            Error::StackUnderflow => {
+                write!(f, "stack underflow, returned with nothing to return to")
+            }
+            Error::MemoryOutOfBounds { addr } => {
+                write!(
+                    f,
+                    "memory access at {:#06X} runs past the end of memory",
+                    addr
+                )
+            }
+            Error::RomTooLarge { size, max } => write!(
+                f,
+                "rom is {} bytes, but only {} bytes fit in memory",
+                size, max
+            ),
+            Error::NoSuchRegister(x) => write!(f, "there is no register V{:X}", x),
+            Error::Rom(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rom(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<rom::Error> for Error {
+    fn from(error: rom::Error) -> Self {
+        Error::Rom(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::UnrecognisedInstruction(opcode) => {
+                Error::UnrecognisedInstruction((opcode >> 8) as u8, opcode as u8)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Platform;
+
+    // A machine with `rom` loaded at the usual start address.
+    fn chip8(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.ram[NORMAL_START_INDEX..NORMAL_START_INDEX + rom.len()].copy_from_slice(rom);
+        chip8.pc = NORMAL_START_INDEX;
+        chip8
+    }
+
+    fn run(chip8: &mut Chip8, input: &Input, instructions: usize) {
+        for _ in 0..instructions {
+            assert!(chip8.step(input).is_ok(), "the instruction should run");
+        }
+    }
+
+    #[test]
+    fn runs_without_a_frontend() {
+        // LD V0, 5 then ADD V0, 3, then draw the font's 0 at (V0, V0).
+        let mut chip8 = chip8(&[0x60, 0x05, 0x70, 0x03, 0xF0, 0x29, 0xD0, 0x05]);
+        run(&mut chip8, &Input::new(), 4);
+
+        assert_eq!(chip8.registers.get(0), 8);
+        assert!(chip8.display.get(8, 8));
+        assert!(!chip8.display.get(7, 8));
+    }
+
+    #[test]
+    fn waits_for_a_key() {
+        // LD V1, K
+        let mut chip8 = chip8(&[0xF1, 0x0A]);
+        let mut input = Input::new();
+
+        run(&mut chip8, &input, 3);
+        assert_eq!(chip8.pc, NORMAL_START_INDEX);
+
+        input.press(0xC);
+        run(&mut chip8, &input, 1);
+        assert_eq!(chip8.pc, NORMAL_START_INDEX + 2);
+        assert_eq!(chip8.registers.get(1), 0xC);
+    }
+
+    #[test]
+    fn subn_reads_vy_from_the_third_nibble() {
+        // LD V1, 3, LD V2, 10 then SUBN V1, V2.
+        let mut chip8 = chip8(&[0x61, 0x03, 0x62, 0x0A, 0x81, 0x27]);
+        run(&mut chip8, &Input::new(), 3);
+
+        assert_eq!(chip8.registers.get(1), 7);
+        assert_eq!(chip8.registers.get(2), 10);
+    }
+
+    #[test]
+    fn skips_on_keys() {
+        // LD V0, 5, SKP V0, then SKNP V0 twice.
+        let rom = [
+            0x60, 0x05, 0xE0, 0x9E, 0x00, 0xE0, 0xE0, 0xA1, 0x00, 0xE0, 0xE0, 0xA1,
+        ];
+        let mut input = Input::new();
+
+        let mut cpu = chip8(&rom);
+        run(&mut cpu, &input, 2);
+        assert_eq!(cpu.pc, 0x204);
+
+        input.press(0x5);
+        let mut cpu = chip8(&rom);
+        run(&mut cpu, &input, 2);
+        assert_eq!(cpu.pc, 0x206);
+        run(&mut cpu, &input, 1);
+        assert_eq!(cpu.pc, 0x208);
+
+        input.clear();
+        let mut cpu = chip8(&rom[6..]);
+        run(&mut cpu, &input, 1);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    fn on(platform: Platform, rom: &[u8]) -> Chip8 {
+        let mut chip8 = chip8(rom);
+        chip8.set_platform(platform);
+        chip8.set_quirks(platform.quirks());
+        chip8
+    }
+
+    #[test]
+    fn writes_alu_flags_after_the_result() {
+        // LD VF, 200, LD V1, 100, then ADD VF, V1.
+        let mut cpu = chip8(&[0x6F, 0xC8, 0x61, 0x64, 0x8F, 0x14]);
+        run(&mut cpu, &Input::new(), 3);
+        assert_eq!(cpu.registers.v_f, 1);
+
+        // LD VF, 5, LD V1, 3, then SUB VF, V1 and SUBN VF, V1.
+        let mut cpu = chip8(&[0x6F, 0x05, 0x61, 0x03, 0x8F, 0x15]);
+        run(&mut cpu, &Input::new(), 3);
+        assert_eq!(cpu.registers.v_f, 1);
+
+        let mut cpu = chip8(&[0x6F, 0x05, 0x61, 0x03, 0x8F, 0x17]);
+        run(&mut cpu, &Input::new(), 3);
+        assert_eq!(cpu.registers.v_f, 0);
+    }
+
+    #[test]
+    fn sets_no_borrow_for_equal_operands() {
+        // LD V0, 5, LD V1, 5, then SUB V0, V1 or SUBN V0, V1.
+        for opcode in [0x05, 0x07] {
+            let mut cpu = chip8(&[0x60, 0x05, 0x61, 0x05, 0x80, 0x10 | opcode]);
+            run(&mut cpu, &Input::new(), 3);
+
+            assert_eq!(cpu.registers.get(0), 0);
+            assert_eq!(cpu.registers.v_f, 1);
+        }
+    }
+
+    #[test]
+    fn sets_vf_to_the_bit_shifted_out() {
+        // LD V0, 0x81, then SHR V0 or SHL V0.
+        let mut cpu = chip8(&[0x60, 0x81, 0x80, 0x06]);
+        run(&mut cpu, &Input::new(), 2);
+        assert_eq!((cpu.registers.get(0), cpu.registers.v_f), (0x40, 1));
+
+        let mut cpu = chip8(&[0x60, 0x81, 0x80, 0x0E]);
+        run(&mut cpu, &Input::new(), 2);
+        assert_eq!((cpu.registers.get(0), cpu.registers.v_f), (0x02, 1));
+    }
+
+    #[test]
+    fn resets_vf_on_vip() {
+        // LD VF, 1 then OR V0, V1.
+        let rom = [0x6F, 0x01, 0x80, 0x11];
+
+        let mut vip = on(Platform::Vip, &rom);
+        run(&mut vip, &Input::new(), 2);
+        assert_eq!(vip.registers.v_f, 0);
+
+        let mut schip = on(Platform::Schip, &rom);
+        run(&mut schip, &Input::new(), 2);
+        assert_eq!(schip.registers.v_f, 1);
+    }
+
+    #[test]
+    fn increments_i_by_platform() {
+        // LD I, 0x300 then LD [I], V2.
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+        let expected = [
+            (Platform::Vip, 0x303),
+            (Platform::Chip48, 0x302),
+            (Platform::Schip, 0x300),
+            (Platform::Xochip, 0x303),
+        ];
+
+        for (platform, i) in expected {
+            let mut chip8 = on(platform, &rom);
+            run(&mut chip8, &Input::new(), 2);
+            assert_eq!(chip8.registers.i, i, "{:?}", platform);
+        }
+    }
+
+    #[test]
+    fn shifts_vy_on_vip() {
+        // LD V0, 1, LD V1, 4, then SHR V0, V1.
+        let rom = [0x60, 0x01, 0x61, 0x04, 0x80, 0x16];
+
+        let mut vip = on(Platform::Vip, &rom);
+        run(&mut vip, &Input::new(), 3);
+        assert_eq!((vip.registers.get(0), vip.registers.v_f), (2, 0));
+
+        let mut schip = on(Platform::Schip, &rom);
+        run(&mut schip, &Input::new(), 3);
+        assert_eq!((schip.registers.get(0), schip.registers.v_f), (0, 1));
+    }
+
+    #[test]
+    fn jumps_with_vx_on_schip() {
+        // LD V2, 4 then JP V0, 0x210.
+        let rom = [0x62, 0x04, 0xB2, 0x10];
+
+        let mut vip = on(Platform::Vip, &rom);
+        run(&mut vip, &Input::new(), 2);
+        assert_eq!(vip.pc, 0x210);
+
+        let mut schip = on(Platform::Schip, &rom);
+        run(&mut schip, &Input::new(), 2);
+        assert_eq!(schip.pc, 0x214);
+    }
+
+    #[test]
+    fn clips_sprites_on_vip() {
+        // LD V0, 62, LD V1, 0, LD F, V1 then DRW V0, V1, 5. The 0 glyph is
+        // four pixels wide, so two of them are past the right edge.
+        let rom = [0x60, 0x3E, 0x61, 0x00, 0xF1, 0x29, 0xD0, 0x15];
+
+        // The VIP waits for the display to refresh before drawing.
+        let mut vip = on(Platform::Vip, &rom);
+        vip.tick_timers();
+        run(&mut vip, &Input::new(), 4);
+        assert!(vip.display.get(63, 0));
+        assert!(!vip.display.get(0, 0));
+
+        for mut chip8 in [on(Platform::Xochip, &rom), chip8(&rom)] {
+            run(&mut chip8, &Input::new(), 4);
+            assert!(chip8.display.get(63, 0));
+            assert!(chip8.display.get(0, 0));
+        }
+    }
+
+    #[test]
+    fn runs_schip_instructions_only_on_schip_platforms() {
+        // HIGH
+        let mut vip = on(Platform::Vip, &[0x00, 0xFF]);
+        assert!(vip.step(&Input::new()).is_err());
+
+        let mut schip = on(Platform::Schip, &[0x00, 0xFF]);
+        run(&mut schip, &Input::new(), 1);
+        assert!(schip.display.is_hires());
+    }
+
+    #[test]
+    fn draws_nothing_for_dxy0_on_vip() {
+        // LD I, 0x300 then DRW V0, V0, 0.
+        let mut vip = on(Platform::Vip, &[0xA3, 0x00, 0xD0, 0x00]);
+        vip.ram[0x300..0x320].fill(0xFF);
+        run(&mut vip, &Input::new(), 2);
+
+        assert!(!vip.display.get(0, 0));
+    }
+
+    #[test]
+    fn sets_vf_to_collided_rows_in_hires_on_schip() {
+        // HIGH, LD V0, 60, LD I, 0x300, then DRW V0, V0, 6 twice.
+        let rom = [0x00, 0xFF, 0x60, 0x3C, 0xA3, 0x00, 0xD0, 0x06, 0xD0, 0x06];
+
+        // Two of the rows are clipped off the bottom each time, and the
+        // second time the other four collide.
+        let mut schip = on(Platform::Schip, &rom);
+        schip.ram[0x300..0x306].fill(0xFF);
+        run(&mut schip, &Input::new(), 4);
+        assert_eq!(schip.registers.v_f, 2);
+        run(&mut schip, &Input::new(), 1);
+        assert_eq!(schip.registers.v_f, 6);
+
+        let mut xochip = on(Platform::Xochip, &rom);
+        xochip.ram[0x300..0x306].fill(0xFF);
+        run(&mut xochip, &Input::new(), 4);
+        assert_eq!(xochip.registers.v_f, 0);
+        run(&mut xochip, &Input::new(), 1);
+        assert_eq!(xochip.registers.v_f, 1);
+    }
+
+    #[test]
+    fn runs_xochip_instructions_only_on_xochip() {
+        // PLANE 3
+        let mut schip = on(Platform::Schip, &[0xF3, 0x01]);
+        assert!(schip.step(&Input::new()).is_err());
+
+        let mut xochip = on(Platform::Xochip, &[0xF3, 0x01]);
+        run(&mut xochip, &Input::new(), 1);
+        assert_eq!(xochip.display.planes(), 3);
+    }
+
+    #[test]
+    fn skips_long_loads_whole_on_xochip() {
+        // SE V0, 0 then LD I, LONG 0x1234.
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34];
+
+        let mut xochip = on(Platform::Xochip, &rom);
+        run(&mut xochip, &Input::new(), 1);
+        assert_eq!(xochip.pc, 0x206);
+
+        let mut vip = on(Platform::Vip, &rom);
+        run(&mut vip, &Input::new(), 1);
+        assert_eq!(vip.pc, 0x204);
+    }
+
+    #[test]
+    fn stores_and_loads_register_ranges() {
+        // LD V1, 1, LD V2, 2, LD V3, 3, LD I, 0x300, then LD [I], V1-V3 and
+        // LD [I], V3-V1 with I moved on by 4 in between.
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x04, 0x53, 0x12,
+        ];
+        let mut xochip = on(Platform::Xochip, &rom);
+        run(&mut xochip, &Input::new(), 7);
+
+        assert_eq!(xochip.ram[0x300..0x303], [1, 2, 3]);
+        assert_eq!(xochip.ram[0x304..0x307], [3, 2, 1]);
+        assert_eq!(xochip.registers.i, 0x304);
+
+        // LD I, 0x300, then LD V5-V7, [I] and LD VA-V8, [I].
+        let rom = [0xA3, 0x00, 0x55, 0x73, 0x5A, 0x83];
+        let mut xochip = on(Platform::Xochip, &rom);
+        xochip.ram[0x300..0x303].copy_from_slice(&[7, 8, 9]);
+        run(&mut xochip, &Input::new(), 3);
+
+        let registers: Vec<u8> = (5..=0xA).map(|n| xochip.registers.get(n)).collect();
+        assert_eq!(registers, [7, 8, 9, 9, 8, 7]);
+        assert_eq!(xochip.registers.i, 0x300);
+    }
+
+    #[test]
+    fn restores_saved_states() {
+        // LD V3, 9, LD I, 0x345, then CALL 0x208 and DRW V3, V3, 5 there.
+        let rom = [0x63, 0x09, 0xA3, 0x45, 0x22, 0x08, 0x00, 0x00, 0xD3, 0x35];
+        let mut chip8 = on(Platform::Schip, &rom);
+        chip8.ram[0x345..0x34A].fill(0xAA);
+        run(&mut chip8, &Input::new(), 4);
+        let saved = chip8.save_state();
+
+        let mut restored = on(Platform::Schip, &[]);
+        restored.load_state(&saved).unwrap();
+
+        assert_eq!(restored.ram, chip8.ram);
+        assert_eq!(restored.registers.get(3), 9);
+        assert_eq!(restored.registers.i, 0x345);
+        assert_eq!((restored.pc, restored.sp), (0x20A, 1));
+        assert_eq!(restored.stack[0], 0x204);
+        assert!(restored.display.get(9, 9));
+        assert_eq!(restored.save_state(), saved);
+    }
+
+    #[test]
+    fn saves_only_the_platforms_memory() {
+        let vip = on(Platform::Vip, &[]).save_state();
+        let xochip = on(Platform::Xochip, &[]).save_state();
+
+        assert_eq!(xochip.len() - vip.len(), MEMORY_SIZE - CLASSIC_MEMORY_SIZE);
+
+        let mut chip8 = on(Platform::Vip, &[]);
+        assert_eq!(
+            chip8.load_state(&xochip),
+            Err(state::Error::Invalid("memory size"))
+        );
+    }
+
+    #[test]
+    fn rejects_states_that_are_invalid() {
+        let mut chip8 = on(Platform::Vip, &[0x12, 0x00]);
+        let saved = chip8.save_state();
+
+        // The program counter follows the header, the thumbnail, the memory
+        // and the registers.
+        let offset = 6 + 2 + 64 * 32 / 4 + 4 + CLASSIC_MEMORY_SIZE + 16 + 4;
+        assert_eq!(saved[offset..offset + 2], [0x02, 0x00]);
+        let mut corrupt = saved.clone();
+        corrupt[offset..offset + 2].copy_from_slice(&0x1000u16.to_be_bytes());
+        assert_eq!(
+            chip8.load_state(&corrupt),
+            Err(state::Error::Invalid("program counter"))
+        );
+
+        assert_eq!(
+            chip8.load_state(&saved[..saved.len() - 1]),
+            Err(state::Error::Truncated)
+        );
+
+        // Nothing changes when a state is rejected.
+        assert_eq!(chip8.pc, 0x200);
+        assert_eq!(chip8.save_state(), saved);
+    }
+
+    #[test]
+    fn draws_the_same_numbers_from_the_same_seed() {
+        // RND V0, 0xFF to RND V3, 0xFF.
+        let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0xC3, 0xFF];
+        let numbers = |cpu: &Chip8| (0..4).map(|x| cpu.registers.get(x)).collect::<Vec<_>>();
+
+        let mut first = chip8(&rom);
+        first.set_rng(Rng::new(99, Algorithm::Xorshift));
+        run(&mut first, &Input::new(), 2);
+        let saved = first.save_state();
+        run(&mut first, &Input::new(), 2);
+
+        let mut second = chip8(&rom);
+        second.set_rng(Rng::new(99, Algorithm::Xorshift));
+        run(&mut second, &Input::new(), 4);
+        assert_eq!(numbers(&first), numbers(&second));
+
+        // The generator is saved with the machine, so a restored state
+        // carries on with the same numbers.
+        let mut restored = chip8(&rom);
+        restored.load_state(&saved).unwrap();
+        run(&mut restored, &Input::new(), 2);
+        assert_eq!(numbers(&restored), numbers(&first));
+    }
+
+    #[test]
+    fn ticks_the_timers_once_per_frame() {
+        // LD V0, 3, LD DT, V0, LD ST, V0, then JP to itself.
+        let mut cpu = chip8(&[0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06]);
+
+        // Instructions alone never tick the timers.
+        run(&mut cpu, &Input::new(), 50);
+        assert_eq!((cpu.registers.dt, cpu.registers.st), (3, 3));
+
+        for remaining in [2, 1, 0, 0] {
+            assert!(cpu.run_frame(&Input::new()).error.is_none());
+            assert_eq!((cpu.registers.dt, cpu.registers.st), (remaining, remaining));
+        }
+        assert!(!cpu.sound_on());
+    }
+
+    #[test]
+    fn runs_the_instructions_per_frame() {
+        // ADD V0, 1 over and over.
+        let rom = [0x70, 0x01].repeat(32);
+
+        for instructions in [0, 1, 7, 30] {
+            let mut cpu = chip8(&rom);
+            cpu.set_instructions_per_frame(instructions);
+            assert!(cpu.run_frame(&Input::new()).error.is_none());
+            assert_eq!(cpu.registers.get(0) as u32, instructions);
+        }
+
+        // EXIT ends the frame early.
+        let mut cpu = chip8(&[0x70, 0x01, 0x00, 0xFD, 0x70, 0x01]);
+        cpu.set_platform(Platform::Schip);
+        assert!(cpu.run_frame(&Input::new()).error.is_none());
+        assert!(cpu.exited());
+        assert_eq!(cpu.registers.get(0), 1);
+    }
+
+    #[test]
+    fn reports_what_happened_in_a_frame() {
+        // CLS, LD V0, 2, LD ST, V0, then JP to itself.
+        let mut cpu = chip8(&[0x00, 0xE0, 0x60, 0x02, 0xF0, 0x18, 0x12, 0x06]);
+
+        let frame = cpu.run_frame(&Input::new());
+        assert!(frame.display_changed && frame.sound_on);
+        assert!(!frame.waiting_for_key && frame.error.is_none());
+
+        let frame = cpu.run_frame(&Input::new());
+        assert!(!frame.display_changed && !frame.sound_on);
+    }
+
+    #[test]
+    fn ends_the_frame_while_waiting_for_a_key() {
+        // ADD V0, 1, LD V1, K, ADD V0, 1, then JP to itself.
+        let mut cpu = chip8(&[0x70, 0x01, 0xF1, 0x0A, 0x70, 0x01, 0x12, 0x06]);
+
+        let frame = cpu.run_frame(&Input::new());
+        assert!(frame.waiting_for_key);
+        assert_eq!((cpu.registers.get(0), cpu.pc), (1, 0x202));
+
+        // The next frame picks up where this one stopped.
+        let mut input = Input::new();
+        input.press(0x7);
+        let frame = cpu.run_frame(&input);
+        assert!(!frame.waiting_for_key);
+        assert_eq!(cpu.registers.get(1), 0x7);
+        assert_eq!((cpu.registers.get(0), cpu.pc), (2, 0x206));
+    }
+
+    #[test]
+    fn ends_the_frame_while_waiting_for_the_display() {
+        // DRW V0, V0, 1 twice, then ADD V1, 1.
+        let mut cpu = on(Platform::Vip, &[0xD0, 0x01, 0xD0, 0x01, 0x71, 0x01]);
+
+        // Nothing has been drawn until the display refreshes.
+        let frame = cpu.run_frame(&Input::new());
+        assert!(!frame.display_changed);
+        assert_eq!(cpu.pc, 0x200);
+
+        // Then each frame draws one sprite.
+        let frame = cpu.run_frame(&Input::new());
+        assert!(frame.display_changed);
+        assert!(cpu.display.get(0, 0));
+        assert_eq!(cpu.pc, 0x202);
+
+        let frame = cpu.run_frame(&Input::new());
+        assert!(frame.display_changed);
+        assert!(!cpu.display.get(0, 0));
+        assert_eq!(cpu.registers.get(1), 1);
+    }
+
+    #[test]
+    fn stops_the_frame_on_an_error() {
+        // LD V0, 5, LD DT, V0, then an unknown instruction.
+        let mut cpu = chip8(&[0x60, 0x05, 0xF0, 0x15, 0xFF, 0xFF]);
+
+        let frame = cpu.run_frame(&Input::new());
+        assert!(matches!(
+            frame.error,
+            Some(Error::UnrecognisedInstruction(0xFF, 0xFF))
+        ));
+        // The timers don't tick for a frame that stopped early.
+        assert_eq!(cpu.registers.dt, 5);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    fn fails(cpu: &mut Chip8, instructions: usize) -> Error {
+        for _ in 0..instructions {
+            if let Err(err) = cpu.step(&Input::new()) {
+                return err;
+            }
+        }
+        panic!("the instructions should fail");
+    }
+
+    #[test]
+    fn reports_stack_errors() {
+        // CALL 0x200 forever.
+        let mut cpu = chip8(&[0x22, 0x00]);
+        assert!(matches!(fails(&mut cpu, 17), Error::StackOverflow));
+        assert_eq!(cpu.sp, 16);
+
+        // RET
+        let mut cpu = chip8(&[0x00, 0xEE]);
+        assert!(matches!(fails(&mut cpu, 1), Error::StackUnderflow));
+    }
+
+    #[test]
+    fn registers_stop_at_vf() {
+        let mut cpu = Chip8::new();
+        cpu.set_v(0xF, 0x12).unwrap();
+        assert_eq!(cpu.v(0xF).unwrap(), 0x12);
+        assert!(matches!(cpu.v(0x10), Err(Error::NoSuchRegister(0x10))));
+        assert!(matches!(
+            cpu.set_v(0xFF, 1),
+            Err(Error::NoSuchRegister(0xFF))
+        ));
+
+        // The same goes for registers named by a debugger.
+        cpu.set_register(Register::V(0xE), 0x34).unwrap();
+        assert_eq!(cpu.register(Register::V(0xE)).unwrap(), 0x34);
+        assert!(matches!(
+            cpu.register(Register::V(0x10)),
+            Err(Error::NoSuchRegister(0x10))
+        ));
+        assert!(matches!(
+            cpu.set_register(Register::V(0x10), 1),
+            Err(Error::NoSuchRegister(0x10))
+        ));
+    }
+
+    #[test]
+    fn snapshots_the_state_set_through_accessors() {
+        // CALL 0x204, then LD V1, 2 there.
+        let mut cpu = on(Platform::Vip, &[0x22, 0x04, 0x00, 0x00, 0x61, 0x02]);
+        run(&mut cpu, &Input::new(), 2);
+        cpu.set_i(0x300);
+        cpu.set_v(0xA, 7).unwrap();
+
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.pc, 0x206);
+        assert_eq!(snapshot.stack, [0x200]);
+        assert_eq!((snapshot.v[0x1], snapshot.v[0xA]), (2, 7));
+        assert_eq!(snapshot.i, 0x300);
+        assert_eq!(snapshot.memory.len(), CLASSIC_MEMORY_SIZE);
+        assert_eq!(snapshot.memory, cpu.memory());
+
+        cpu.set_pc(0x202);
+        cpu.set_stack(&[]).unwrap();
+        assert!(matches!(
+            cpu.set_stack(&[0x200; 17]),
+            Err(Error::StackOverflow)
+        ));
+        let changed = cpu.snapshot();
+        assert_eq!((changed.pc, changed.stack.len()), (0x202, 0));
+        assert_ne!(changed, snapshot);
+    }
+
+    #[test]
+    fn peek_and_poke_past_the_end_of_memory() {
+        let mut cpu = Chip8::new();
+        cpu.poke(0xFFE, &[1, 2]).unwrap();
+        assert_eq!(cpu.peek(0xFFE, 2).unwrap(), [1, 2]);
+        assert!(matches!(
+            cpu.peek(0xFFF, 2),
+            Err(Error::MemoryOutOfBounds { addr: 0xFFF })
+        ));
+        assert!(matches!(
+            cpu.peek(usize::MAX, 2),
+            Err(Error::MemoryOutOfBounds { addr: usize::MAX })
+        ));
+        assert!(matches!(
+            cpu.poke(usize::MAX, &[1]),
+            Err(Error::MemoryOutOfBounds { addr: usize::MAX })
+        ));
+    }
+
+    #[test]
+    fn classic_platforms_address_4kb() {
+        // LD I, 0xFFE then LD [I], V2 writes past the end of 4KB.
+        let rom = [0xAF, 0xFE, 0xF2, 0x55];
+        let mut vip = on(Platform::Vip, &rom);
+        assert!(matches!(
+            fails(&mut vip, 2),
+            Error::MemoryOutOfBounds { addr: 0xFFE }
+        ));
+
+        let mut xochip = on(Platform::Xochip, &rom);
+        run(&mut xochip, &Input::new(), 2);
+        assert_eq!(xochip.ram[0xFFE..0x1001], [0, 0, 0]);
+
+        // LD I, LONG 0xFFFF then LD B, V0 writes past the end of 64KB.
+        let mut xochip = on(Platform::Xochip, &[0xF0, 0x00, 0xFF, 0xFF, 0xF0, 0x33]);
+        assert!(matches!(
+            fails(&mut xochip, 2),
+            Error::MemoryOutOfBounds { addr: 0xFFFF }
+        ));
+    }
+
+    #[test]
+    fn reports_sprites_past_the_end_of_memory() {
+        // LD I, 0xFFC then DRW V0, V0, 5.
+        let mut cpu = chip8(&[0xAF, 0xFC, 0xD0, 0x05]);
+        assert!(matches!(
+            fails(&mut cpu, 2),
+            Error::MemoryOutOfBounds { addr: 0xFFC }
+        ));
+    }
+
+    #[test]
+    fn roms_fit_in_the_platforms_memory() {
+        let rom = vec![0; 0xE01];
+
+        let mut cpu = Chip8::new();
+        assert!(matches!(
+            cpu.load_rom_bytes(&rom, LoadOptions::new()),
+            Err(Error::RomTooLarge {
+                size: 0xE01,
+                max: 0xE00
+            })
+        ));
+        assert!(cpu.load_rom_bytes(&rom[1..], LoadOptions::new()).is_ok());
+
+        let mut cpu = Chip8::new();
+        cpu.set_platform(Platform::Xochip);
+        assert!(cpu.load_rom_bytes(&rom, LoadOptions::new()).is_ok());
+    }
+
+    #[test]
+    fn loads_cartridges_with_their_settings() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cartridges/face.gif");
+
+        let mut cpu = Chip8::new();
+        cpu.load_rom(path, LoadOptions::new()).unwrap();
+        assert_eq!(cpu.platform, Platform::Xochip);
+        assert!(cpu.quirks.shifting && !cpu.quirks.memory);
+        assert_eq!(cpu.instructions_per_frame, 20);
+
+        // A platform in the options wins over the cartridge's.
+        let options = LoadOptions {
+            platform: Some(Platform::Schip),
+            ..LoadOptions::new()
+        };
+        let mut cpu = Chip8::new();
+        cpu.load_rom(path, options).unwrap();
+        assert_eq!(cpu.platform, Platform::Schip);
+        assert_eq!(cpu.quirks, Platform::Schip.quirks());
+    }
+
+    #[test]
+    fn resumes_a_frame_interrupted_by_a_breakpoint() {
+        // LD V0, 2, LD DT, V0, then ADD V1, 1 over and over.
+        let mut rom = vec![0x60, 0x02, 0xF0, 0x15];
+        rom.extend([0x71, 0x01].repeat(16));
+        let mut cpu = chip8(&rom);
+        cpu.add_breakpoint(0x208);
+
+        let frame = cpu.run_frame(&Input::new());
+        assert_eq!(frame.breakpoint, Some(0x208));
+        assert_eq!(cpu.registers.get(1), 2);
+        // The frame isn't over, so the timers haven't ticked.
+        assert_eq!(cpu.registers.dt, 2);
+
+        // Stepping past the breakpoint runs the frame's next instruction, and
+        // the frame then finishes with the rest of its 10.
+        cpu.run_instruction(&Input::new());
+        let frame = cpu.run_frame(&Input::new());
+        assert_eq!(frame.breakpoint, None);
+        assert_eq!(cpu.registers.get(1), 8);
+        assert_eq!(cpu.registers.dt, 1);
+
+        // Whole frames follow.
+        assert!(cpu.remove_breakpoint(0x208));
+        cpu.run_frame(&Input::new());
+        assert_eq!(cpu.registers.get(1), 16);
+        assert_eq!(cpu.registers.dt, 0);
+    }
+
+    #[test]
+    fn resumes_a_frame_interrupted_by_an_error() {
+        // LD V0, 1, then an unknown instruction that is fixed below, then
+        // ADD V1, 1 over and over.
+        let mut rom = vec![0x60, 0x01, 0xFF, 0xFF];
+        rom.extend([0x71, 0x01].repeat(16));
+        let mut cpu = chip8(&rom);
+
+        assert!(cpu.run_frame(&Input::new()).error.is_some());
+        cpu.poke(0x202, &[0x71, 0x01]).unwrap();
+        assert!(cpu.run_frame(&Input::new()).error.is_none());
+        assert_eq!(cpu.registers.get(1), 9);
+    }
+
+    fn memory_watchpoint(addr: usize, length: usize, access: Access) -> Watchpoint {
+        Watchpoint::Memory {
+            addr,
+            length,
+            access,
+        }
+    }
+
+    #[test]
+    fn stops_frames_at_watchpoints() {
+        // LD I, 0x300, LD V0, 7, LD [I], V1, LD V2, [I], then ADD V3, 1
+        // over and over.
+        let mut rom = vec![0xA3, 0x00, 0x60, 0x07, 0xF1, 0x55, 0xF2, 0x65];
+        rom.extend([0x73, 0x01].repeat(16));
+        let mut cpu = chip8(&rom);
+        cpu.add_watchpoint(memory_watchpoint(0x301, 4, Access::Write));
+        cpu.add_watchpoint(memory_watchpoint(0x300, 1, Access::Read));
+
+        let frame = cpu.run_frame(&Input::new());
+        let hit = frame.watch.expect("the write should be watched");
+        assert_eq!((hit.pc, hit.instruction), (0x204, Instruction::LdIVx(1)));
+        assert_eq!(
+            hit.watched,
+            Watched::Memory {
+                addr: 0x301,
+                access: Access::Write,
+                old: 0,
+                new: 0,
+            }
+        );
+        assert_eq!(
+            hit.to_string(),
+            "0301 written, 00 -> 00 by LD [I], V1 at 0204"
+        );
+
+        let frame = cpu.run_frame(&Input::new());
+        let hit = frame.watch.expect("the read should be watched");
+        assert_eq!(hit.to_string(), "0300 read as 07 by LD V2, [I] at 0206");
+
+        // The rest of the frame runs when nothing else is watched.
+        let frame = cpu.run_frame(&Input::new());
+        assert!(frame.watch.is_none());
+        assert_eq!(cpu.registers.get(3), 6);
+    }
+
+    #[test]
+    fn watches_registers_change() {
+        // LD V1, 0, LD V1, 5, then LD I, 0x345.
+        let mut cpu = chip8(&[0x61, 0x00, 0x61, 0x05, 0xA3, 0x45]);
+        cpu.add_watchpoint(Watchpoint::Register(Register::V(1)));
+        cpu.add_watchpoint(Watchpoint::Register(Register::I));
+
+        // Writing the value a register already has isn't a change.
+        let frame = cpu.run_frame(&Input::new());
+        let hit = frame.watch.expect("V1 should change");
+        assert_eq!(hit.pc, 0x202);
+        assert_eq!(hit.to_string(), "V1 changed, 00 -> 05 by LD V1, 5 at 0202");
+
+        let frame = cpu.run_frame(&Input::new());
+        assert_eq!(
+            frame.watch.map(|hit| hit.watched),
+            Some(Watched::Register {
+                register: Register::I,
+                old: 0,
+                new: 0x345
+            })
+        );
+
+        assert!(cpu.remove_watchpoint(Watchpoint::Register(Register::I)));
+        assert!(!cpu.remove_watchpoint(Watchpoint::Register(Register::I)));
+        assert_eq!(cpu.watchpoints().len(), 1);
+    }
+
+    #[test]
+    fn watchpoints_run_to_the_end_of_memory() {
+        // LD I, 300 then LD [I], V0 writes 0x300.
+        let mut cpu = chip8(&[0xA3, 0x00, 0xF0, 0x55]);
+        cpu.add_watchpoint(memory_watchpoint(usize::MAX, usize::MAX, Access::Write));
+        cpu.add_watchpoint(memory_watchpoint(0x2FF, usize::MAX, Access::Write));
+
+        let frame = cpu.run_instruction(&Input::new());
+        assert!(frame.watch.is_none());
+        let frame = cpu.run_instruction(&Input::new());
+        assert!(matches!(
+            frame.watch.map(|hit| hit.watched),
+            Some(Watched::Memory {
+                addr: 0x300,
+                access: Access::Write,
+                ..
+            })
+        ));
+    }
 }
@@ -1,15 +1,44 @@
-use clap::Parser;
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::chip8::{LoadOptions, MEMORY_SIZE};
+use crate::database::Settings;
+use crate::quirks::{parse_override, Platform, Quirks};
+use crate::rng::{Algorithm, Rng};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Path to the chip-8 rom that you want to run
-    pub path: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to the chip-8 rom that you want to run, or an Octo (.8o) source
+    /// file or cartridge (.gif) to compile and run. Roms can be in zip or gzip
+    /// archives, and `-` reads the rom from standard input
+    #[arg(required = true)]
+    pub path: Option<String>,
 
     /// Display debug output when running a chip-8 rom
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Start paused in the interactive debugger. F12 pauses again while running
+    #[arg(long)]
+    pub debugger: bool,
+
+    /// Wait for a GDB remote debugger to connect on this localhost port
+    #[arg(long, value_name = "PORT", conflicts_with = "debugger")]
+    pub gdb: Option<u16>,
+
+    /// Write a JSON line to this file for every instruction executed, with
+    /// the registers it changed
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+
+    /// Also record the memory each instruction writes in the trace
+    #[arg(long, requires = "trace")]
+    pub trace_memory: bool,
+
     /// Set the color in hex (e.g #FF0000) for pixels that are on
     #[arg(short, long)]
     pub fg: Option<String>,
@@ -18,7 +47,309 @@ pub struct Cli {
     #[arg(short, long)]
     pub bg: Option<String>,
 
+    /// Set the color in hex for pixels that are only on in XO-CHIP's second
+    /// plane
+    #[arg(long)]
+    pub fg2: Option<String>,
+
+    /// Set the color in hex for pixels that are on in both XO-CHIP planes
+    #[arg(long)]
+    pub blend: Option<String>,
+
+    #[command(flatten)]
+    pub machine: Machine,
+
+    /// How many seconds of history to keep for rewinding with backspace, or 0
+    /// to turn rewinding off
+    #[arg(long, default_value_t = 10)]
+    pub rewind_seconds: u32,
+
+    /// The most memory in megabytes to use for rewind history
+    #[arg(long, default_value_t = 16)]
+    pub rewind_memory: usize,
+}
+
+// How the emulated machine is set up, shared by running a rom and testing one.
+#[derive(Args)]
+pub struct Machine {
     /// Start the emulator in ETI 660 Mode
     #[arg(short, long)]
     pub eti_mode: bool,
+
+    /// Load the rom at this address in hex, rather than 200 (or 600 in ETI
+    /// 660 mode)
+    #[arg(long, value_name = "ADDR", value_parser = parse_address, conflicts_with = "eti_mode")]
+    pub load_address: Option<usize>,
+
+    /// The platform the rom was written for, which decides how ambiguous
+    /// instructions behave. Defaults to the rom's platform in the database.
+    /// Without one, crab-8 behaves as it always has
+    #[arg(short, long, value_enum)]
+    pub platform: Option<Platform>,
+
+    /// Override one of the platform's quirks, e.g. `--quirk shifting=off`.
+    /// Quirks are vf-reset, memory, memory-by-x, display-wait, clipping,
+    /// shifting and jumping
+    #[arg(short, long = "quirk", value_name = "QUIRK[=on|off]", value_parser = parse_override)]
+    pub quirks: Vec<(String, bool)>,
+
+    /// How many instructions to run per 60Hz frame. Defaults to the rom's
+    /// speed in the database, or 10
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: Option<u32>,
+
+    /// Seed the random number generator, so that runs with the same input
+    /// repeat exactly
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// The random number generator to use. `vip` uses the arithmetic of the
+    /// COSMAC VIP interpreter's routine
+    #[arg(long, value_enum, default_value_t = Algorithm::Xorshift)]
+    pub rng: Algorithm,
+
+    /// A copy of the CHIP-8 database, to look up the settings for known roms
+    #[arg(long, value_name = "DIR", env = "CRAB8_DATABASE")]
+    pub database: Option<String>,
+}
+
+impl Machine {
+    // Where to load the rom. The quirks are set separately, with `quirks`.
+    pub fn load_options(&self) -> LoadOptions {
+        let mut options = if self.eti_mode {
+            LoadOptions::eti_660()
+        } else {
+            LoadOptions::new()
+        };
+
+        if let Some(address) = self.load_address {
+            options.address = address;
+        }
+
+        options
+    }
+
+    // Uses the rom's settings from the database for anything that wasn't
+    // given on the command line.
+    pub fn apply(&mut self, settings: &Settings) {
+        // The database's quirks are relative to its platform, and the command
+        // line's override them.
+        if self.platform.is_none() {
+            self.platform = settings.platform;
+            self.quirks.splice(0..0, settings.quirks.iter().cloned());
+        }
+
+        self.ipf = self.ipf.or(settings.instructions_per_frame);
+
+        if !self.eti_mode {
+            self.load_address = self.load_address.or(settings.start_address);
+        }
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.ipf.unwrap_or(10)
+    }
+
+    // Checks the options that depend on each other, which clap can't while
+    // it parses them one at a time.
+    pub fn check(&self) -> Result<(), clap::Error> {
+        let platform = self.platform.unwrap_or_default();
+
+        match self.load_address {
+            Some(address) if address >= platform.memory_size() => {
+                let name = platform
+                    .to_possible_value()
+                    .expect("every platform has a name");
+                Err(Cli::command().error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "--load-address {:X} is past the end of the {} platform's memory, which ends at {:X}",
+                        address,
+                        name.get_name(),
+                        platform.memory_size() - 1
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The quirks of the chosen platform, with any overrides applied.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = match self.platform {
+            Some(platform) => platform.quirks(),
+            None => Quirks::default(),
+        };
+
+        for (name, value) in &self.quirks {
+            quirks
+                .set(name, *value)
+                .expect("quirk names are checked when parsing");
+        }
+
+        quirks
+    }
+
+    pub fn rng(&self) -> Rng {
+        match self.seed {
+            Some(seed) => Rng::new(seed, self.rng),
+            None => Rng::from_entropy(self.rng),
+        }
+    }
+}
+
+fn parse_address(text: &str) -> Result<usize, String> {
+    let digits = text.trim_start_matches("0x");
+    let address = usize::from_str_radix(digits, 16)
+        .map_err(|_| format!("`{}` is not a hex address", text))?;
+
+    if address >= MEMORY_SIZE {
+        return Err(format!("{:X} is past the end of memory", address));
+    }
+
+    Ok(address)
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print an annotated disassembly of a chip-8 rom without running it
+    Disasm {
+        /// Path to the chip-8 rom that you want to disassemble
+        path: String,
+
+        /// Disassemble the rom as an ETI 660 program
+        #[arg(short, long)]
+        eti_mode: bool,
+    },
+
+    /// Assemble a chip-8 assembly source file into a rom
+    Asm {
+        /// Path to the assembly source file
+        path: String,
+
+        /// Where to write the rom, defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Assemble the program to run as an ETI 660 program
+        #[arg(short, long)]
+        eti_mode: bool,
+    },
+
+    /// Compile an Octo assembly language (.8o) source file into a rom
+    Octo {
+        /// Path to the Octo source file
+        path: String,
+
+        /// Where to write the rom, defaults to the source path with a .ch8 extension
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Compare two execution traces and show where they first differ
+    TraceDiff {
+        /// A trace written by `--trace`, or in the text format from the README
+        a: String,
+
+        /// The trace to compare it with
+        b: String,
+
+        /// How many instructions before and after the difference to show
+        #[arg(short, long, default_value_t = 5)]
+        context: usize,
+    },
+    /// Run a rom without a window for a number of frames, and check what is
+    /// left on the screen
+    Test {
+        /// Path to the chip-8 rom to test, or an Octo (.8o) source file. `-`
+        /// reads the rom from standard input
+        path: String,
+
+        /// How many 60Hz frames to run
+        #[arg(long, default_value_t = 600)]
+        frames: u32,
+
+        /// A script of keys to press on given frames
+        #[arg(long, value_name = "FILE")]
+        input: Option<String>,
+
+        /// The screen expected at the end, as text or a PBM image. Without
+        /// it, the final screen is printed as text
+        #[arg(long, value_name = "FILE")]
+        expect: Option<String>,
+
+        #[command(flatten)]
+        machine: Machine,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(args: &[&str]) -> Machine {
+        let args = ["crab-8"].iter().chain(args).chain(&["rom.ch8"]);
+        Cli::try_parse_from(args).unwrap().machine
+    }
+
+    #[test]
+    fn load_addresses_fit_in_the_platforms_memory() {
+        assert_eq!(machine(&[]).load_options().address, 0x200);
+        assert_eq!(machine(&["--eti-mode"]).load_options().address, 0x600);
+
+        let machine_at =
+            |address, platform| machine(&["--load-address", address, "--platform", platform]);
+        assert_eq!(machine_at("0x300", "vip").load_options().address, 0x300);
+        assert!(machine_at("FFF", "vip").check().is_ok());
+        assert!(machine_at("1000", "vip").check().is_err());
+        assert!(machine_at("1000", "xochip").check().is_ok());
+
+        let args = ["crab-8", "--load-address", "10000", "rom.ch8"];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            platform: Some(Platform::Xochip),
+            quirks: vec![
+                ("clipping".to_string(), true),
+                ("jumping".to_string(), true),
+            ],
+            instructions_per_frame: Some(1000),
+            start_address: Some(0x300),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn fills_in_settings_from_the_database() {
+        let mut machine = machine(&["--quirk", "jumping=off"]);
+        machine.apply(&settings());
+
+        assert_eq!(machine.platform, Some(Platform::Xochip));
+        assert_eq!(machine.instructions_per_frame(), 1000);
+        assert_eq!(machine.load_options().address, 0x300);
+        // The command line's quirks override the database's.
+        let mut quirks = Platform::Xochip.quirks();
+        quirks.clipping = true;
+        quirks.jumping = false;
+        assert_eq!(machine.quirks(), quirks);
+    }
+
+    #[test]
+    fn prefers_the_command_line_to_the_database() {
+        let mut schip = machine(&["--platform", "schip", "--ipf", "20", "--eti-mode"]);
+        schip.apply(&settings());
+
+        // The database's quirks are for its platform, so they go with it.
+        assert_eq!(schip.platform, Some(Platform::Schip));
+        assert_eq!(schip.quirks(), Platform::Schip.quirks());
+        assert_eq!(schip.instructions_per_frame(), 20);
+        assert_eq!(schip.load_options().address, 0x600);
+
+        let mut defaults = machine(&[]);
+        defaults.apply(&Settings::default());
+        assert_eq!(defaults.platform, None);
+        assert_eq!(defaults.quirks(), Quirks::default());
+        assert_eq!(defaults.instructions_per_frame(), 10);
+    }
 }
@@ -0,0 +1,333 @@
+// Settings for known roms, from a local copy of the CHIP-8 database.
+// Reference: https://github.com/chip-8/chip-8-database
+//
+// The database is a directory holding `sha1-hashes.json`, which maps the
+// SHA-1 hash of each rom to an index into `programs.json`. Each program in
+// there lists its roms by hash, with the platforms they run on, how fast they
+// expect to run, where they load, their keys and their colours.
+//
+// Only the settings crab-8 can use are read, and the rest is ignored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::quirks::{Platform, Quirks};
+
+pub struct Database {
+    hashes: HashMap<String, usize>,
+    programs: Vec<Program>,
+}
+
+// What the database says about a rom.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub title: String,
+    pub platform: Option<Platform>,
+    // Quirks that differ from the platform's, as `--quirk` takes them.
+    pub quirks: Vec<(String, bool)>,
+    pub instructions_per_frame: Option<u32>,
+    pub start_address: Option<usize>,
+    // The keypad keys for the game's controls, by the database's names for
+    // them, e.g. "up" or "a".
+    pub keys: BTreeMap<String, u8>,
+    // In the order `Renderer::set_colors` takes them.
+    pub colors: [Option<String>; 4],
+}
+
+impl Settings {
+    // The platform's quirks, with the overrides applied.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.platform.unwrap_or_default().quirks();
+
+        for (name, value) in &self.quirks {
+            quirks
+                .set(name, *value)
+                .expect("settings only override known quirks");
+        }
+
+        quirks
+    }
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    start_address: Option<usize>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+impl Database {
+    pub fn load(directory: &str) -> Result<Self, Error> {
+        let read = |name: &str| {
+            let path = Path::new(directory).join(name);
+            fs::read_to_string(&path)
+                .map_err(|err| Error::Io(path.to_string_lossy().into_owned(), err))
+        };
+
+        Self::parse(&read("sha1-hashes.json")?, &read("programs.json")?)
+    }
+
+    pub fn parse(hashes: &str, programs: &str) -> Result<Self, Error> {
+        Ok(Self {
+            hashes: serde_json::from_str(hashes)
+                .map_err(|err| Error::Json("sha1-hashes.json", err))?,
+            programs: serde_json::from_str(programs)
+                .map_err(|err| Error::Json("programs.json", err))?,
+        })
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<Settings> {
+        let hash = hash(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let rom = program.roms.get(&hash)?;
+
+        let mut settings = Settings {
+            title: program.title.clone(),
+            instructions_per_frame: rom.tickrate.filter(|tickrate| *tickrate > 0),
+            start_address: rom.start_address,
+            keys: rom
+                .keys
+                .iter()
+                .filter(|(_, key)| **key <= 0xF)
+                .map(|(name, key)| (name.clone(), *key))
+                .collect(),
+            ..Settings::default()
+        };
+
+        // Roms that run on several platforms list them in order of
+        // preference, so the first one crab-8 emulates is used.
+        if let Some((id, (platform, quirks))) = rom
+            .platforms
+            .iter()
+            .find_map(|id| Some((id, platform(id)?)))
+        {
+            settings.platform = Some(platform);
+            settings.quirks = quirks;
+
+            if let Some(overrides) = rom.quirky_platforms.get(id) {
+                let mut overrides: Vec<_> = overrides.iter().collect();
+                overrides.sort();
+                settings.quirks.extend(
+                    overrides
+                        .into_iter()
+                        .filter_map(|(name, on)| quirk(name, *on)),
+                );
+            }
+        }
+
+        if let Some(colors) = &rom.colors {
+            for (color, pixel) in settings.colors.iter_mut().zip(&colors.pixels) {
+                *color = is_color(pixel).then(|| pixel.clone());
+            }
+        }
+
+        Some(settings)
+    }
+}
+
+// The lowercase hex SHA-1 hash the database keys roms by.
+pub fn hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// The platform for one of the database's platform ids, with the quirks it has
+// that differ from ours.
+fn platform(id: &str) -> Option<(Platform, Vec<(String, bool)>)> {
+    let platform = match id {
+        "originalChip8" | "hybridVIP" => (Platform::Vip, vec![]),
+        // Later interpreters for modern computers.
+        "modernChip8" => (
+            Platform::Vip,
+            vec![
+                ("vf-reset".to_string(), false),
+                ("display-wait".to_string(), false),
+            ],
+        ),
+        "chip48" => (Platform::Chip48, vec![]),
+        "superchip1" | "superchip" => (Platform::Schip, vec![]),
+        "xochip" => (Platform::Xochip, vec![]),
+        _ => return None,
+    };
+
+    Some(platform)
+}
+
+// One of the database's quirks, as one of ours.
+fn quirk(name: &str, on: bool) -> Option<(String, bool)> {
+    let (name, on) = match name {
+        "shift" => ("shifting", on),
+        "memoryIncrementByX" => ("memory-by-x", on),
+        "memoryLeaveIUnchanged" => ("memory", !on),
+        "wrap" => ("clipping", !on),
+        "jump" => ("jumping", on),
+        "vblank" => ("display-wait", on),
+        "logic" => ("vf-reset", on),
+        _ => return None,
+    };
+
+    Some((name.to_string(), on))
+}
+
+// Whether a colour is one `Renderer::set_colors` can take.
+pub(crate) fn is_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(String, io::Error),
+    Json(&'static str, serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, err) => write!(f, "{}: {}", path, err),
+            Error::Json(file, err) => write!(f, "{}: {}", file, err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, err) => Some(err),
+            Error::Json(_, err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    fn database() -> Database {
+        let hashes = format!(r#"{{"{}": 0}}"#, hash(&ROM));
+        let programs = format!(
+            r##"[{{
+                "title": "Loop",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "file": "loop.ch8",
+                        "platforms": ["megachip8", "superchip", "xochip"],
+                        "tickrate": 30,
+                        "startAddress": 768,
+                        "keys": {{"up": 5, "a": 6, "b": 16}},
+                        "colors": {{"pixels": ["#000000", "#FFaa00", "red", "#12345"]}},
+                        "quirkyPlatforms": {{
+                            "superchip": {{
+                                "shift": false,
+                                "memoryLeaveIUnchanged": false,
+                                "memoryIncrementByX": true,
+                                "wrap": true,
+                                "mystery": true
+                            }}
+                        }}
+                    }}
+                }}
+            }}]"##,
+            hash(&ROM)
+        );
+
+        Database::parse(&hashes, &programs).unwrap()
+    }
+
+    #[test]
+    fn hashes_roms_with_sha1() {
+        assert_eq!(hash(&[]), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn looks_up_roms_by_their_hash() {
+        let database = database();
+
+        assert!(database.lookup(&[0x00, 0xE0]).is_none());
+        let settings = database.lookup(&ROM).unwrap();
+        assert_eq!(settings.title, "Loop");
+    }
+
+    #[test]
+    fn reads_the_settings_crab_8_uses() {
+        let settings = database().lookup(&ROM).unwrap();
+
+        // megachip8 isn't emulated, so the next platform is used.
+        assert_eq!(settings.platform, Some(Platform::Schip));
+        assert_eq!(
+            settings.quirks,
+            [
+                ("memory-by-x".to_string(), true),
+                ("memory".to_string(), true),
+                ("shifting".to_string(), false),
+                ("clipping".to_string(), false),
+            ]
+        );
+        assert_eq!(settings.instructions_per_frame, Some(30));
+        assert_eq!(settings.start_address, Some(0x300));
+        assert_eq!(
+            settings.keys,
+            BTreeMap::from([("a".to_string(), 6), ("up".to_string(), 5)])
+        );
+        assert_eq!(
+            settings.colors,
+            [
+                Some("#000000".to_string()),
+                Some("#FFaa00".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_broken_database() {
+        assert!(matches!(
+            Database::parse("[]", "[]"),
+            Err(Error::Json("sha1-hashes.json", _))
+        ));
+        assert!(matches!(
+            Database::parse("{}", "{}"),
+            Err(Error::Json("programs.json", _))
+        ));
+    }
+
+    #[test]
+    fn only_takes_hex_colors() {
+        assert!(is_color("#12ab3F"));
+        assert!(!is_color("12ab3F"));
+        assert!(!is_color("#12ab3"));
+        assert!(!is_color("#12ab3FF"));
+        assert!(!is_color("#12ab3G"));
+        assert!(!is_color("white"));
+    }
+}
@@ -0,0 +1,616 @@
+// Interactive command line debugger.
+//
+// The frontend hands control to the debugger whenever the program is paused,
+// by `--debugger` at startup, a breakpoint, an error or the pause key. Each
+// call to `prompt` reads and runs one command from stdin, so the frontend can
+// redraw the screen between commands. An empty line repeats the last command.
+//
+// Addresses, lengths and bytes are hex, with or without a leading 0x. An
+// address can also be any label from the disassembly of the rom, e.g.
+// `break sub_2A4`. Step counts are decimal.
+
+use std::io::{self, Write};
+
+use crate::chip8::{Access, Chip8, Register, WatchHit, Watchpoint};
+use crate::disasm::Listing;
+use crate::input::Input;
+use crate::instruction::{decode_long, Instruction};
+
+// Stepping over a subroutine that never returns would otherwise hang.
+const STEP_LIMIT: usize = 10_000_000;
+
+const HELP: &str = "\
+Commands:
+  s, step [n]           Run the next n instructions, 1 by default
+  n, next               Step, running a CALL until it returns
+  o, out                Run until the current subroutine returns
+  c, continue           Resume running
+  b, break [addr]       Set a breakpoint, or list them without an address
+  d, delete <addr>      Clear a breakpoint
+  w, watch [target]..   Watch memory, `watch <addr> [length] [r|w|rw]`, or a
+                        register such as `watch vf`. Lists them without a target
+  unwatch <target>      Clear the watchpoints on an address or register
+  r, regs               Print the registers, timers and stack
+  x <addr> [length]     Print memory, 16 bytes by default
+  poke <addr> <byte>..  Write bytes to memory
+  l, list [addr]        Disassemble around an address, the PC by default
+  h, help               Print this message
+  q, quit               Stop the emulator";
+
+pub enum Action {
+    // Stay paused, and prompt for another command.
+    Pause,
+    Continue,
+    Quit,
+}
+
+// Why a step stopped, when it wasn't simply finished.
+enum Stop {
+    Done,
+    Breakpoint,
+    Watch(WatchHit),
+    WaitingForKey,
+    Exited,
+    Error(String),
+    Limit,
+}
+
+pub struct Debugger {
+    listing: Listing,
+    last_command: String,
+    // The address the location was last printed at, so that it is printed
+    // again after anything that moves the program counter.
+    shown: Option<usize>,
+}
+
+impl Debugger {
+    pub fn new(listing: Listing) -> Self {
+        Self {
+            listing,
+            last_command: String::new(),
+            shown: None,
+        }
+    }
+
+    pub fn prompt(&mut self, cpu: &mut Chip8, input: &Input) -> Action {
+        if self.shown != Some(cpu.pc()) {
+            self.print_location(cpu);
+        }
+
+        print!("(crab-8) ");
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            // Without a terminal there is nobody to ask, so just carry on.
+            Ok(0) | Err(_) => return Action::Continue,
+            Ok(_) => {}
+        }
+
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+
+        self.run_command(&line, cpu, input)
+    }
+
+    pub fn run_command(&mut self, line: &str, cpu: &mut Chip8, input: &Input) -> Action {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Action::Pause,
+        };
+
+        match (command, args) {
+            ("s" | "step", _) => {
+                let count = match args.first() {
+                    Some(count) => match count.parse() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            println!("`{}` isn't a number of steps", count);
+                            return Action::Pause;
+                        }
+                    },
+                    None => 1,
+                };
+
+                for step in 1..=count {
+                    let stop = match self.step(cpu, input) {
+                        // Breakpoints stop the steps like they stop
+                        // `continue`, unless there are none left anyway.
+                        Stop::Done
+                            if step < count && cpu.breakpoints().any(|addr| addr == cpu.pc()) =>
+                        {
+                            Stop::Breakpoint
+                        }
+                        stop => stop,
+                    };
+                    if !matches!(stop, Stop::Done) {
+                        self.report(stop, cpu);
+                        break;
+                    }
+                }
+            }
+            ("n" | "next", []) => {
+                let stop = match cpu.current_instruction() {
+                    Ok(Instruction::Call(_)) => {
+                        let depth = cpu.stack().len();
+                        self.run_until(cpu, input, |cpu| cpu.stack().len() == depth)
+                    }
+                    _ => self.step(cpu, input),
+                };
+                self.report(stop, cpu);
+            }
+            ("o" | "out", []) => {
+                let depth = cpu.stack().len();
+                if depth == 0 {
+                    println!("Not in a subroutine");
+                    return Action::Pause;
+                }
+
+                let stop = self.run_until(cpu, input, |cpu| cpu.stack().len() < depth);
+                self.report(stop, cpu);
+            }
+            ("c" | "continue", []) => {
+                // A breakpoint at the program counter would stop the program
+                // again straight away, so get past it first.
+                if cpu.breakpoints().any(|addr| addr == cpu.pc()) {
+                    let stop = self.step(cpu, input);
+                    if !matches!(stop, Stop::Done) {
+                        self.report(stop, cpu);
+                        return Action::Pause;
+                    }
+                }
+
+                self.shown = None;
+                return Action::Continue;
+            }
+            ("b" | "break", []) => {
+                for addr in cpu.breakpoints() {
+                    println!("{}", self.describe(addr));
+                }
+            }
+            ("b" | "break", [addr]) => {
+                if let Some(addr) = self.address(cpu, addr) {
+                    cpu.add_breakpoint(addr);
+                    println!("Breakpoint at {}", self.describe(addr));
+                }
+            }
+            ("d" | "delete", [addr]) => {
+                if let Some(addr) = self.address(cpu, addr) {
+                    if !cpu.remove_breakpoint(addr) {
+                        println!("No breakpoint at {}", self.describe(addr));
+                    }
+                }
+            }
+            ("w" | "watch", []) => {
+                for watchpoint in cpu.watchpoints() {
+                    println!("{}", self.describe_watchpoint(watchpoint));
+                }
+            }
+            ("w" | "watch", [target, options @ ..]) => {
+                if let Some(watchpoint) = self.watchpoint(cpu, target, options) {
+                    cpu.add_watchpoint(watchpoint);
+                    println!("Watching {}", self.describe_watchpoint(&watchpoint));
+                }
+            }
+            ("unwatch", [target]) => {
+                let removed: Vec<Watchpoint> = match target.parse::<Register>() {
+                    Ok(register) => vec![Watchpoint::Register(register)],
+                    Err(_) => match self.address(cpu, target) {
+                        Some(addr) => cpu
+                            .watchpoints()
+                            .iter()
+                            .filter(|watchpoint| {
+                                matches!(watchpoint, Watchpoint::Memory { addr: start, .. } if *start == addr)
+                            })
+                            .copied()
+                            .collect(),
+                        None => return Action::Pause,
+                    },
+                };
+
+                let count = removed
+                    .into_iter()
+                    .filter(|watchpoint| cpu.remove_watchpoint(*watchpoint))
+                    .count();
+                if count == 0 {
+                    println!("Nothing is watching {}", target);
+                }
+            }
+            ("r" | "regs", []) => cpu.dump_cpu_to_stdout(),
+            ("x", [addr] | [addr, _]) => {
+                let length = match args.get(1) {
+                    Some(length) => match parse_hex(length) {
+                        Some(length) => length,
+                        None => {
+                            println!("`{}` isn't a hex length", length);
+                            return Action::Pause;
+                        }
+                    },
+                    None => 16,
+                };
+
+                if let Some(addr) = self.address(cpu, addr) {
+                    match cpu.peek(addr, length) {
+                        Ok(bytes) => {
+                            for (n, line) in bytes.chunks(16).enumerate() {
+                                let hex: Vec<String> =
+                                    line.iter().map(|byte| format!("{:02X}", byte)).collect();
+                                println!("{:04X}: {}", addr + n * 16, hex.join(" "));
+                            }
+                        }
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            ("poke", [addr, bytes @ ..]) if !bytes.is_empty() => {
+                let addr = match self.address(cpu, addr) {
+                    Some(addr) => addr,
+                    None => return Action::Pause,
+                };
+
+                let mut values = vec![];
+                for byte in bytes {
+                    match parse_hex(byte).filter(|byte| *byte <= 0xFF) {
+                        Some(byte) => values.push(byte as u8),
+                        None => {
+                            println!("`{}` isn't a hex byte", byte);
+                            return Action::Pause;
+                        }
+                    }
+                }
+
+                if let Err(err) = cpu.poke(addr, &values) {
+                    println!("{}", err);
+                }
+            }
+            ("l" | "list", []) => {
+                for line in self.list(cpu, cpu.pc()) {
+                    println!("{}", line);
+                }
+            }
+            ("l" | "list", [addr]) => {
+                if let Some(addr) = self.address(cpu, addr) {
+                    for line in self.list(cpu, addr) {
+                        println!("{}", line);
+                    }
+                }
+            }
+            ("h" | "help", _) => println!("{}", HELP),
+            ("q" | "quit", []) => return Action::Quit,
+            _ => println!("Unknown command `{}`, try `help`", line),
+        }
+
+        Action::Pause
+    }
+
+    // Runs a single instruction.
+    fn step(&mut self, cpu: &mut Chip8, input: &Input) -> Stop {
+        let frame = cpu.run_instruction(input);
+
+        match (frame.error, frame.watch) {
+            (Some(err), _) => Stop::Error(err.to_string()),
+            (None, Some(hit)) => Stop::Watch(hit),
+            (None, None) if cpu.exited() => Stop::Exited,
+            (None, None) => Stop::Done,
+        }
+    }
+
+    // Runs at least one instruction, then keeps going until `done` is true or
+    // something else stops it.
+    fn run_until(&mut self, cpu: &mut Chip8, input: &Input, done: impl Fn(&Chip8) -> bool) -> Stop {
+        for _ in 0..STEP_LIMIT {
+            let frame = cpu.run_instruction(input);
+
+            if let Some(err) = frame.error {
+                return Stop::Error(err.to_string());
+            }
+            if cpu.exited() {
+                return Stop::Exited;
+            }
+            if let Some(hit) = frame.watch {
+                return Stop::Watch(hit);
+            }
+            if done(cpu) {
+                return Stop::Done;
+            }
+            if cpu.breakpoints().any(|addr| addr == cpu.pc()) {
+                return Stop::Breakpoint;
+            }
+            if frame.waiting_for_key {
+                return Stop::WaitingForKey;
+            }
+        }
+
+        Stop::Limit
+    }
+
+    fn report(&mut self, stop: Stop, cpu: &Chip8) {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint => println!("Breakpoint at {}", self.describe(cpu.pc())),
+            Stop::Watch(hit) => println!("Watchpoint: {}", hit),
+            Stop::WaitingForKey => {
+                println!("Waiting for a key, continue and press one to get further")
+            }
+            Stop::Exited => println!("The program exited"),
+            Stop::Error(err) => println!("Error: {}", err),
+            Stop::Limit => println!("Stopped after {} instructions", STEP_LIMIT),
+        }
+    }
+
+    fn print_location(&mut self, cpu: &Chip8) {
+        self.shown = Some(cpu.pc());
+
+        if let Some(label) = self.label(cpu.pc()) {
+            println!("{}:", label);
+        }
+        println!("{}", self.line(cpu, cpu.pc()));
+    }
+
+    // A few instructions either side of addr, stopping at the end of memory.
+    // Instructions before it are assumed to be 2 bytes long, which is wrong
+    // only around XO-CHIP's `LD I, LONG`.
+    fn list(&self, cpu: &Chip8, addr: usize) -> Vec<String> {
+        let mut lines = vec![];
+        let mut here = addr.saturating_sub(8);
+
+        for _ in 0..10 {
+            // Only `LD I, LONG` is followed by more than its first 2 bytes.
+            let size = match cpu.peek(here, 2) {
+                Ok([0xF0, 0x00]) => 4,
+                Ok(_) => 2,
+                Err(_) => break,
+            };
+
+            if let Some(label) = self.label(here) {
+                lines.push(format!("{}:", label));
+            }
+            lines.push(self.line(cpu, here));
+
+            here += size;
+        }
+
+        lines
+    }
+
+    // One line of disassembly, marking the program counter and breakpoints.
+    fn line(&self, cpu: &Chip8, addr: usize) -> String {
+        let marker = match (addr == cpu.pc(), cpu.breakpoints().any(|b| b == addr)) {
+            (true, _) => "=>",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+
+        let (bytes, text) = match cpu.peek(addr, 2) {
+            Ok(bytes) => {
+                let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+                let next = cpu
+                    .peek(addr + 2, 2)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                    .unwrap_or(0);
+
+                match decode_long(opcode, next) {
+                    Ok(instruction) if instruction.size() == 4 => (
+                        format!("{:04X} {:04X}", opcode, next),
+                        self.listing.text(&instruction),
+                    ),
+                    Ok(instruction) => (
+                        format!("{:04X}     ", opcode),
+                        self.listing.text(&instruction),
+                    ),
+                    Err(_) => (format!("{:04X}     ", opcode), "???".to_string()),
+                }
+            }
+            Err(_) => (String::new(), "past the end of memory".to_string()),
+        };
+
+        format!("{} {:04X}  {}  {}", marker, addr, bytes, text)
+    }
+
+    fn label(&self, addr: usize) -> Option<&str> {
+        u16::try_from(addr)
+            .ok()
+            .and_then(|addr| self.listing.label(addr))
+    }
+
+    fn describe(&self, addr: usize) -> String {
+        match self.label(addr) {
+            Some(label) => format!("{:04X} ({})", addr, label),
+            None => format!("{:04X}", addr),
+        }
+    }
+
+    // Parses `<register>` or `<addr> [length] [r|w|rw]`. Memory watchpoints
+    // default to 1 byte, and to writes.
+    fn watchpoint(&self, cpu: &Chip8, target: &str, options: &[&str]) -> Option<Watchpoint> {
+        if let Ok(register) = target.parse::<Register>() {
+            if !options.is_empty() {
+                println!("Register watchpoints don't take a length or access");
+                return None;
+            }
+            return Some(Watchpoint::Register(register));
+        }
+
+        let addr = self.address(cpu, target)?;
+        let mut length = 1;
+        let mut access = Access::Write;
+
+        for option in options {
+            match *option {
+                "r" => access = Access::Read,
+                "w" => access = Access::Write,
+                "rw" => access = Access::ReadWrite,
+                option => match parse_hex(option).filter(|length| *length > 0) {
+                    Some(option) => length = option,
+                    None => {
+                        println!("`{}` isn't a hex length or one of r, w or rw", option);
+                        return None;
+                    }
+                },
+            }
+        }
+
+        Some(Watchpoint::Memory {
+            addr,
+            length,
+            access,
+        })
+    }
+
+    fn describe_watchpoint(&self, watchpoint: &Watchpoint) -> String {
+        match *watchpoint {
+            Watchpoint::Memory {
+                addr,
+                length,
+                access,
+            } => {
+                let access = match access {
+                    Access::Read => "reads",
+                    Access::Write => "writes",
+                    Access::ReadWrite => "reads and writes",
+                };
+                format!("{} of {} bytes at {}", access, length, self.describe(addr))
+            }
+            Watchpoint::Register(register) => format!("changes to {}", register),
+        }
+    }
+
+    // An address in the memory the program can use.
+    fn address(&self, cpu: &Chip8, text: &str) -> Option<usize> {
+        let addr = self
+            .listing
+            .address(text)
+            .map(usize::from)
+            .or_else(|| parse_hex(text));
+
+        match addr {
+            Some(addr) if cpu.peek(addr, 1).is_err() => {
+                println!("{:X} is past the end of memory", addr);
+                None
+            }
+            Some(addr) => Some(addr),
+            None => {
+                println!("`{}` isn't an address or label", text);
+                None
+            }
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::LoadOptions;
+    use crate::disasm;
+
+    fn start(rom: &[u8]) -> (Debugger, Chip8) {
+        let mut cpu = Chip8::new();
+        cpu.load_rom_bytes(rom, LoadOptions::new()).unwrap();
+
+        (Debugger::new(disasm::disassemble(rom, 0x200)), cpu)
+    }
+
+    fn run(debugger: &mut Debugger, cpu: &mut Chip8, line: &str) -> Action {
+        debugger.run_command(line, cpu, &Input::new())
+    }
+
+    // LD V0, 1 to LD V0, 5.
+    const LOADS: [u8; 10] = [0x60, 0x01, 0x60, 0x02, 0x60, 0x03, 0x60, 0x04, 0x60, 0x05];
+
+    #[test]
+    fn steps_stop_at_breakpoints() {
+        let (mut debugger, mut cpu) = start(&LOADS);
+
+        run(&mut debugger, &mut cpu, "break 206");
+        run(&mut debugger, &mut cpu, "step 4");
+        assert_eq!(cpu.pc(), 0x206);
+
+        // A step from the breakpoint gets past it.
+        run(&mut debugger, &mut cpu, "step 2");
+        assert_eq!(cpu.pc(), 0x20A);
+
+        // The last step can land on one without it getting in the way.
+        let (mut debugger, mut cpu) = start(&LOADS);
+        run(&mut debugger, &mut cpu, "break 204");
+        run(&mut debugger, &mut cpu, "step 2");
+        assert_eq!(cpu.pc(), 0x204);
+    }
+
+    #[test]
+    fn continues_past_a_breakpoint() {
+        let (mut debugger, mut cpu) = start(&LOADS);
+
+        run(&mut debugger, &mut cpu, "break 204");
+        assert!(matches!(
+            run(&mut debugger, &mut cpu, "continue"),
+            Action::Continue
+        ));
+        assert_eq!(cpu.run_frame(&Input::new()).breakpoint, Some(0x204));
+
+        run(&mut debugger, &mut cpu, "continue");
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(cpu.run_frame(&Input::new()).breakpoint, None);
+    }
+
+    #[test]
+    fn steps_over_and_out_of_subroutines() {
+        // CALL 0x206, LD V0, 1, JP 0x204, then LD V1, 2 and RET.
+        let rom = [0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0x61, 0x02, 0x00, 0xEE];
+
+        let (mut debugger, mut cpu) = start(&rom);
+        run(&mut debugger, &mut cpu, "next");
+        assert_eq!(cpu.pc(), 0x202);
+
+        let (mut debugger, mut cpu) = start(&rom);
+        run(&mut debugger, &mut cpu, "step");
+        assert_eq!(cpu.pc(), 0x206);
+        run(&mut debugger, &mut cpu, "out");
+        assert_eq!((cpu.pc(), cpu.stack().len()), (0x202, 0));
+    }
+
+    #[test]
+    fn addresses_stay_in_memory() {
+        let (mut debugger, mut cpu) = start(&[0x00, 0xE0]);
+
+        for line in [
+            "x FFFFFFFFFFFFFFFF 10",
+            "x FF0 FFFFFFFFFFFFFFFF",
+            "poke FFFFFFFFFFFFFFFF 1 2",
+            "list FFFFFFFFFFFFFFFF",
+            "watch FFFFFFFFFFFFFFFF FFFFFFFFFFFFFFFF",
+            "break 1000",
+        ] {
+            run(&mut debugger, &mut cpu, line);
+        }
+
+        assert_eq!(cpu.breakpoints().count(), 0);
+        assert!(cpu.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn lists_up_to_the_end_of_memory() {
+        let (debugger, cpu) = start(&LOADS);
+
+        let lines = debugger.list(&cpu, 0x200);
+        assert_eq!(lines.len(), 10);
+        assert!(lines[4].starts_with("=> 0200  6001"), "{}", lines[4]);
+
+        // The last instruction in memory is still listed.
+        let lines = debugger.list(&cpu, 0xFFE);
+        assert_eq!(lines.len(), 5);
+        assert!(lines[4].starts_with("   0FFE  0000"), "{}", lines[4]);
+    }
+}
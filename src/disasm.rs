@@ -0,0 +1,316 @@
+// Offline disassembly of a rom.
+//
+// Chip-8 roms freely mix code and data (sprites, tables, strings), so a
+// linear sweep of the rom would decode sprite bytes as instructions. Instead
+// we follow the flow of control from the entry point, the same way the
+// interpreter would, and only bytes that can actually be reached are treated
+// as instructions. Everything else is emitted as data.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::instruction::{decode_long, Instruction};
+
+pub struct Listing {
+    origin: u16,
+    bytes: Vec<u8>,
+    // Addresses at which a reachable instruction starts.
+    code: HashSet<u16>,
+    labels: BTreeMap<u16, String>,
+}
+
+pub fn disassemble(bytes: &[u8], origin: u16) -> Listing {
+    let mut listing = Listing {
+        origin,
+        bytes: bytes.to_vec(),
+        code: HashSet::new(),
+        labels: BTreeMap::new(),
+    };
+
+    let mut pending = vec![origin];
+
+    while let Some(addr) = pending.pop() {
+        if listing.code.contains(&addr) {
+            continue;
+        }
+
+        let instruction = match listing.decode(addr) {
+            Some(instruction) => instruction,
+            // Running off the end of the rom, or into something that doesn't
+            // decode, ends this path. Those bytes are left as data.
+            _ => continue,
+        };
+
+        listing.code.insert(addr);
+        let next = addr.wrapping_add(instruction.size() as u16);
+
+        match instruction {
+            Instruction::Jp(target) => {
+                listing.add_label(target, "label");
+                pending.push(target);
+            }
+            Instruction::Call(target) => {
+                listing.add_label(target, "sub");
+                pending.push(target);
+                pending.push(next);
+            }
+            // The target of a computed jump depends on V0 at runtime, so the
+            // base address is the best we can do.
+            Instruction::JpV0(target) => {
+                listing.add_label(target, "table");
+                pending.push(target);
+            }
+            Instruction::Ret | Instruction::Exit => {}
+            // Skips continue at either the next instruction or the one after.
+            Instruction::SeByte(..)
+            | Instruction::SneByte(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_) => {
+                // NOTE: XO-CHIP skips the whole of a 4 byte instruction.
+                let size = match listing.decode(next) {
+                    Some(skipped) => skipped.size(),
+                    None => 2,
+                };
+                pending.push(next.wrapping_add(size as u16));
+                pending.push(next);
+            }
+            Instruction::LdI(target) | Instruction::LdILong(target) => {
+                listing.add_label(target, "data");
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    listing
+}
+
+impl Listing {
+    fn opcode(&self, addr: u16) -> Option<u16> {
+        if !self.contains(addr) || !self.contains(addr.wrapping_add(1)) {
+            return None;
+        }
+
+        let index = (addr - self.origin) as usize;
+        Some(((self.bytes[index] as u16) << 8) | self.bytes[index + 1] as u16)
+    }
+
+    // The instruction at addr, if there is a whole one there.
+    fn decode(&self, addr: u16) -> Option<Instruction> {
+        let opcode = self.opcode(addr)?;
+        let instruction =
+            decode_long(opcode, self.opcode(addr.wrapping_add(2)).unwrap_or(0)).ok()?;
+
+        if !self.contains(addr.wrapping_add(instruction.size() as u16 - 1)) {
+            return None;
+        }
+
+        Some(instruction)
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && ((addr - self.origin) as usize) < self.bytes.len()
+    }
+
+    fn add_label(&mut self, addr: u16, kind: &str) {
+        // Only addresses inside the rom get a label, since the listing can
+        // only define those. Other targets are left as numbers.
+        if !self.contains(addr) {
+            return;
+        }
+
+        // Jump and call targets take priority over data references, so a
+        // subroutine that is also pointed at by I keeps its `sub_` name.
+        match self.labels.get(&addr) {
+            Some(existing) if !existing.starts_with("data") => {}
+            _ => {
+                self.labels.insert(addr, format!("{}_{:03X}", kind, addr));
+            }
+        }
+    }
+
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.code.contains(&addr)
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    // The address a label was given to.
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(label))
+            .map(|(addr, _)| *addr)
+    }
+
+    // The instruction text, with any address operand replaced by its label.
+    pub fn text(&self, instruction: &Instruction) -> String {
+        let target = match *instruction {
+            Instruction::Jp(addr)
+            | Instruction::Call(addr)
+            | Instruction::JpV0(addr)
+            | Instruction::LdI(addr)
+            | Instruction::LdILong(addr) => self.label(addr),
+            _ => None,
+        };
+
+        match (instruction, target) {
+            (Instruction::Jp(_), Some(label)) => format!("JP {}", label),
+            (Instruction::Call(_), Some(label)) => format!("CALL {}", label),
+            (Instruction::JpV0(_), Some(label)) => format!("JP V0, {}", label),
+            (Instruction::LdI(_), Some(label)) => format!("LD I, {}", label),
+            (Instruction::LdILong(_), Some(label)) => format!("LD I, LONG {}", label),
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.origin as usize + self.bytes.len();
+        let mut addr = self.origin as usize;
+        let mut data: Vec<u8> = vec![];
+        let mut data_start = addr;
+
+        writeln!(f, "; entry point {:03X}", self.origin)?;
+
+        while addr < end {
+            let here = addr as u16;
+
+            // An instruction is only printed as such when nothing jumps into
+            // the middle of it, otherwise that label would be lost.
+            let instruction = match self.decode(here) {
+                Some(instruction)
+                    if self.is_code(here)
+                        && (1..instruction.size() as u16)
+                            .all(|offset| self.label(here.wrapping_add(offset)).is_none()) =>
+                {
+                    Some(instruction)
+                }
+                _ => None,
+            };
+
+            if self.label(here).is_some() || instruction.is_some() || data.len() == 8 {
+                write_data(f, data_start, &data)?;
+                data.clear();
+            }
+
+            if let Some(label) = self.label(here) {
+                writeln!(f, "{}:", label)?;
+            }
+
+            match instruction {
+                Some(instruction) => {
+                    let index = addr - self.origin as usize;
+                    let bytes: Vec<String> = self.bytes[index..index + instruction.size()]
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect();
+                    writeln!(
+                        f,
+                        "    {:<23} ; {:03X}  {}",
+                        self.text(&instruction),
+                        addr,
+                        bytes.join(" ")
+                    )?;
+                    addr += instruction.size();
+                }
+                None => {
+                    if data.is_empty() {
+                        data_start = addr;
+                    }
+                    data.push(self.bytes[addr - self.origin as usize]);
+                    addr += 1;
+                }
+            }
+        }
+
+        write_data(f, data_start, &data)
+    }
+}
+
+fn write_data(f: &mut fmt::Formatter<'_>, addr: usize, data: &[u8]) -> fmt::Result {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{:#04X}", byte)).collect();
+    let binary: Vec<String> = data.iter().map(|byte| format!("{:08b}", byte)).collect();
+
+    writeln!(
+        f,
+        "    {:<23} ; {:03X}  {}",
+        format!("db {}", bytes.join(", ")),
+        addr,
+        // Showing data as bits makes sprites easy to spot in the listing.
+        binary.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const ROM: [u8; 16] = [
+        0x22, 0x08, // CALL sub_208
+        0xA2, 0x0A, // LD I, data_20A
+        0xB2, 0x0C, // JP V0, table_20C
+        0xF0, 0x90, // never reached
+        0x00, 0xEE, // sub_208: RET
+        0xFF, 0x81, // data_20A
+        0x12, 0x0E, // table_20C: JP label_20E
+        0x12, 0x0E, // label_20E: JP label_20E
+    ];
+
+    #[test]
+    fn separates_code_from_data() {
+        let listing = disassemble(&ROM, 0x200);
+
+        for addr in [0x200, 0x202, 0x204, 0x208, 0x20C, 0x20E] {
+            assert!(listing.is_code(addr), "{:03X}", addr);
+        }
+        for addr in [0x206, 0x20A] {
+            assert!(!listing.is_code(addr), "{:03X}", addr);
+        }
+    }
+
+    #[test]
+    fn names_labels_by_how_they_are_used() {
+        let listing = disassemble(&ROM, 0x200);
+
+        assert_eq!(listing.label(0x208), Some("sub_208"));
+        assert_eq!(listing.label(0x20A), Some("data_20A"));
+        assert_eq!(listing.label(0x20C), Some("table_20C"));
+        assert_eq!(listing.label(0x20E), Some("label_20E"));
+        assert_eq!(listing.label(0x200), None);
+
+        let text = listing.to_string();
+        assert!(text.contains("    CALL sub_208 "));
+        assert!(text.contains("    LD I, data_20A "));
+        assert!(text.contains("    JP V0, table_20C "));
+        assert!(text.contains("label_20E:\n    JP label_20E "));
+        assert!(text.contains("    db 0xF0, 0x90 "));
+        assert!(text.contains("data_20A:\n    db 0xFF, 0x81 "));
+    }
+
+    #[test]
+    fn code_labels_win_over_data_labels() {
+        // LD I, 0x204 then CALL 0x204, which returns straight away.
+        let listing = disassemble(&[0xA2, 0x04, 0x22, 0x04, 0x00, 0xEE], 0x200);
+
+        assert_eq!(listing.label(0x204), Some("sub_204"));
+    }
+
+    #[test]
+    fn leaves_targets_outside_the_rom_as_addresses() {
+        let listing = disassemble(&[0xA3, 0x00, 0x13, 0x00], 0x200);
+
+        assert_eq!(listing.label(0x300), None);
+        assert!(listing.to_string().contains("    JP 0x300 "));
+    }
+}
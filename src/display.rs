@@ -1,6 +1,10 @@
 // Reference: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 
-const PIXEL_COUNT: usize = 64 * 32;
+use crate::state::{self, Reader, Writer};
+
+// SUPER-CHIP doubles the resolution in both directions, so the memory is
+// sized for that and only the top left 64x32 pixels' worth is used otherwise.
+pub const PIXEL_COUNT: usize = 128 * 64;
 
 // 2.4 - Display
 
@@ -9,47 +13,161 @@ pub struct Display {
     // monochrome display with this format:
     // (0,  0)    (63,  0)
     // (0, 31)    (63, 31)
-    pub memory: [bool; PIXEL_COUNT],
+    // Pixels are stored row by row, `width()` pixels to a row.
+    // NOTE: XO-CHIP has two bitplanes, so each pixel holds a bit for each
+    //       plane and can be one of four colours. Other programs only ever
+    //       draw to the first plane.
+    pub memory: [u8; PIXEL_COUNT],
+
+    // SUPER-CHIP's 128x64 high resolution mode.
+    hires: bool,
+
+    // The planes selected with XO-CHIP's Fn01 - PLANE n, as a bit mask.
+    // Drawing, clearing and scrolling only affect the selected planes.
+    planes: u8,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
-            memory: [false; PIXEL_COUNT],
+            memory: [0; PIXEL_COUNT],
+            hires: false,
+            planes: 1,
         }
     }
 
-    pub fn set(&mut self, x: usize, y: usize, pixel: bool) -> bool {
-        // Sprites are XORed onto the existing screen.
-        let current = self.get(x, y);
-
-        if current ^ pixel {
-            self.memory[self.to_index(x, y)] = true;
-            // Pixel was not erased, so return false
-            false
+    pub fn width(&self) -> usize {
+        if self.hires {
+            128
         } else {
-            self.memory[self.to_index(x, y)] = false;
-            // If the pixel was erased (On -> Off) then return true
-            true
+            64
         }
     }
 
+    pub fn height(&self) -> usize {
+        self.width() / 2
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // 00FE - LOW, 00FF - HIGH
+    // NOTE: The screen is cleared when the resolution changes, as it is in
+    //       Octo, since the old contents would be laid out for the wrong width.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.memory = [0; PIXEL_COUNT];
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    // Fn01 - PLANE n
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, plane: u8, pixel: bool) -> bool {
+        // Sprites are XORed onto the existing screen.
+        let index = self.to_index(x, y);
+        let current = self.memory[index] & plane != 0;
+        if pixel {
+            self.memory[index] ^= plane;
+        }
+
+        // If the pixel was erased (On -> Off) then return true
+        // NOTE: Only a set sprite pixel can erase one, drawing an unset pixel
+        //       over an unset pixel is not a collision.
+        current && pixel
+    }
+
+    // Whether the pixel is on in any plane.
     pub fn get(&self, x: usize, y: usize) -> bool {
-        self.memory[self.to_index(x, y)]
+        self.memory[self.to_index(x, y)] != 0
     }
 
     pub fn clear(&mut self) {
-        self.memory = [false; PIXEL_COUNT];
+        for pixel in self.memory.iter_mut() {
+            *pixel &= !self.planes;
+        }
+    }
+
+    // 00Cn - SCD nibble
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    // 00FB - SCR
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    // 00FC - SCL
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    // Moves the selected planes by (dx, dy). Pixels scrolled off the screen
+    // are lost, and the space left behind is blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let previous = self.memory;
+
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    previous[self.to_index(from_x as usize, from_y as usize)] & self.planes
+                } else {
+                    0
+                };
+
+                let index = self.to_index(x as usize, y as usize);
+                self.memory[index] = (self.memory[index] & !self.planes) | moved;
+            }
+        }
     }
 
     fn to_index(&self, x: usize, y: usize) -> usize {
-        y * 64 + x
+        y * self.width() + x
+    }
+
+    pub fn save_state(&self, writer: &mut Writer) {
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        writer.bytes(&self.memory);
+    }
+
+    pub fn load_state(reader: &mut Reader) -> Result<Self, state::Error> {
+        let hires = reader.bool()?;
+        let planes = reader.u8()?;
+        let pixels = reader.bytes(PIXEL_COUNT)?;
+
+        if planes > 0b11 {
+            return Err(state::Error::Invalid("plane selection"));
+        }
+
+        if pixels.iter().any(|pixel| *pixel > 0b11) {
+            return Err(state::Error::Invalid("pixel"));
+        }
+
+        let mut memory = [0; PIXEL_COUNT];
+        memory.copy_from_slice(pixels);
+
+        Ok(Self {
+            memory,
+            hires,
+            planes,
+        })
     }
 
     pub fn dump_to_stdout(&self) {
-        for line in self.memory.chunks(64) {
+        let pixels = self.width() * self.height();
+        for line in self.memory[..pixels].chunks(self.width()) {
             for pixel in line {
-                if *pixel {
+                if *pixel != 0 {
                     print!("#");
                 } else {
                     print!(" ");
@@ -60,62 +178,113 @@ impl Display {
     }
 }
 
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Chip-8 draws graphics on screen through the use of sprites.
 // A sprite is a group of bytes which are a binary representation of the desired
 // picture.
 pub struct Sprite<'a> {
     bytes: &'a [u8],
+    // Bytes per row, 1 for normal sprites and 2 for SUPER-CHIP's 16x16 ones.
+    row_bytes: usize,
 }
 
 impl<'a> Sprite<'a> {
+    // NOTE: When XO-CHIP draws to both planes, the bytes for the second plane
+    //       follow those for the first, so a sprite has twice as many bytes.
     pub fn new(bytes: &'a [u8]) -> Self {
         // Chip-8 sprites may be up to 15 bytes
-        assert!(bytes.len() <= 15);
+        assert!(bytes.len() <= 15 * 2);
 
-        Self { bytes }
+        Self {
+            bytes,
+            row_bytes: 1,
+        }
     }
 
-    pub fn draw(&self, x: usize, y: usize, display: &mut Display) -> Collision {
+    // Dxy0 - A 16x16 sprite, stored as 16 rows of 2 bytes.
+    pub fn large(bytes: &'a [u8]) -> Self {
+        assert!(bytes.len() <= 32 * 2);
+
+        Self {
+            bytes,
+            row_bytes: 2,
+        }
+    }
+
+    pub fn draw(&self, x: usize, y: usize, clip: bool, display: &mut Display) -> Collision {
+        let (width, height) = (display.width(), display.height());
+
         // If the sprite is positioned so part of it is outside the coordinates
         // of the display, it wraps around to the opposite side of the screen.
-        let mut dx = x % 64;
-        let mut dy = y % 32;
+        // NOTE: When clipping, only the starting position wraps, and the parts
+        //       of the sprite past the edges aren't drawn.
+        let x = x % width;
+        let y = y % height;
+
+        let planes: Vec<u8> = [1, 2]
+            .into_iter()
+            .filter(|plane| display.planes() & plane != 0)
+            .collect();
 
-        let mut collision = Collision::False;
+        if planes.is_empty() {
+            return Collision::default();
+        }
 
-        for byte in self.bytes.iter() {
-            // A sprite is a group of bytes which are a binary representation of
-            // the desired picture.
-            let pixels = self.to_pixels(*byte);
+        let plane_bytes = self.bytes.len() / planes.len();
+        let rows = plane_bytes / self.row_bytes;
 
-            for pixel in pixels {
-                let collide = display.set(dx, dy, pixel);
+        let mut collided = vec![false; rows];
+        let mut clipped = 0;
 
-                // Sprites are XORed onto the existing screen. If this causes
-                // any pixels to be erased, VF is set to 1, otherwise it is set
-                // to 0
-                if collide {
-                    collision = Collision::True;
+        for (index, plane) in planes.iter().enumerate() {
+            let bytes = &self.bytes[index * plane_bytes..(index + 1) * plane_bytes];
+
+            for (row, bytes) in bytes.chunks(self.row_bytes).enumerate() {
+                if clip && y + row >= height {
+                    if index == 0 {
+                        clipped += 1;
+                    }
+                    continue;
                 }
 
-                dx += 1;
-                dx %= 64;
-            }
+                let dy = (y + row) % height;
+
+                // A sprite is a group of bytes which are a binary representation
+                // of the desired picture.
+                let pixels = bytes.iter().flat_map(|byte| self.to_pixels(*byte));
 
-            dx = x % 64;
-            dy += 1;
-            dy %= 32;
+                for (column, pixel) in pixels.enumerate() {
+                    if clip && x + column >= width {
+                        break;
+                    }
+
+                    // Sprites are XORed onto the existing screen. If this
+                    // causes any pixels to be erased, VF is set to 1, otherwise
+                    // it is set to 0
+                    if display.set((x + column) % width, dy, *plane, pixel) {
+                        collided[row] = true;
+                    }
+                }
+            }
         }
 
-        collision
+        Collision {
+            rows: collided.iter().filter(|row| **row).count() as u8,
+            clipped,
+        }
     }
 
     fn to_pixels(&self, byte: u8) -> [bool; 8] {
         let mut byte = byte;
         let mut pixels = [false; 8];
-        for i in 0..8 {
+        for pixel in pixels.iter_mut() {
             if byte.leading_ones() > 0 {
-                pixels[i] = true;
+                *pixel = true;
             }
             byte = byte.rotate_left(1);
         }
@@ -124,8 +293,121 @@ impl<'a> Sprite<'a> {
     }
 }
 
-#[derive(PartialEq)]
-pub enum Collision {
-    True,
-    False,
+#[derive(Default)]
+pub struct Collision {
+    // The number of rows in which a pixel was erased.
+    pub rows: u8,
+    // The number of rows that were clipped off the bottom of the screen.
+    pub clipped: u8,
+}
+
+impl Collision {
+    pub fn any(&self) -> bool {
+        self.rows > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(display: &Display) -> Vec<(usize, usize)> {
+        let mut pixels = vec![];
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                if display.get(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn scrolls_in_both_resolutions() {
+        for hires in [false, true] {
+            let mut display = Display::new();
+            display.set_hires(hires);
+            let (width, height) = (display.width(), display.height());
+            display.set(0, 0, 1, true);
+            display.set(width - 1, height - 1, 1, true);
+
+            display.scroll_down(2);
+            assert_eq!(lit(&display), [(0, 2)]);
+
+            display.scroll_right(4);
+            assert_eq!(lit(&display), [(4, 2)]);
+
+            display.scroll_left(4);
+            display.scroll_left(4);
+            assert_eq!(lit(&display), []);
+        }
+    }
+
+    #[test]
+    fn draws_16x16_sprites() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        let bytes: Vec<u8> = (0..16).flat_map(|_| [0x80, 0x01]).collect();
+
+        Sprite::large(&bytes).draw(10, 20, false, &mut display);
+
+        assert!(display.get(10, 20) && display.get(25, 20));
+        assert!(display.get(10, 35) && display.get(25, 35));
+        assert!(!display.get(11, 20) && !display.get(10, 36));
+    }
+
+    #[test]
+    fn counts_collided_and_clipped_rows() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        let bytes = [0xFF; 4];
+
+        let collision = Sprite::new(&bytes).draw(0, 62, true, &mut display);
+        assert_eq!((collision.rows, collision.clipped), (0, 2));
+
+        let collision = Sprite::new(&bytes).draw(4, 60, true, &mut display);
+        assert_eq!((collision.rows, collision.clipped), (2, 0));
+        assert!(collision.any());
+    }
+
+    #[test]
+    fn only_changes_the_selected_planes() {
+        let mut display = Display::new();
+        display.set(0, 0, 1, true);
+        display.set(0, 0, 2, true);
+        display.set(1, 0, 2, true);
+
+        display.select_planes(2);
+        display.scroll_down(1);
+        assert_eq!(display.memory[0], 1);
+        assert_eq!(display.memory[display.to_index(0, 1)], 2);
+        assert_eq!(display.memory[display.to_index(1, 1)], 2);
+
+        display.clear();
+        assert_eq!(lit(&display), [(0, 0)]);
+
+        display.select_planes(0b111);
+        assert_eq!(display.planes(), 3);
+    }
+
+    #[test]
+    fn draws_a_sprite_for_each_selected_plane() {
+        let mut display = Display::new();
+        display.select_planes(3);
+
+        // One row for the first plane, then one for the second.
+        Sprite::new(&[0xC0, 0x60]).draw(0, 0, false, &mut display);
+        assert_eq!(display.memory[..3], [1, 3, 2]);
+
+        // Only the first plane is selected, so only it collides.
+        display.select_planes(1);
+        let collision = Sprite::new(&[0x40]).draw(0, 0, false, &mut display);
+        assert_eq!(collision.rows, 1);
+        assert_eq!(display.memory[..3], [1, 2, 2]);
+
+        display.select_planes(0);
+        let collision = Sprite::new(&[0xFF]).draw(0, 0, false, &mut display);
+        assert!(!collision.any());
+    }
 }
@@ -0,0 +1,585 @@
+// A GDB remote serial protocol stub.
+// Reference: https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// `--gdb <PORT>` waits for a debugger to connect on localhost before running
+// the rom, and starts stopped at its first instruction. The frontend polls the
+// stub once per frame, and skips running frames while it is stopped.
+//
+// The registers are, in order and numbered from 0:
+//
+//     v0 - vf   8 bits each
+//     i         16 bits
+//     pc        16 bits
+//     sp        8 bits
+//     dt, st    8 bits each
+//
+// NOTE: GDB has no Chip-8 architecture, so registers are sent big endian, the
+//       same as Chip-8's own byte order, and described by a target.xml that
+//       front ends can ask for with qXfer.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::chip8::{Access, Chip8, Error, FrameResult, Register, Watched, Watchpoint};
+use crate::input::Input;
+
+// Sizes in bytes of the registers, in the order above.
+const REGISTERS: [(Register, usize); 21] = [
+    (Register::V(0x0), 1),
+    (Register::V(0x1), 1),
+    (Register::V(0x2), 1),
+    (Register::V(0x3), 1),
+    (Register::V(0x4), 1),
+    (Register::V(0x5), 1),
+    (Register::V(0x6), 1),
+    (Register::V(0x7), 1),
+    (Register::V(0x8), 1),
+    (Register::V(0x9), 1),
+    (Register::V(0xA), 1),
+    (Register::V(0xB), 1),
+    (Register::V(0xC), 1),
+    (Register::V(0xD), 1),
+    (Register::V(0xE), 1),
+    (Register::V(0xF), 1),
+    (Register::I, 2),
+    (Register::Pc, 2),
+    (Register::Sp, 1),
+    (Register::Dt, 1),
+    (Register::St, 1),
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.crab8.chip8">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// Signal numbers for stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Stopped,
+    // The debugger detached, and the rom should carry on without it.
+    Detached,
+    // The debugger asked for the emulator to stop.
+    Killed,
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    // Bytes received but not yet handled.
+    received: Vec<u8>,
+    status: Status,
+    // Acknowledgements are sent until the debugger asks for QStartNoAckMode.
+    acks: bool,
+}
+
+impl GdbStub {
+    // Waits for a debugger to connect to the port on localhost.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (stream, _) = listener.accept()?;
+
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // Polling reads give up almost immediately, so a running rom isn't
+        // held up waiting for packets that aren't coming.
+        stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            received: vec![],
+            status: Status::Stopped,
+            acks: true,
+        })
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.status == Status::Stopped
+    }
+
+    // Handles anything the debugger has sent since the last poll.
+    pub fn poll(&mut self, cpu: &mut Chip8, input: &Input) -> io::Result<Status> {
+        let mut buffer = [0; 4096];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => return Ok(Status::Detached),
+            Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(err),
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            if let Some(reply) = self.handle(&packet, cpu, input) {
+                self.send(&reply)?;
+            }
+
+            if matches!(self.status, Status::Detached | Status::Killed) {
+                break;
+            }
+        }
+
+        Ok(self.status)
+    }
+
+    // Tells the debugger why a frame stopped, if it did.
+    pub fn report(&mut self, cpu: &Chip8, frame: &FrameResult) -> io::Result<()> {
+        if self.status != Status::Running {
+            return Ok(());
+        }
+
+        let reply = if cpu.exited() {
+            "W00".to_string()
+        } else if let Some(err) = &frame.error {
+            stop_reply(signal(err))
+        } else if let Some(hit) = &frame.watch {
+            watch_reply(&hit.watched)
+        } else if frame.breakpoint.is_some() {
+            stop_reply(SIGTRAP)
+        } else {
+            return Ok(());
+        };
+
+        self.status = Status::Stopped;
+        self.send(&reply)
+    }
+
+    // Takes the next whole packet from what has been received. Ctrl-C, sent
+    // outside of any packet, interrupts a running rom.
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.received.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.received.remove(0);
+                    if self.status == Status::Running {
+                        self.status = Status::Stopped;
+                        self.send(&stop_reply(SIGINT))?;
+                    }
+                }
+                Some(b'$') => break,
+                // Acknowledgements, and anything else between packets.
+                Some(_) => {
+                    self.received.remove(0);
+                }
+            }
+        }
+
+        let end = match self.received.iter().position(|byte| *byte == b'#') {
+            Some(end) if self.received.len() >= end + 3 => end,
+            _ => return Ok(None),
+        };
+
+        let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+        let data = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..])
+            .ok()
+            .and_then(|text| u8::from_str_radix(text, 16).ok());
+
+        // A corrupted packet is asked for again, and skipped meanwhile.
+        if checksum != Some(checksum_of(data)) {
+            if self.acks {
+                self.stream.write_all(b"-")?;
+            }
+            return self.next_packet();
+        }
+
+        if self.acks {
+            self.stream.write_all(b"+")?;
+        }
+
+        Ok(Some(String::from_utf8_lossy(data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+
+    // The reply to a packet, or None if it resumes running and the reply
+    // comes when the rom stops.
+    fn handle(&mut self, packet: &str, cpu: &mut Chip8, input: &Input) -> Option<String> {
+        // Packets are lossily decoded, so the first character may not be a
+        // single byte.
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => REGISTERS
+                .iter()
+                .map(|(register, size)| Some(hex_register(cpu.register(*register).ok()?, *size)))
+                .collect::<Option<String>>()
+                .unwrap_or_else(|| "E01".to_string()),
+            "G" => {
+                let mut offset = 0;
+                for (register, size) in REGISTERS {
+                    let value = args.get(offset..offset + size * 2).and_then(parse_hex);
+                    match value.map(|value| cpu.set_register(register, value as u16)) {
+                        Some(Ok(())) => {}
+                        _ => return Some("E01".to_string()),
+                    }
+                    offset += size * 2;
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                let register = parse_hex(args).and_then(|n| REGISTERS.get(n));
+                match register.map(|(register, size)| (cpu.register(*register), size)) {
+                    Some((Ok(value), size)) => hex_register(value, *size),
+                    _ => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    Some((REGISTERS.get(parse_hex(n)?)?, parse_hex(value)?))
+                });
+                match register {
+                    Some(((register, _), value))
+                        if cpu.set_register(*register, value as u16).is_ok() =>
+                    {
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => {
+                let range = args
+                    .split_once(',')
+                    .and_then(|(addr, length)| Some((parse_hex(addr)?, parse_hex(length)?)));
+                match range.and_then(|(addr, length)| cpu.peek(addr, length).ok()) {
+                    Some(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+                    None => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, length) = range.split_once(',')?;
+                    let bytes = parse_bytes(data)?;
+                    (parse_hex(length)? == bytes.len()).then_some((parse_hex(addr)?, bytes))
+                });
+                match write.map(|(addr, bytes)| cpu.poke(addr, &bytes)) {
+                    Some(Ok(())) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            }
+            // Software and hardware breakpoints are the same thing here.
+            // Types 2, 3 and 4 are write, read and access watchpoints, whose
+            // kind is the number of bytes watched.
+            "Z" | "z" => {
+                let point = args.split_once(',').and_then(|(kind, args)| {
+                    let (addr, length) = args.split_once(',')?;
+                    Some((kind, parse_hex(addr)?, parse_hex(length)?))
+                });
+                let access = match point {
+                    Some(("2", ..)) => Some(Access::Write),
+                    Some(("3", ..)) => Some(Access::Read),
+                    Some(("4", ..)) => Some(Access::ReadWrite),
+                    _ => None,
+                };
+
+                match (point, access) {
+                    // Breakpoints are a single address, whatever length they
+                    // say.
+                    (Some(("0" | "1", addr, _)), _) if cpu.peek(addr, 1).is_err() => {
+                        "E01".to_string()
+                    }
+                    (Some(("0" | "1", addr, _)), _) if command == "Z" => {
+                        cpu.add_breakpoint(addr);
+                        "OK".to_string()
+                    }
+                    (Some(("0" | "1", addr, _)), _) => {
+                        cpu.remove_breakpoint(addr);
+                        "OK".to_string()
+                    }
+                    (Some((_, addr, length)), Some(_)) if cpu.peek(addr, length).is_err() => {
+                        "E01".to_string()
+                    }
+                    (Some((_, addr, length)), Some(access)) => {
+                        let watchpoint = Watchpoint::Memory {
+                            addr,
+                            length,
+                            access,
+                        };
+                        if command == "Z" {
+                            cpu.add_watchpoint(watchpoint);
+                        } else {
+                            cpu.remove_watchpoint(watchpoint);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => String::new(),
+                }
+            }
+            "s" => {
+                let frame = cpu.run_instruction(input);
+                match (frame.error, frame.watch) {
+                    _ if cpu.exited() => "W00".to_string(),
+                    (Some(err), _) => stop_reply(signal(&err)),
+                    (None, Some(hit)) => watch_reply(&hit.watched),
+                    (None, None) => stop_reply(SIGTRAP),
+                }
+            }
+            "c" => {
+                // Step off any breakpoint at the current address first,
+                // otherwise continuing would stop at it straight away.
+                let frame = cpu.run_instruction(input);
+                if cpu.exited() {
+                    return Some("W00".to_string());
+                }
+                if let Some(err) = frame.error {
+                    return Some(stop_reply(signal(&err)));
+                }
+                if let Some(hit) = frame.watch {
+                    return Some(watch_reply(&hit.watched));
+                }
+
+                self.status = Status::Running;
+                return None;
+            }
+            "k" => {
+                self.status = Status::Killed;
+                return None;
+            }
+            "D" => {
+                self.status = Status::Detached;
+                "OK".to_string()
+            }
+            // There is only ever the one thread.
+            "H" | "T" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let range = range
+                .split_once(',')
+                .and_then(|(offset, length)| Some((parse_hex(offset)?, parse_hex(length)?)));
+            return match range {
+                Some((offset, length)) => {
+                    let xml = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                    let chunk = &xml[..length.min(xml.len())];
+                    let more = if chunk.len() < xml.len() { "m" } else { "l" };
+                    format!("{}{}", more, chunk)
+                }
+                None => "E01".to_string(),
+            };
+        }
+
+        match packet {
+            "QStartNoAckMode" => {
+                self.acks = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+// A stop for a memory watchpoint names the address. GDB has no register
+// watchpoints of this kind, so those are reported as a plain trap.
+fn watch_reply(watched: &Watched) -> String {
+    match *watched {
+        Watched::Memory { addr, access, .. } => {
+            let kind = match access {
+                Access::Write => "watch",
+                Access::Read => "rwatch",
+                Access::ReadWrite => "awatch",
+            };
+            format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+        }
+        Watched::Register { .. } => stop_reply(SIGTRAP),
+    }
+}
+
+fn signal(error: &Error) -> u8 {
+    match error {
+        Error::UnrecognisedInstruction(..) => SIGILL,
+        Error::StackOverflow | Error::StackUnderflow | Error::MemoryOutOfBounds { .. } => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+fn hex_register(value: u16, size: usize) -> String {
+    match size {
+        1 => format!("{:02x}", value as u8),
+        _ => format!("{:04x}", value),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::LoadOptions;
+
+    // A debugger at the other end of a connection to the stub.
+    struct Client {
+        stream: TcpStream,
+        stub: GdbStub,
+        cpu: Chip8,
+    }
+
+    impl Client {
+        fn connect(rom: &[u8]) -> Self {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(1)))
+                .unwrap();
+            let stub = GdbStub::new(listener.accept().unwrap().0).unwrap();
+
+            let mut cpu = Chip8::new();
+            cpu.load_rom_bytes(rom, LoadOptions::new()).unwrap();
+
+            Self { stream, stub, cpu }
+        }
+
+        // Sends a packet, and polls the stub until its reply arrives.
+        fn send(&mut self, data: &[u8]) -> String {
+            self.stream.write_all(b"$").unwrap();
+            self.stream.write_all(data).unwrap();
+            self.stream
+                .write_all(format!("#{:02x}", checksum_of(data)).as_bytes())
+                .unwrap();
+
+            let mut received = vec![];
+            for _ in 0..1000 {
+                self.stub.poll(&mut self.cpu, &Input::new()).unwrap();
+
+                let mut buffer = [0; 4096];
+                match self.stream.read(&mut buffer) {
+                    Ok(length) => received.extend_from_slice(&buffer[..length]),
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(err) => panic!("{}", err),
+                }
+
+                let text = String::from_utf8_lossy(&received);
+                if let Some(end) = text.find('#').filter(|end| text.len() >= end + 3) {
+                    let start = text.find('$').expect("a reply starts with $");
+                    return text[start + 1..end].to_string();
+                }
+            }
+
+            panic!("no reply to {}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn replies_to_a_session() {
+        // LD V0, 12 then LD I, 204.
+        let mut client = Client::connect(&[0x60, 0x12, 0xA2, 0x04]);
+
+        assert!(client
+            .send(b"qSupported:multiprocess+")
+            .starts_with("PacketSize="));
+        assert_eq!(client.send(b"QStartNoAckMode"), "OK");
+        assert_eq!(client.send(b"?"), "S05");
+        assert_eq!(client.send(b"m200,4"), "6012a204");
+        assert_eq!(client.send(b"s"), "S05");
+        assert_eq!(client.send(b"p0"), "12");
+        assert_eq!(client.send(b"p11"), "0202");
+        assert_eq!(client.send(b"M300,2:abcd"), "OK");
+        assert_eq!(client.send(b"m2ff,3"), "00abcd");
+        assert_eq!(client.send(b"Z0,202,2"), "OK");
+        assert_eq!(client.send(b"z0,202,2"), "OK");
+        assert_eq!(client.send(b"Z2,300,2"), "OK");
+        assert_eq!(client.send(b"z2,300,2"), "OK");
+        assert_eq!(client.send(b"Z5,300,2"), "");
+        assert_eq!(client.send(b"D"), "OK");
+    }
+
+    #[test]
+    fn rejects_bad_packets() {
+        let mut client = Client::connect(&[0x00, 0xE0]);
+
+        // Unknown packets get an empty reply, even when they aren't text.
+        assert_eq!(client.send("é".as_bytes()), "");
+        assert_eq!(client.send(&[0xFF, 0x00]), "");
+
+        for packet in [
+            "mffffffffffffffff,2",
+            "m200,ffffffffffffffff",
+            "mfff,2",
+            "Mffffffffffffffff,1:00",
+            "Z0,1000,2",
+            "Z1,ffffffffffffffff,2",
+            "z0,ffffffffffffffff,ffffffffffffffff",
+            "Z2,ffffffffffffffff,10",
+            "Z3,300,ffffffffffffffff",
+            "z4,ffffffffffffffff,ffffffffffffffff",
+            "p15",
+            "Gzz",
+        ] {
+            assert_eq!(client.send(packet.as_bytes()), "E01", "{}", packet);
+        }
+
+        assert!(client.cpu.watchpoints().is_empty());
+        assert_eq!(client.cpu.breakpoints().count(), 0);
+    }
+}
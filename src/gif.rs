@@ -0,0 +1,387 @@
+// A decoder for GIF images, as far as reading Octo cartridges needs.
+// Reference: https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+//
+// Only the palette index of each pixel is kept. Colour tables are skipped,
+// and so are extensions, which only hold timing, transparency and comments.
+
+use std::fmt;
+
+pub const MAGIC: [&[u8; 6]; 2] = [b"GIF87a", b"GIF89a"];
+
+// LZW codes are at most 12 bits long.
+const MAX_CODES: usize = 4096;
+
+#[derive(Debug)]
+pub struct Error {
+    // Where in the file the problem was found.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {:#X}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Gif {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Frame>,
+}
+
+// One image of the GIF, placed at `left` and `top` on the screen.
+pub struct Frame {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+    // The palette index of each pixel, a row at a time from the top.
+    pub pixels: Vec<u8>,
+}
+
+pub fn is_gif(bytes: &[u8]) -> bool {
+    MAGIC.iter().any(|magic| bytes.starts_with(*magic))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Gif, Error> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if !is_gif(reader.take(6)?) {
+        return Err(reader.error("not a GIF image"));
+    }
+
+    // Logical screen descriptor.
+    let width = reader.u16()? as usize;
+    let height = reader.u16()? as usize;
+    let flags = reader.byte()?;
+    reader.take(2)?;
+    reader.skip_color_table(flags)?;
+
+    let mut frames = vec![];
+
+    loop {
+        match reader.byte()? {
+            // Image descriptor.
+            0x2C => frames.push(reader.frame()?),
+            // Extension, with a label and then sub-blocks like image data.
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            }
+            // Trailer.
+            0x3B => break,
+            byte => {
+                reader.position -= 1;
+                return Err(reader.error(&format!("unexpected block {:#04X}", byte)));
+            }
+        }
+    }
+
+    Ok(Gif {
+        width,
+        height,
+        frames,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> Error {
+        Error {
+            offset: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + count)
+            .ok_or_else(|| self.error("the image is cut short"))?;
+        self.position += count;
+
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Both the global and local colour tables are flagged by the top bit of
+    // their descriptor's flags, which also hold their size in the low 3 bits.
+    fn skip_color_table(&mut self, flags: u8) -> Result<(), Error> {
+        if flags & 0x80 != 0 {
+            self.take(3 << ((flags & 0x07) + 1))?;
+        }
+
+        Ok(())
+    }
+
+    // Data is split into blocks of up to 255 bytes, each starting with its
+    // length, and ends with an empty block.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, Error> {
+        let mut data = vec![];
+
+        loop {
+            let length = self.byte()? as usize;
+            if length == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.take(length)?);
+        }
+    }
+
+    fn frame(&mut self) -> Result<Frame, Error> {
+        let left = self.u16()? as usize;
+        let top = self.u16()? as usize;
+        let width = self.u16()? as usize;
+        let height = self.u16()? as usize;
+        let flags = self.byte()?;
+        self.skip_color_table(flags)?;
+
+        let start = self.position;
+        let code_size = self.byte()?;
+        if !(1..=11).contains(&code_size) {
+            self.position = start;
+            return Err(self.error(&format!("bad LZW code size {}", code_size)));
+        }
+
+        let data = self.sub_blocks()?;
+        let mut pixels = lzw(&data, code_size, width * height).map_err(|message| Error {
+            offset: start,
+            message,
+        })?;
+
+        if pixels.len() < width * height {
+            return Err(Error {
+                offset: start,
+                message: format!(
+                    "{}x{} image with only {} pixels",
+                    width,
+                    height,
+                    pixels.len()
+                ),
+            });
+        }
+
+        // An empty image has no rows to reorder.
+        if flags & 0x40 != 0 && width > 0 {
+            pixels = deinterlace(&pixels, width, height);
+        }
+
+        Ok(Frame {
+            left,
+            top,
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+// Decompresses image data, with codes packed least significant bit first.
+// Codes below `clear` stand for themselves, and each code after a clear adds
+// a string to the table: the previous code's string plus the first byte of
+// the current one's. Decoding stops after `limit` pixels, since anything past
+// the end of the image is never shown.
+fn lzw(data: &[u8], min_code_size: u8, limit: usize) -> Result<Vec<u8>, String> {
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+
+    // Each string is stored as the code of the string it extends, along with
+    // its last byte, its first byte and its length.
+    let mut prefix = vec![0usize; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut first = vec![0u8; MAX_CODES];
+    let mut length = vec![1usize; MAX_CODES];
+    for code in 0..clear {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+    }
+
+    let mut code_size = min_code_size as usize + 1;
+    let mut next = end + 1;
+    let mut previous: Option<usize> = None;
+    let mut pixels = vec![];
+
+    let mut bit = 0;
+    while pixels.len() < limit && bit + code_size <= data.len() * 8 {
+        let mut code = 0;
+        for index in 0..code_size {
+            let position = bit + index;
+            code |= ((data[position / 8] >> (position % 8)) as usize & 1) << index;
+        }
+        bit += code_size;
+
+        if code == clear {
+            code_size = min_code_size as usize + 1;
+            next = end + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+
+        match previous {
+            None if code < clear => {}
+            None => return Err(format!("LZW code {} before any string", code)),
+            Some(previous) => {
+                // A code can be the one about to be added, when the string
+                // repeats its own first byte.
+                let byte = match code {
+                    code if code < next => first[code],
+                    code if code == next => first[previous],
+                    code => return Err(format!("LZW code {} is not in the table", code)),
+                };
+
+                if next < MAX_CODES {
+                    prefix[next] = previous;
+                    suffix[next] = byte;
+                    first[next] = first[previous];
+                    length[next] = length[previous] + 1;
+                    next += 1;
+
+                    if next == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+        }
+
+        // Strings are stored back to front.
+        let start = pixels.len();
+        pixels.resize(start + length[code], 0);
+        let mut string = code;
+        for pixel in pixels[start..].iter_mut().rev() {
+            *pixel = suffix[string];
+            string = prefix[string];
+        }
+
+        previous = Some(code);
+    }
+
+    pixels.truncate(limit);
+    Ok(pixels)
+}
+
+// Interlaced images store every 8th row starting from the first, then every
+// 8th from the 5th, every 4th from the 3rd and every 2nd from the 2nd.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let rows = [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .into_iter()
+        .flat_map(|(start, step)| (start..height).step_by(step));
+
+    let mut deinterlaced = vec![0; pixels.len()];
+    for (row, y) in pixels.chunks(width).zip(rows) {
+        deinterlaced[y * width..(y + 1) * width].copy_from_slice(row);
+    }
+
+    deinterlaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACE: &[u8] = include_bytes!("../tests/cartridges/face.gif");
+    const FACE_INTERLACED: &[u8] = include_bytes!("../tests/cartridges/face-interlaced.gif");
+    const LARGE: &[u8] = include_bytes!("../tests/cartridges/large.gif");
+
+    // A GIF of one image without colour tables, whose LZW data is `data`.
+    fn image(width: u8, height: u8, code_size: u8, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&[width, 0, height, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0x2C, 0, 0, 0, 0, width, 0, height, 0, 0, code_size]);
+        bytes.push(data.len() as u8);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&[0, 0x3B]);
+        bytes
+    }
+
+    fn error(bytes: &[u8]) -> Error {
+        match decode(bytes) {
+            Ok(_) => panic!("the image shouldn't decode"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn decodes_frames() {
+        let gif = decode(FACE).unwrap();
+        assert_eq!((gif.width, gif.height), (32, 24));
+        assert_eq!(gif.frames.len(), 2);
+        for frame in &gif.frames {
+            assert_eq!((frame.left, frame.top), (0, 0));
+            assert_eq!((frame.width, frame.height), (32, 24));
+            assert_eq!(frame.pixels.len(), 32 * 24);
+            assert!(frame.pixels.iter().all(|pixel| *pixel < 16));
+        }
+    }
+
+    #[test]
+    fn deinterlaces_rows() {
+        let plain = decode(FACE).unwrap();
+        let interlaced = decode(FACE_INTERLACED).unwrap();
+
+        for (plain, interlaced) in plain.frames.iter().zip(&interlaced.frames) {
+            assert_eq!(plain.pixels, interlaced.pixels);
+        }
+    }
+
+    #[test]
+    fn decodes_past_a_full_code_table() {
+        let gif = decode(LARGE).unwrap();
+        assert_eq!(gif.frames.len(), 1);
+        assert_eq!(gif.frames[0].pixels.len(), 128 * 128);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_image() {
+        // Four 1s, then a code that isn't in the table yet.
+        let gif = decode(&image(2, 2, 2, &[0x4C, 0x12, 0x5F])).unwrap();
+        assert_eq!(gif.frames[0].pixels, [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        assert_eq!(error(b"GIF88a").message, "not a GIF image");
+        assert_eq!(
+            error(&image(2, 2, 2, &[0x4C, 0x01])).message,
+            "2x2 image with only 1 pixels"
+        );
+        assert_eq!(
+            error(&image(2, 2, 12, &[0x4C, 0x01])).message,
+            "bad LZW code size 12"
+        );
+
+        let mut unknown = image(2, 2, 2, &[0x4C, 0x12, 0x5F]);
+        let trailer = unknown.len() - 1;
+        unknown[trailer] = 0x00;
+        let err = error(&unknown);
+        assert_eq!(
+            (err.offset, err.message.as_str()),
+            (trailer, "unexpected block 0x00")
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        for length in 0..FACE.len() {
+            assert_eq!(error(&FACE[..length]).message, "the image is cut short");
+        }
+    }
+}
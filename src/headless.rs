@@ -0,0 +1,491 @@
+// Running roms without a window, for automated tests.
+//
+// `crab-8 test` runs a rom for a number of frames, pressing keys from a
+// script, and then compares the screen with the one expected.
+//
+// Input scripts have a line for each key press, with the frame or range of
+// frames to hold the keys down for, then the keys in hex:
+//
+//     # frame  keys
+//     60       5
+//     120-130  4 6
+//
+// Frames count from 0. Blank lines and lines starting with `#` are ignored.
+//
+// Screens are either text, with a line per row of `#` for pixels that are on
+// and spaces or `.` for pixels that are off, as `Display::dump_to_stdout`
+// prints them, or PBM images with 1 (black) for pixels that are on. Text rows
+// can leave off their trailing spaces, but every row has to be there, since
+// the number of rows tells the resolution apart.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::chip8::{self, Chip8};
+use crate::display::Display;
+use crate::input::Input;
+
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Default)]
+pub struct Script {
+    presses: Vec<(RangeInclusive<u32>, Vec<u8>)>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut presses = vec![];
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| Error {
+                line: index + 1,
+                message,
+            };
+
+            let mut fields = line.split_whitespace();
+            let range = fields.next().expect("blank lines are skipped");
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let frame = |text: &str| {
+                text.parse::<u32>()
+                    .map_err(|_| error(format!("`{}` is not a frame number", range)))
+            };
+            let frames = frame(start)?..=frame(end)?;
+            if frames.is_empty() {
+                return Err(error(format!("`{}` ends before it starts", range)));
+            }
+
+            let keys = fields
+                .map(|key| match u8::from_str_radix(key, 16) {
+                    Ok(key) if key <= 0xF => Ok(key),
+                    _ => Err(error(format!("`{}` is not a key from 0 to F", key))),
+                })
+                .collect::<Result<Vec<u8>, Error>>()?;
+
+            if keys.is_empty() {
+                return Err(error("no keys to press".to_string()));
+            }
+
+            presses.push((frames, keys));
+        }
+
+        Ok(Self { presses })
+    }
+
+    // The keys held down during a frame.
+    pub fn input(&self, frame: u32) -> Input {
+        let mut input = Input::new();
+
+        for (frames, keys) in &self.presses {
+            if frames.contains(&frame) {
+                for key in keys {
+                    input.press(*key);
+                }
+            }
+        }
+
+        input
+    }
+}
+
+// Runs `frames` frames, or until the program exits.
+pub fn run(cpu: &mut Chip8, frames: u32, script: &Script) -> Result<(), chip8::Error> {
+    for frame in 0..frames {
+        if let Some(err) = cpu.run_frame(&script.input(frame)).error {
+            return Err(err);
+        }
+
+        if cpu.exited() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Which pixels of the display are on, in any plane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Screen {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    // Reads a screen from a PBM image, or from text.
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        match bytes {
+            [b'P', b'1' | b'4', ..] => Self::parse_pbm(bytes),
+            _ => {
+                let text = std::str::from_utf8(bytes).map_err(|_| "not text or a PBM image")?;
+                Self::parse_text(text)
+            }
+        }
+    }
+
+    fn parse_text(text: &str) -> Result<Self, String> {
+        let rows: Vec<&str> = text.lines().collect();
+        let (width, height) = match rows.len() {
+            32 => (64, 32),
+            64 => (128, 64),
+            rows => return Err(format!("{} rows, a screen has 32 or 64", rows)),
+        };
+
+        let mut pixels = vec![false; width * height];
+
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() > width {
+                return Err(format!("line {}: wider than {} pixels", y + 1, width));
+            }
+
+            for (x, pixel) in row.chars().enumerate() {
+                pixels[y * width + x] = match pixel {
+                    '#' => true,
+                    ' ' | '.' => false,
+                    _ => return Err(format!("line {}: unexpected `{}`", y + 1, pixel)),
+                };
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Reads both plain (P1) and raw (P4) PBM images.
+    // Reference: https://netpbm.sourceforge.net/doc/pbm.html
+    fn parse_pbm(bytes: &[u8]) -> Result<Self, String> {
+        let mut pbm = Pbm { bytes, position: 2 };
+        let raw = bytes[1] == b'4';
+
+        let mut number = || {
+            pbm.token()
+                .and_then(|token| std::str::from_utf8(token).ok()?.parse::<usize>().ok())
+                .ok_or("bad PBM header")
+        };
+        let (width, height) = (number()?, number()?);
+        if !matches!((width, height), (64, 32) | (128, 64)) {
+            return Err(format!(
+                "{}x{} image, a screen is 64x32 or 128x64",
+                width, height
+            ));
+        }
+
+        let pixels: Vec<bool> = if raw {
+            // A single whitespace character separates the header from the
+            // pixels, which are packed 8 to a byte.
+            let start = pbm.position + 1;
+            let data = bytes.get(start..start + width / 8 * height);
+            let data = data.ok_or("the image is cut short")?;
+
+            data.iter()
+                .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+                .collect()
+        } else {
+            let mut pixels = vec![];
+            while pixels.len() < width * height {
+                match pbm.next_non_space().ok_or("the image is cut short")? {
+                    b'0' => pixels.push(false),
+                    b'1' => pixels.push(true),
+                    byte => return Err(format!("unexpected `{}` in the image", byte as char)),
+                }
+            }
+            pixels
+        };
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // The differences between this screen and the expected one, or None if
+    // they are the same.
+    pub fn diff<'a>(&'a self, expected: &'a Screen) -> Option<ScreenDiff<'a>> {
+        (self != expected).then_some(ScreenDiff {
+            actual: self,
+            expected,
+        })
+    }
+}
+
+impl From<&Display> for Screen {
+    fn from(display: &Display) -> Self {
+        let (width, height) = (display.width(), display.height());
+
+        Self {
+            width,
+            height,
+            pixels: display.memory[..width * height]
+                .iter()
+                .map(|pixel| *pixel != 0)
+                .collect(),
+        }
+    }
+}
+
+// The same as `Display::dump_to_stdout`.
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.pixels.chunks(self.width) {
+            let row: String = row
+                .iter()
+                .map(|pixel| if *pixel { '#' } else { ' ' })
+                .collect();
+            writeln!(f, "{}", row)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Draws the actual screen over the expected one, with `#` for pixels that are
+// on in both, `+` for pixels that are on but shouldn't be, and `-` for pixels
+// that are off but should be on.
+pub struct ScreenDiff<'a> {
+    actual: &'a Screen,
+    expected: &'a Screen,
+}
+
+impl ScreenDiff<'_> {
+    // How many pixels differ. Every pixel does when the resolutions differ.
+    pub fn count(&self) -> usize {
+        if self.actual.width != self.expected.width {
+            return self.actual.pixels.len();
+        }
+
+        self.actual
+            .pixels
+            .iter()
+            .zip(&self.expected.pixels)
+            .filter(|(actual, expected)| actual != expected)
+            .count()
+    }
+}
+
+impl fmt::Display for ScreenDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (actual, expected) = (self.actual, self.expected);
+
+        if actual.width != expected.width {
+            writeln!(
+                f,
+                "The screen is {}x{}, but {}x{} was expected:",
+                actual.width, actual.height, expected.width, expected.height
+            )?;
+            return write!(f, "{}", actual);
+        }
+
+        writeln!(f, "{} pixels differ:", self.count())?;
+
+        // A border shows where the rows end, since they end in spaces.
+        let border = format!("+{}+", "-".repeat(actual.width));
+        writeln!(f, "{}", border)?;
+
+        for y in 0..actual.height {
+            let row: String = (0..actual.width)
+                .map(|x| match (actual.get(x, y), expected.get(x, y)) {
+                    (true, true) => '#',
+                    (true, false) => '+',
+                    (false, true) => '-',
+                    (false, false) => ' ',
+                })
+                .collect();
+            writeln!(f, "|{}|", row)?;
+        }
+
+        writeln!(f, "{}", border)?;
+        writeln!(f, "+ is on but should be off, - is off but should be on")
+    }
+}
+
+// Reads the header and pixels of a PBM image, which can have comments
+// anywhere before the pixels.
+struct Pbm<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Pbm<'a> {
+    fn next_non_space(&mut self) -> Option<u8> {
+        loop {
+            let byte = *self.bytes.get(self.position)?;
+            self.position += 1;
+
+            match byte {
+                b'#' => {
+                    while self
+                        .bytes
+                        .get(self.position)
+                        .is_some_and(|byte| *byte != b'\n')
+                    {
+                        self.position += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => {}
+                byte => return Some(byte),
+            }
+        }
+    }
+
+    // The next whitespace separated token, leaving the position on the
+    // whitespace after it.
+    fn token(&mut self) -> Option<&'a [u8]> {
+        self.next_non_space()?;
+        let start = self.position - 1;
+
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+
+        Some(&self.bytes[start..self.position])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 64x32 screen of text with the given pixels on.
+    fn text(on: &[(usize, usize)]) -> String {
+        let mut rows = vec![vec![' '; 64]; 32];
+        for &(x, y) in on {
+            rows[y][x] = '#';
+        }
+
+        rows.iter()
+            .map(|row| row.iter().collect::<String>() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn presses_keys_on_the_frames_in_the_script() {
+        let script = Script::parse("# frame  keys\n\n60 5\n120-130  4 6\n").unwrap();
+
+        assert!(script.input(60).is_key_pressed(5));
+        assert!(!script.input(59).is_key_pressed(5));
+        assert!(!script.input(61).is_key_pressed(5));
+        for frame in [120, 125, 130] {
+            let input = script.input(frame);
+            assert!(input.is_key_pressed(4) && input.is_key_pressed(6));
+        }
+        assert!(!script.input(131).is_key_pressed(4));
+    }
+
+    #[test]
+    fn rejects_bad_scripts() {
+        for (text, message) in [
+            ("60", "no keys to press"),
+            ("x 5", "`x` is not a frame number"),
+            ("10-x 5", "`10-x` is not a frame number"),
+            ("60 10", "`10` is not a key from 0 to F"),
+            ("130-120 5", "`130-120` ends before it starts"),
+        ] {
+            let err = Script::parse(&format!("# keys\n{}", text)).unwrap_err();
+            assert_eq!((err.line, err.message.as_str()), (2, message), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_text_screens() {
+        // Rows can leave off their trailing spaces, and use `.` for off.
+        let screen = Screen::parse(text(&[(0, 0), (63, 31)]).as_bytes()).unwrap();
+        assert_eq!((screen.width(), screen.height()), (64, 32));
+        assert!(screen.get(0, 0) && screen.get(63, 31));
+        assert!(!screen.get(1, 0));
+
+        let short = "#.#\n".to_string() + &"\n".repeat(63);
+        let screen = Screen::parse(short.as_bytes()).unwrap();
+        assert_eq!((screen.width(), screen.height()), (128, 64));
+        assert!(screen.get(0, 0) && !screen.get(1, 0) && screen.get(2, 0));
+
+        assert!(Screen::parse(b"#\n").is_err());
+        assert!(Screen::parse(text(&[]).replace(' ', "x").as_bytes()).is_err());
+        assert!(Screen::parse(format!("{} \n", "#".repeat(64)).repeat(32).as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parses_pbm_images() {
+        let mut plain = "P1\n# a comment\n64 32\n".to_string();
+        for y in 0..32 {
+            for x in 0..64 {
+                plain.push(if (x, y) == (3, 1) { '1' } else { '0' });
+                plain.push(' ');
+            }
+            plain.push('\n');
+        }
+        let screen = Screen::parse(plain.as_bytes()).unwrap();
+        assert!(screen.get(3, 1));
+        assert_eq!(screen, Screen::parse(text(&[(3, 1)]).as_bytes()).unwrap());
+
+        let mut raw = b"P4\n64 32\n".to_vec();
+        let mut data = vec![0; 64 / 8 * 32];
+        data[64 / 8] = 0b0001_0000;
+        raw.extend(&data);
+        assert_eq!(Screen::parse(&raw).unwrap(), screen);
+
+        raw.pop();
+        assert!(Screen::parse(&raw).is_err());
+        assert!(Screen::parse(b"P1\n32 32\n").is_err());
+        assert!(Screen::parse(b"P1\n64 32\n0 0 2").is_err());
+    }
+
+    #[test]
+    fn shows_the_pixels_that_differ() {
+        let expected = Screen::parse(text(&[(0, 0), (1, 0)]).as_bytes()).unwrap();
+        let actual = Screen::parse(text(&[(1, 0), (2, 0)]).as_bytes()).unwrap();
+
+        assert!(actual.diff(&actual).is_none());
+        let diff = actual.diff(&expected).unwrap();
+        assert_eq!(diff.count(), 2);
+
+        let text = diff.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "2 pixels differ:");
+        assert_eq!(lines[2], format!("|-#+{}|", " ".repeat(61)));
+        assert_eq!(lines.len(), 1 + 32 + 2 + 1);
+
+        // Screens of different resolutions differ everywhere.
+        let hires = Screen::parse("\n".repeat(64).as_bytes()).unwrap();
+        let diff = hires.diff(&expected).unwrap();
+        assert_eq!(diff.count(), 128 * 64);
+        assert!(diff
+            .to_string()
+            .starts_with("The screen is 128x64, but 64x32 was expected:"));
+    }
+}
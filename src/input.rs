@@ -0,0 +1,88 @@
+// 2.3 - Keyboard
+// The computers which originally used the Chip-8 Language had a 16-key
+// hexadecimal keypad with the following layout:
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+
+// Input is the frontend-neutral state of that keypad. Frontends translate
+// their own key events into keypad values (0x0 - 0xF) and press them here,
+// the CPU only ever reads from it.
+
+use std::collections::HashSet;
+
+#[derive(Debug, Default)]
+pub struct Input {
+    active: HashSet<u8>,
+    last_key: Option<u8>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            active: HashSet::new(),
+            last_key: None,
+        }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        // The keypad only has 16 keys, anything else can't be pressed.
+        if key > 0xF {
+            return;
+        }
+
+        self.active.insert(key);
+        self.last_key = Some(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.active.contains(&key)
+    }
+
+    pub fn most_recent_key(&self) -> Option<&u8> {
+        match self.last_key {
+            Some(key) => self.active.get(&key),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presses_keypad_keys() {
+        let mut input = Input::new();
+        input.press(0x5);
+        input.press(0xA);
+
+        assert!(input.is_key_pressed(0x5) && input.is_key_pressed(0xA));
+        assert!(!input.is_key_pressed(0x0));
+        assert_eq!(input.most_recent_key(), Some(&0xA));
+    }
+
+    #[test]
+    fn ignores_keys_off_the_keypad() {
+        let mut input = Input::new();
+        input.press(0x10);
+
+        assert!(!input.is_key_pressed(0x10));
+        assert_eq!(input.most_recent_key(), None);
+    }
+
+    #[test]
+    fn forgets_released_keys() {
+        let mut input = Input::new();
+        input.press(0x3);
+        input.clear();
+
+        assert!(!input.is_key_pressed(0x3));
+        assert_eq!(input.most_recent_key(), None);
+    }
+}
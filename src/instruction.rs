@@ -0,0 +1,482 @@
+// Reference: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+
+use std::fmt;
+
+// 3.0 - Chip-8 Instructions
+// The original implementation of the Chip-8 language includes 36 different
+// instructions. In these listings, the following variables are used:
+//     nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
+//     n or nibble - A 4-bit value, the lowest 4 bits of the instruction
+//     x - A 4-bit value, the lower 4 bits of the high byte of the instruction
+//     y - A 4-bit value, the upper 4 bits of the low byte of the instruction
+//     kk or byte - An 8-bit value, the lowest 8 bits of the instruction
+//
+// SUPER-CHIP 1.1 adds 10 more, for its 128x64 high resolution mode, scrolling,
+// large sprites and fonts, and the HP-48's RPL user flags. XO-CHIP adds 6, for
+// its 64KB of memory, register ranges, bitplanes and audio patterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 0nnn - SYS addr
+    Sys(u16),
+    // 00Cn - SCD nibble (SUPER-CHIP)
+    Scd(u8),
+    // 00E0 - CLS
+    Cls,
+    // 00EE - RET
+    Ret,
+    // 00FB - SCR (SUPER-CHIP)
+    Scr,
+    // 00FC - SCL (SUPER-CHIP)
+    Scl,
+    // 00FD - EXIT (SUPER-CHIP)
+    Exit,
+    // 00FE - LOW (SUPER-CHIP)
+    Low,
+    // 00FF - HIGH (SUPER-CHIP)
+    High,
+    // 1nnn - JP addr
+    Jp(u16),
+    // 2nnn - CALL addr
+    Call(u16),
+    // 3xkk - SE Vx, byte
+    SeByte(u8, u8),
+    // 4xkk - SNE Vx, byte
+    SneByte(u8, u8),
+    // 5xy0 - SE Vx, Vy
+    SeReg(u8, u8),
+    // 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+    LdIRange(u8, u8),
+    // 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+    LdRangeI(u8, u8),
+    // 6xkk - LD Vx, byte
+    LdByte(u8, u8),
+    // 7xkk - ADD Vx, byte
+    AddByte(u8, u8),
+    // 8xy0 - LD Vx, Vy
+    LdReg(u8, u8),
+    // 8xy1 - OR Vx, Vy
+    Or(u8, u8),
+    // 8xy2 - AND Vx, Vy
+    And(u8, u8),
+    // 8xy3 - XOR Vx, Vy
+    Xor(u8, u8),
+    // 8xy4 - ADD Vx, Vy
+    AddReg(u8, u8),
+    // 8xy5 - SUB Vx, Vy
+    Sub(u8, u8),
+    // 8xy6 - SHR Vx {, Vy}
+    Shr(u8, u8),
+    // 8xy7 - SUBN Vx, Vy
+    Subn(u8, u8),
+    // 8xyE - SHL Vx {, Vy}
+    Shl(u8, u8),
+    // 9xy0 - SNE Vx, Vy
+    SneReg(u8, u8),
+    // Annn - LD I, addr
+    LdI(u16),
+    // Bnnn - JP V0, addr
+    JpV0(u16),
+    // Cxkk - RND Vx, byte
+    Rnd(u8, u8),
+    // Dxyn - DRW Vx, Vy, nibble
+    // Dxy0 - DRW Vx, Vy, 0 draws a 16x16 sprite on SUPER-CHIP
+    Drw(u8, u8, u8),
+    // Ex9E - SKP Vx
+    Skp(u8),
+    // ExA1 - SKNP Vx
+    Sknp(u8),
+    // F000 nnnn - LD I, LONG addr (XO-CHIP)
+    LdILong(u16),
+    // Fn01 - PLANE n (XO-CHIP)
+    Plane(u8),
+    // F002 - AUDIO (XO-CHIP)
+    Audio,
+    // Fx07 - LD Vx, DT
+    LdVxDt(u8),
+    // Fx0A - LD Vx, K
+    LdVxK(u8),
+    // Fx15 - LD DT, Vx
+    LdDtVx(u8),
+    // Fx18 - LD ST, Vx
+    LdStVx(u8),
+    // Fx1E - ADD I, Vx
+    AddI(u8),
+    // Fx29 - LD F, Vx
+    LdF(u8),
+    // Fx30 - LD HF, Vx (SUPER-CHIP)
+    LdHf(u8),
+    // Fx33 - LD B, Vx
+    LdB(u8),
+    // Fx3A - PITCH Vx (XO-CHIP)
+    Pitch(u8),
+    // Fx55 - LD [I], Vx
+    LdIVx(u8),
+    // Fx65 - LD Vx, [I]
+    LdVxI(u8),
+    // Fx75 - LD R, Vx (SUPER-CHIP)
+    LdRVx(u8),
+    // Fx85 - LD Vx, R (SUPER-CHIP)
+    LdVxR(u8),
+}
+
+// XO-CHIP's F000 nnnn is the only instruction that is 4 bytes long, with its
+// address in the second word. `next` is the word following the opcode, and is
+// only used by that instruction.
+pub fn decode_long(opcode: u16, next: u16) -> Result<Instruction, DecodeError> {
+    match opcode {
+        0xF000 => Ok(Instruction::LdILong(next)),
+        _ => decode(opcode),
+    }
+}
+
+// All other instructions are 2 bytes long and are stored most-significant-byte
+// first, so decoding works on the combined 16-bit opcode.
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let nnn = opcode & 0x0FFF;
+    let x = ((opcode >> 8) & 0xF) as u8;
+    let y = ((opcode >> 4) & 0xF) as u8;
+    let n = (opcode & 0xF) as u8;
+    let kk = (opcode & 0xFF) as u8;
+
+    let instruction = match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xC, _) => Instruction::Scd(n),
+        (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+        (0x0, 0x0, 0xF, 0xB) => Instruction::Scr,
+        (0x0, 0x0, 0xF, 0xC) => Instruction::Scl,
+        (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+        (0x0, 0x0, 0xF, 0xE) => Instruction::Low,
+        (0x0, 0x0, 0xF, 0xF) => Instruction::High,
+        (0x0, _, _, _) => Instruction::Sys(nnn),
+        (0x1, _, _, _) => Instruction::Jp(nnn),
+        (0x2, _, _, _) => Instruction::Call(nnn),
+        (0x3, _, _, _) => Instruction::SeByte(x, kk),
+        (0x4, _, _, _) => Instruction::SneByte(x, kk),
+        (0x5, _, _, 0x0) => Instruction::SeReg(x, y),
+        (0x5, _, _, 0x2) => Instruction::LdIRange(x, y),
+        (0x5, _, _, 0x3) => Instruction::LdRangeI(x, y),
+        (0x6, _, _, _) => Instruction::LdByte(x, kk),
+        (0x7, _, _, _) => Instruction::AddByte(x, kk),
+        (0x8, _, _, 0x0) => Instruction::LdReg(x, y),
+        (0x8, _, _, 0x1) => Instruction::Or(x, y),
+        (0x8, _, _, 0x2) => Instruction::And(x, y),
+        (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+        (0x8, _, _, 0x4) => Instruction::AddReg(x, y),
+        (0x8, _, _, 0x5) => Instruction::Sub(x, y),
+        (0x8, _, _, 0x6) => Instruction::Shr(x, y),
+        (0x8, _, _, 0x7) => Instruction::Subn(x, y),
+        (0x8, _, _, 0xE) => Instruction::Shl(x, y),
+        (0x9, _, _, 0x0) => Instruction::SneReg(x, y),
+        (0xA, _, _, _) => Instruction::LdI(nnn),
+        (0xB, _, _, _) => Instruction::JpV0(nnn),
+        (0xC, _, _, _) => Instruction::Rnd(x, kk),
+        (0xD, _, _, _) => Instruction::Drw(x, y, n),
+        (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+        (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+        (0xF, _, 0x0, 0x1) => Instruction::Plane(x),
+        (0xF, 0x0, 0x0, 0x2) => Instruction::Audio,
+        (0xF, _, 0x0, 0x7) => Instruction::LdVxDt(x),
+        (0xF, _, 0x0, 0xA) => Instruction::LdVxK(x),
+        (0xF, _, 0x1, 0x5) => Instruction::LdDtVx(x),
+        (0xF, _, 0x1, 0x8) => Instruction::LdStVx(x),
+        (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+        (0xF, _, 0x2, 0x9) => Instruction::LdF(x),
+        (0xF, _, 0x3, 0x0) => Instruction::LdHf(x),
+        (0xF, _, 0x3, 0x3) => Instruction::LdB(x),
+        (0xF, _, 0x3, 0xA) => Instruction::Pitch(x),
+        (0xF, _, 0x5, 0x5) => Instruction::LdIVx(x),
+        (0xF, _, 0x6, 0x5) => Instruction::LdVxI(x),
+        (0xF, _, 0x7, 0x5) => Instruction::LdRVx(x),
+        (0xF, _, 0x8, 0x5) => Instruction::LdVxR(x),
+        _ => return Err(DecodeError::UnrecognisedInstruction(opcode)),
+    };
+
+    Ok(instruction)
+}
+
+impl Instruction {
+    // Whether the instruction was added by XO-CHIP.
+    pub fn is_xochip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::LdIRange(..)
+                | Instruction::LdRangeI(..)
+                | Instruction::LdILong(_)
+                | Instruction::Plane(_)
+                | Instruction::Audio
+                | Instruction::Pitch(_)
+        )
+    }
+
+    // Whether the instruction was added by SUPER-CHIP.
+    pub fn is_schip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Scd(_)
+                | Instruction::Scr
+                | Instruction::Scl
+                | Instruction::Exit
+                | Instruction::Low
+                | Instruction::High
+                | Instruction::LdHf(_)
+                | Instruction::LdRVx(_)
+                | Instruction::LdVxR(_)
+        )
+    }
+
+    // The number of bytes the instruction takes up in memory.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // The bytes of the instruction as they are stored in memory.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+
+        if let Instruction::LdILong(addr) = self {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    // The inverse of decode, producing the 16-bit opcode for an instruction.
+    // For LD I, LONG this is only the first word, see `to_bytes`.
+    pub fn encode(&self) -> u16 {
+        let op = |high: u16, x: u8, y: u8, n: u8| {
+            (high << 12) | ((x as u16) << 8) | ((y as u16) << 4) | n as u16
+        };
+        let op_kk = |high: u16, x: u8, kk: u8| (high << 12) | ((x as u16) << 8) | kk as u16;
+
+        match *self {
+            Instruction::Sys(addr) => addr & 0x0FFF,
+            Instruction::Scd(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(addr) => 0x1000 | (addr & 0x0FFF),
+            Instruction::Call(addr) => 0x2000 | (addr & 0x0FFF),
+            Instruction::SeByte(x, kk) => op_kk(0x3, x, kk),
+            Instruction::SneByte(x, kk) => op_kk(0x4, x, kk),
+            Instruction::SeReg(x, y) => op(0x5, x, y, 0x0),
+            Instruction::LdIRange(x, y) => op(0x5, x, y, 0x2),
+            Instruction::LdRangeI(x, y) => op(0x5, x, y, 0x3),
+            Instruction::LdByte(x, kk) => op_kk(0x6, x, kk),
+            Instruction::AddByte(x, kk) => op_kk(0x7, x, kk),
+            Instruction::LdReg(x, y) => op(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => op(0x8, x, y, 0x1),
+            Instruction::And(x, y) => op(0x8, x, y, 0x2),
+            Instruction::Xor(x, y) => op(0x8, x, y, 0x3),
+            Instruction::AddReg(x, y) => op(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => op(0x8, x, y, 0x5),
+            Instruction::Shr(x, y) => op(0x8, x, y, 0x6),
+            Instruction::Subn(x, y) => op(0x8, x, y, 0x7),
+            Instruction::Shl(x, y) => op(0x8, x, y, 0xE),
+            Instruction::SneReg(x, y) => op(0x9, x, y, 0x0),
+            Instruction::LdI(addr) => 0xA000 | (addr & 0x0FFF),
+            Instruction::JpV0(addr) => 0xB000 | (addr & 0x0FFF),
+            Instruction::Rnd(x, kk) => op_kk(0xC, x, kk),
+            Instruction::Drw(x, y, n) => op(0xD, x, y, n),
+            Instruction::Skp(x) => op_kk(0xE, x, 0x9E),
+            Instruction::Sknp(x) => op_kk(0xE, x, 0xA1),
+            Instruction::LdILong(_) => 0xF000,
+            Instruction::Plane(n) => op_kk(0xF, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(x) => op_kk(0xF, x, 0x07),
+            Instruction::LdVxK(x) => op_kk(0xF, x, 0x0A),
+            Instruction::LdDtVx(x) => op_kk(0xF, x, 0x15),
+            Instruction::LdStVx(x) => op_kk(0xF, x, 0x18),
+            Instruction::AddI(x) => op_kk(0xF, x, 0x1E),
+            Instruction::LdF(x) => op_kk(0xF, x, 0x29),
+            Instruction::LdHf(x) => op_kk(0xF, x, 0x30),
+            Instruction::LdB(x) => op_kk(0xF, x, 0x33),
+            Instruction::Pitch(x) => op_kk(0xF, x, 0x3A),
+            Instruction::LdIVx(x) => op_kk(0xF, x, 0x55),
+            Instruction::LdVxI(x) => op_kk(0xF, x, 0x65),
+            Instruction::LdRVx(x) => op_kk(0xF, x, 0x75),
+            Instruction::LdVxR(x) => op_kk(0xF, x, 0x85),
+        }
+    }
+}
+
+// The mnemonics follow Cowgod's reference. They differ from the text the old
+// `--debug` output built by hand: addresses are hex with a 0x prefix where JP
+// and CALL used to print decimal, bytes are decimal where RND used to print
+// hex, register names are upper case, and SHR and SHL name the Vy register
+// the original interpreter shifts.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(addr) => write!(f, "SYS {:#05X}", addr),
+            Instruction::Scd(n) => write!(f, "SCD {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(addr) => write!(f, "JP {:#05X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL {:#05X}", addr),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, {}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, {}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdIRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LdRangeI(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, {}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, {}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(addr) => write!(f, "LD I, {:#05X}", addr),
+            Instruction::JpV0(addr) => write!(f, "JP V0, {:#05X}", addr),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, {}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong(addr) => write!(f, "LD I, LONG {:#06X}", addr),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnrecognisedInstruction(u16),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnrecognisedInstruction(opcode) => {
+                write!(f, "Unrecognised Instruction: {:04X}", opcode)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_instruction() {
+        let table = [
+            (0x0123, Instruction::Sys(0x123), "SYS 0x123"),
+            (0x00C3, Instruction::Scd(3), "SCD 3"),
+            (0x00E0, Instruction::Cls, "CLS"),
+            (0x00EE, Instruction::Ret, "RET"),
+            (0x00FB, Instruction::Scr, "SCR"),
+            (0x00FC, Instruction::Scl, "SCL"),
+            (0x00FD, Instruction::Exit, "EXIT"),
+            (0x00FE, Instruction::Low, "LOW"),
+            (0x00FF, Instruction::High, "HIGH"),
+            (0x12A4, Instruction::Jp(0x2A4), "JP 0x2A4"),
+            (0x2F00, Instruction::Call(0xF00), "CALL 0xF00"),
+            (0x3A10, Instruction::SeByte(0xA, 0x10), "SE VA, 16"),
+            (0x4BFF, Instruction::SneByte(0xB, 0xFF), "SNE VB, 255"),
+            (0x5120, Instruction::SeReg(0x1, 0x2), "SE V1, V2"),
+            (0x5122, Instruction::LdIRange(0x1, 0x2), "LD [I], V1-V2"),
+            (0x5213, Instruction::LdRangeI(0x2, 0x1), "LD V2-V1, [I]"),
+            (0x6307, Instruction::LdByte(0x3, 0x07), "LD V3, 7"),
+            (0x7401, Instruction::AddByte(0x4, 0x01), "ADD V4, 1"),
+            (0x8560, Instruction::LdReg(0x5, 0x6), "LD V5, V6"),
+            (0x8561, Instruction::Or(0x5, 0x6), "OR V5, V6"),
+            (0x8562, Instruction::And(0x5, 0x6), "AND V5, V6"),
+            (0x8563, Instruction::Xor(0x5, 0x6), "XOR V5, V6"),
+            (0x8564, Instruction::AddReg(0x5, 0x6), "ADD V5, V6"),
+            (0x8565, Instruction::Sub(0x5, 0x6), "SUB V5, V6"),
+            (0x8566, Instruction::Shr(0x5, 0x6), "SHR V5, V6"),
+            (0x8567, Instruction::Subn(0x5, 0x6), "SUBN V5, V6"),
+            (0x856E, Instruction::Shl(0x5, 0x6), "SHL V5, V6"),
+            (0x9780, Instruction::SneReg(0x7, 0x8), "SNE V7, V8"),
+            (0xA3A0, Instruction::LdI(0x3A0), "LD I, 0x3A0"),
+            (0xB204, Instruction::JpV0(0x204), "JP V0, 0x204"),
+            (0xC90F, Instruction::Rnd(0x9, 0x0F), "RND V9, 15"),
+            (0xD125, Instruction::Drw(0x1, 0x2, 0x5), "DRW V1, V2, 5"),
+            (0xEC9E, Instruction::Skp(0xC), "SKP VC"),
+            (0xECA1, Instruction::Sknp(0xC), "SKNP VC"),
+            (0xF301, Instruction::Plane(0x3), "PLANE 3"),
+            (0xF002, Instruction::Audio, "AUDIO"),
+            (0xF207, Instruction::LdVxDt(0x2), "LD V2, DT"),
+            (0xF20A, Instruction::LdVxK(0x2), "LD V2, K"),
+            (0xF215, Instruction::LdDtVx(0x2), "LD DT, V2"),
+            (0xF218, Instruction::LdStVx(0x2), "LD ST, V2"),
+            (0xF21E, Instruction::AddI(0x2), "ADD I, V2"),
+            (0xF229, Instruction::LdF(0x2), "LD F, V2"),
+            (0xF230, Instruction::LdHf(0x2), "LD HF, V2"),
+            (0xF233, Instruction::LdB(0x2), "LD B, V2"),
+            (0xF23A, Instruction::Pitch(0x2), "PITCH V2"),
+            (0xFF55, Instruction::LdIVx(0xF), "LD [I], VF"),
+            (0xFF65, Instruction::LdVxI(0xF), "LD VF, [I]"),
+            (0xF775, Instruction::LdRVx(0x7), "LD R, V7"),
+            (0xF785, Instruction::LdVxR(0x7), "LD V7, R"),
+        ];
+
+        for (opcode, instruction, text) in table {
+            assert_eq!(decode(opcode), Ok(instruction), "{:04X}", opcode);
+            assert_eq!(instruction.encode(), opcode, "{}", text);
+            assert_eq!(instruction.to_string(), text, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_opcodes() {
+        for opcode in [0x5121, 0x8568, 0x856F, 0x9781, 0xE19F, 0xF100, 0xF1FF] {
+            assert_eq!(
+                decode(opcode),
+                Err(DecodeError::UnrecognisedInstruction(opcode))
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_long_loads() {
+        let instruction = decode_long(0xF000, 0x1234).unwrap();
+
+        assert_eq!(instruction, Instruction::LdILong(0x1234));
+        assert_eq!(instruction.size(), 4);
+        assert_eq!(instruction.to_bytes(), [0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(instruction.to_string(), "LD I, LONG 0x1234");
+
+        assert_eq!(decode_long(0x00E0, 0xF000), Ok(Instruction::Cls));
+        assert_eq!(Instruction::Cls.size(), 2);
+    }
+}
@@ -13,68 +13,73 @@
 //     A S D F
 //     Z X C V
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 
 use sdl2::keyboard::Keycode;
 
+use crate::input::Input;
+
 #[derive(Debug)]
 pub struct KeyMap {
-    active: HashSet<u8>,
-    last_key: Option<u8>,
+    keys: HashMap<Keycode, u8>,
 }
 
 impl KeyMap {
     pub fn new() -> Self {
-        Self {
-            active: HashSet::new(),
-            last_key: None,
-        }
-    }
+        let keys = HashMap::from([
+            (Keycode::Num1, 0x1),
+            (Keycode::Num2, 0x2),
+            (Keycode::Num3, 0x3),
+            (Keycode::Num4, 0xC),
+            (Keycode::Q, 0x4),
+            (Keycode::W, 0x5),
+            (Keycode::E, 0x6),
+            (Keycode::R, 0xD),
+            (Keycode::A, 0x7),
+            (Keycode::S, 0x8),
+            (Keycode::D, 0x9),
+            (Keycode::F, 0xE),
+            (Keycode::Z, 0xA),
+            (Keycode::X, 0x0),
+            (Keycode::C, 0xB),
+            (Keycode::V, 0xF),
+        ]);
 
-    pub fn add_key(&mut self, keycode: Keycode) {
-        match Self::to_chip8_key(keycode) {
-            Some(key) => {
-                self.active.insert(key);
-                self.last_key = Some(key);
-            }
-            None => {}
-        };
+        Self { keys }
     }
 
-    pub fn clear(&mut self) {
-        self.active.clear();
+    pub fn add_key(&self, input: &mut Input, keycode: Keycode) {
+        if let Some(key) = self.to_chip8_key(keycode) {
+            input.press(key);
+        }
     }
 
-    pub fn is_key_pressed(&self, key: u8) -> bool {
-        self.active.contains(&key)
-    }
+    // Maps the arrow keys, space and return to a game's controls, as the
+    // database names them. The keypad layout above still works too.
+    pub fn set_game_keys(&mut self, keys: &BTreeMap<String, u8>) {
+        let controls = [
+            ("up", Keycode::Up),
+            ("down", Keycode::Down),
+            ("left", Keycode::Left),
+            ("right", Keycode::Right),
+            ("a", Keycode::Space),
+            ("b", Keycode::Return),
+        ];
 
-    pub fn most_recent_key(&self) -> Option<&u8> {
-        match self.last_key {
-            Some(key) => self.active.get(&key),
-            None => None,
+        for (name, keycode) in controls {
+            if let Some(key) = keys.get(name) {
+                self.keys.insert(keycode, *key);
+            }
         }
     }
 
-    fn to_chip8_key(keycode: Keycode) -> Option<u8> {
-        match keycode {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
-            Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
-            _ => return None,
-        }
+    pub fn to_chip8_key(&self, keycode: Keycode) -> Option<u8> {
+        self.keys.get(&keycode).copied()
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::new()
     }
 }
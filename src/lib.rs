@@ -1,6 +1,27 @@
+pub mod archive;
+pub mod asm;
+#[cfg(feature = "sdl")]
 pub mod audio;
+pub mod cartridge;
 pub mod chip8;
 pub mod cli;
+pub mod database;
+pub mod debugger;
+pub mod disasm;
 pub mod display;
+pub mod gdb;
+pub mod gif;
+pub mod headless;
+pub mod input;
+pub mod instruction;
+#[cfg(feature = "sdl")]
 pub mod keymap;
+pub mod octo;
+pub mod quirks;
+#[cfg(feature = "sdl")]
 pub mod render;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod state;
+pub mod trace;
@@ -1,25 +1,344 @@
 use clap::Parser;
-use sdl2::{audio::AudioSpecDesired, event::Event, keyboard::Keycode};
-use std::{thread, time::Duration};
-
-use flake_8::{
-    audio::SquareWave,
-    chip8::{Chip8, Error},
-    cli::Cli,
-    keymap::KeyMap,
-    render::Renderer,
+use std::io::{self, IsTerminal};
+use std::{fs, path::Path, process};
+
+use crab_8::{
+    asm,
+    chip8::Chip8,
+    chip8::{ETI_660_START_INDEX, NORMAL_START_INDEX},
+    cli::{Cli, Command, Machine},
+    database::{Database, Settings},
+    disasm,
+    headless::{self, Screen, Script},
+    octo,
+    rom::{self, Rom},
+    trace::{self, Trace},
 };
 
 fn main() {
+    let args = Cli::parse();
+
+    match args.command {
+        Some(Command::Disasm { ref path, eti_mode }) => disassemble(path, eti_mode),
+        Some(Command::Asm {
+            ref path,
+            ref output,
+            eti_mode,
+        }) => assemble(path, output.as_deref(), eti_mode),
+        Some(Command::Octo {
+            ref path,
+            ref output,
+        }) => compile_octo(path, output.as_deref()),
+        Some(Command::TraceDiff {
+            ref a,
+            ref b,
+            context,
+        }) => trace_diff(a, b, context),
+        Some(Command::Test {
+            ref path,
+            frames,
+            ref input,
+            ref expect,
+            machine,
+        }) => test(path, frames, input.as_deref(), expect.as_deref(), machine),
+        None => run(args),
+    }
+}
+
+fn origin(eti_mode: bool) -> u16 {
+    if eti_mode {
+        ETI_660_START_INDEX as u16
+    } else {
+        NORMAL_START_INDEX as u16
+    }
+}
+
+// Reads a file, or standard input when the path is `-`.
+fn read_file(path: &str) -> Vec<u8> {
+    rom::read_file(path).unwrap_or_else(|err| {
+        eprintln!("Could not open {}: {}", path, err);
+        process::exit(1);
+    })
+}
+
+fn disassemble(path: &str, eti_mode: bool) {
+    let rom = read_rom(path);
+
+    print!("{}", disasm::disassemble(&rom.bytes, origin(eti_mode)));
+}
+
+fn read_source(path: &str) -> String {
+    String::from_utf8(read_file(path)).unwrap_or_else(|_| {
+        eprintln!("Could not read {}: it isn't UTF-8 text", path);
+        process::exit(1);
+    })
+}
+
+fn report(path: &str, result: Result<Vec<u8>, asm::Error>) -> Vec<u8> {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}:{}: {}", path, err.line, err.message);
+        process::exit(1);
+    })
+}
+
+fn assemble(path: &str, output: Option<&str>, eti_mode: bool) {
+    let source = read_source(path);
+    let bytes = report(path, asm::assemble(&source, origin(eti_mode)));
+
+    write_rom(path, output, &bytes);
+}
+
+fn compile_octo(path: &str, output: Option<&str>) {
+    let source = read_source(path);
+    let bytes = report(path, octo::compile(&source));
+
+    write_rom(path, output, &bytes);
+}
+
+fn read_trace(path: &str) -> Trace {
+    Trace::parse(path, &read_source(path)).unwrap_or_else(|err| {
+        eprintln!("{}:{}: {}", path, err.line, err.message);
+        process::exit(2);
+    })
+}
+
+// Exits with 1 when the traces differ, like diff.
+fn trace_diff(a: &str, b: &str, context: usize) {
+    let (a, b) = (read_trace(a), read_trace(b));
+
+    match trace::diff(&a, &b, context) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!(
+            "The traces match, instruction by instruction, for {} instructions",
+            a.steps.len()
+        ),
+    }
+}
+
+// Reads a rom to run, compiling it first if it is Octo source or an Octo
+// cartridge, or taking it out of a zip or gzip archive. When a zip archive holds several roms, asks
+// which one to run, unless the archive came from standard input.
+fn read_rom(path: &str) -> Rom {
+    let interactive = path != "-" && io::stdin().is_terminal();
+    let choose = |names: &[String]| interactive.then(|| choose_rom(path, names));
+
+    Rom::read_choosing(path, choose).unwrap_or_else(|err| {
+        match err {
+            rom::Error::Compile(err) => eprintln!("{}:{}: {}", path, err.line, err.message),
+            err => eprintln!("Could not open {}: {}", path, err),
+        }
+        process::exit(1);
+    })
+}
+
+fn choose_rom(path: &str, names: &[String]) -> String {
+    eprintln!("{} has {} roms:", path, names.len());
+    for (index, name) in names.iter().enumerate() {
+        eprintln!("{:>4}  {}", index + 1, name);
+    }
+
+    loop {
+        eprint!("Which one? ");
+
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            process::exit(1);
+        }
+        let line = line.trim();
+
+        let chosen = match line.parse::<usize>() {
+            Ok(number) => names.get(number.wrapping_sub(1)),
+            Err(_) => names.iter().find(|name| *name == line),
+        };
+        if let Some(name) = chosen {
+            return name.clone();
+        }
+    }
+}
+
+fn write_rom(path: &str, output: Option<&str>, bytes: &[u8]) {
+    let output = match output {
+        Some(output) => output.to_string(),
+        None if path == "-" => {
+            eprintln!("Give an output path with --output when reading from standard input");
+            process::exit(1);
+        }
+        None => Path::new(path)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    if let Err(err) = fs::write(&output, bytes) {
+        eprintln!("Could not write {}: {}", output, err);
+        process::exit(1);
+    }
+
+    eprintln!("bytes written: {}", bytes.len());
+}
+
+// Looks the rom up in the database, if there is one, and fills in the
+// settings that weren't given on the command line from its entry.
+fn look_up(rom: &[u8], machine: &mut Machine) -> Option<Settings> {
+    let directory = machine.database.as_deref()?;
+    let database = Database::load(directory)
+        .map_err(|err| eprintln!("Could not load the database: {}", err))
+        .ok()?;
+
+    let settings = database.lookup(rom)?;
+    eprintln!("Found {} in the database", settings.title);
+    machine.apply(&settings);
+
+    Some(settings)
+}
+
+// Loads a rom into a new machine set up the way `machine` asks, along with
+// the rom's settings from its cartridge or the database.
+fn load(path: &str, rom: &Rom, machine: &mut Machine) -> (Chip8, Option<Settings>) {
+    let settings = match &rom.settings {
+        Some(settings) => {
+            machine.apply(settings);
+            Some(settings.clone())
+        }
+        None => look_up(&rom.bytes, machine),
+    };
+    machine.check().unwrap_or_else(|err| err.exit());
     let mut cpu = Chip8::new();
 
-    let args = Cli::parse();
+    // The platform goes first, since it says how large a rom can be.
+    cpu.set_platform(machine.platform.unwrap_or_default());
+    cpu.set_quirks(machine.quirks());
+    if let Err(err) = cpu.load_rom_bytes(&rom.bytes, machine.load_options()) {
+        eprintln!("Could not load {}: {}", path, err);
+        process::exit(1);
+    }
+    eprintln!("bytes loaded: {}", rom.bytes.len());
+    cpu.set_rng(machine.rng());
+    cpu.set_instructions_per_frame(machine.instructions_per_frame());
+
+    (cpu, settings)
+}
+
+fn test(path: &str, frames: u32, input: Option<&str>, expect: Option<&str>, mut machine: Machine) {
+    let script = match input {
+        Some(input) => Script::parse(&read_source(input)).unwrap_or_else(|err| {
+            eprintln!("{}:{}: {}", input, err.line, err.message);
+            process::exit(2);
+        }),
+        None => Script::default(),
+    };
+    let expected = expect.map(|expect| {
+        let bytes = fs::read(expect).unwrap_or_else(|err| {
+            eprintln!("Could not open {}: {}", expect, err);
+            process::exit(2);
+        });
+        Screen::parse(&bytes).unwrap_or_else(|err| {
+            eprintln!("{}: {}", expect, err);
+            process::exit(2);
+        })
+    });
+
+    let (mut cpu, _) = load(path, &read_rom(path), &mut machine);
+
+    if let Err(err) = headless::run(&mut cpu, frames, &script) {
+        eprintln!("Error: {}", err);
+        cpu.dump_cpu_to_stdout();
+        process::exit(1);
+    }
+
+    let screen = Screen::from(&cpu.display);
+    match expected {
+        Some(expected) => {
+            if let Some(diff) = screen.diff(&expected) {
+                println!("The screen doesn't match {}.", expect.unwrap());
+                print!("{}", diff);
+                process::exit(1);
+            }
+            println!("The screen matches {}", expect.unwrap());
+        }
+        None => print!("{}", screen),
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_args: Cli) {
+    eprintln!("crab-8 was built without the `sdl` feature, so it can't open a window to run roms.");
+    process::exit(1);
+}
 
-    cpu.load_rom(&args.path, args.eti_mode);
+#[cfg(feature = "sdl")]
+fn run(mut args: Cli) {
+    use sdl2::{
+        audio::AudioSpecDesired,
+        event::Event,
+        keyboard::{Keycode, Mod},
+    };
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crab_8::{
+        audio::SquareWave,
+        debugger::{Action, Debugger},
+        gdb::{GdbStub, Status},
+        input::Input,
+        keymap::KeyMap,
+        render::Renderer,
+        rewind::Rewind,
+        trace::Tracer,
+    };
+
+    // The loop below runs one frame at a time. A whole save state is too much
+    // to record every frame, so rewind snapshots are taken every few frames,
+    // and rewinding restores one every few frames to match.
+    const FRAMES_PER_SECOND: u32 = 60;
+    const SNAPSHOTS_PER_SECOND: u32 = 10;
+    const FRAMES_PER_SNAPSHOT: u32 = FRAMES_PER_SECOND / SNAPSHOTS_PER_SECOND;
+    let frame_time = Duration::new(0, 1_000_000_000u32 / FRAMES_PER_SECOND);
+
+    let path = args
+        .path
+        .as_deref()
+        .expect("clap requires a path when no command is given");
+    let rom = read_rom(path);
+    let (mut cpu, settings) = load(path, &rom, &mut args.machine);
+    let settings = settings.unwrap_or_default();
     cpu.set_debug_output(args.debug);
 
+    if let Some(trace) = args.trace.as_deref() {
+        match Tracer::create(trace, args.trace_memory) {
+            Ok(tracer) => cpu.set_tracer(tracer),
+            Err(err) => {
+                eprintln!("Could not create {}: {}", trace, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Wait for GDB before opening the window, which would otherwise sit there
+    // unresponsive until it connects.
+    let mut gdb = args.gdb.map(|port| {
+        eprintln!("Waiting for GDB to connect on 127.0.0.1:{}", port);
+        GdbStub::listen(port).unwrap_or_else(|err| {
+            eprintln!("Could not listen on port {}: {}", port, err);
+            process::exit(1);
+        })
+    });
+
     let mut renderer = Renderer::new(64, 32, 16);
-    renderer.set_colors(args.fg, args.bg);
+    // Colors given on the command line win over the cartridge's or the
+    // database's.
+    let [bg, fg, fg2, blend] = settings.colors;
+    renderer.set_colors([
+        args.bg.clone().or(bg),
+        args.fg.clone().or(fg),
+        args.fg2.clone().or(fg2),
+        args.blend.clone().or(blend),
+    ]);
 
     let desired_audio_spec = AudioSpecDesired {
         freq: Some(44100),
@@ -27,16 +346,44 @@ fn main() {
         samples: None,
     };
 
-    let device = renderer
+    let mut device = renderer
         .audio_subsystem
         .open_playback(None, &desired_audio_spec, |spec| SquareWave {
             phase_inc: 440.0 / spec.freq as f32,
             phase: 0.0,
             volume: 0.25,
+            pattern: None,
+            pattern_inc: 0.0,
+            pattern_phase: 0.0,
+            freq: spec.freq as f32,
         })
         .unwrap();
 
     let mut keymap = KeyMap::new();
+    keymap.set_game_keys(&settings.keys);
+    let mut input = Input::new();
+
+    let mut rewind = Rewind::new(
+        (args.rewind_seconds * SNAPSHOTS_PER_SECOND) as usize,
+        args.rewind_memory * 1024 * 1024,
+    );
+    let mut rewinding = false;
+    let mut frames: u32 = 0;
+
+    // The debugger takes over whenever the program is paused.
+    let mut debugger = args.debugger.then(|| {
+        Debugger::new(disasm::disassemble(
+            &rom.bytes,
+            args.machine.load_options().address as u16,
+        ))
+    });
+    let mut paused = debugger.is_some();
+
+    // Frames only redraw when the display changes, so draw the blank screen
+    // once to start with.
+    renderer.render(&cpu.display);
+
+    let mut next_frame = Instant::now();
 
     'running: loop {
         for event in renderer.event_pump.poll_iter() {
@@ -48,39 +395,209 @@ fn main() {
                 } => {
                     break 'running;
                 }
+                // Backspace runs the emulation backwards while it is held.
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = true;
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => {
+                    rewinding = false;
+                }
+                // F12 pauses into the debugger.
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } if debugger.is_some() => {
+                    paused = true;
+                }
+                // F1-F10 save to a slot, and with shift held load from it.
+                Event::KeyDown {
+                    keycode: Some(key),
+                    keymod,
+                    ..
+                } if state_slot(key).is_some() => {
+                    let slot = state_slot(key).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        load_state(&mut cpu, path, slot);
+                        // The history leads up to the state being replaced.
+                        rewind.clear();
+                    } else {
+                        save_state(&cpu, path, slot);
+                    }
+                }
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
-                    keymap.add_key(key);
+                    keymap.add_key(&mut input, key);
                 }
                 _ => {}
             }
         }
 
-        match cpu.step(&keymap) {
-            Ok(_) => {}
-            Err(err) => match err {
-                Error::UnrecognisedInstruction(high, low) => {
-                    eprintln!("Unrecognised Instruction: {:02X} {:02X}", high, low);
+        if let (true, Some(debugger)) = (paused, debugger.as_mut()) {
+            device.pause();
+
+            match debugger.prompt(&mut cpu, &input) {
+                Action::Pause => {}
+                Action::Continue => paused = false,
+                Action::Quit => break 'running,
+            }
+
+            // Stepping may have drawn, and the frames missed while waiting
+            // for a command shouldn't be made up for.
+            renderer.render(&cpu.display);
+            next_frame = Instant::now();
+            continue;
+        }
+
+        if let Some(stub) = gdb.as_mut() {
+            match stub.poll(&mut cpu, &input) {
+                Ok(Status::Killed) => break 'running,
+                Ok(Status::Detached) => {
+                    eprintln!("GDB detached");
+                    gdb = None;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("Lost the connection to GDB: {}", err);
+                    gdb = None;
+                }
+            }
+        }
+        let stopped = gdb.as_ref().is_some_and(|stub| stub.is_stopped());
+
+        let snapshot = frames.is_multiple_of(FRAMES_PER_SNAPSHOT);
+        frames = frames.wrapping_add(1);
+
+        let (redraw, sound_on) = if stopped {
+            // GDB may have stepped, or written to memory.
+            (true, false)
+        } else if rewinding {
+            if snapshot {
+                if let Err(err) = rewind.step_back(&mut cpu) {
+                    eprintln!("Could not rewind: {}", err);
+                }
+            }
+            (snapshot, false)
+        } else {
+            if snapshot {
+                rewind.record(&cpu);
+            }
+
+            let frame = cpu.run_frame(&input);
+
+            if let Some(stub) = gdb.as_mut() {
+                if let Err(err) = stub.report(&cpu, &frame) {
+                    eprintln!("Lost the connection to GDB: {}", err);
+                    gdb = None;
+                }
+            }
+
+            if let Some(err) = frame.error {
+                eprintln!("Error: {}", err);
+
+                // With a debugger, stop to look at what went wrong.
+                if debugger.is_none() && gdb.is_none() {
                     break 'running;
                 }
-            },
+                paused = true;
+            }
+            if let Some(addr) = frame.breakpoint {
+                println!("Breakpoint at {:04X}", addr);
+                paused = true;
+            }
+            if let Some(hit) = frame.watch {
+                println!("Watchpoint: {}", hit);
+                paused = true;
+            }
+
+            (frame.display_changed, frame.sound_on)
+        };
+
+        if cpu.exited() {
+            break 'running;
         }
 
-        if cpu.sound_on() {
+        if sound_on {
+            device.lock().set_pattern(cpu.audio_pattern(), cpu.pitch());
             device.resume();
         } else {
             device.pause();
         }
 
-        renderer.render(&cpu.display);
+        if redraw {
+            renderer.render(&cpu.display);
+        }
+
+        input.clear();
 
-        keymap.clear();
+        // Sleep until the next frame is due. A frame that ran late doesn't
+        // make the following ones run early to catch up.
+        next_frame += frame_time;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
 
-        thread::sleep(Duration::new(0, 1_000_000_000u32 / 120));
+    if let Some(Err(err)) = cpu.tracer_mut().map(|tracer| tracer.flush()) {
+        eprintln!("Could not write the trace: {}", err);
     }
 
     if args.debug {
         cpu.dump_to_stdout();
     }
 }
+
+#[cfg(feature = "sdl")]
+fn state_slot(key: sdl2::keyboard::Keycode) -> Option<usize> {
+    use sdl2::keyboard::Keycode;
+
+    let keys = [
+        Keycode::F1,
+        Keycode::F2,
+        Keycode::F3,
+        Keycode::F4,
+        Keycode::F5,
+        Keycode::F6,
+        Keycode::F7,
+        Keycode::F8,
+        Keycode::F9,
+        Keycode::F10,
+    ];
+
+    keys.iter()
+        .position(|slot| *slot == key)
+        .map(|index| index + 1)
+}
+
+#[cfg(feature = "sdl")]
+fn save_state(cpu: &crab_8::chip8::Chip8, rom: &str, slot: usize) {
+    let path = crab_8::state::path(rom, slot);
+
+    match fs::write(&path, cpu.save_state()) {
+        Ok(_) => eprintln!("saved state to {}", path),
+        Err(err) => eprintln!("Could not write {}: {}", path, err),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn load_state(cpu: &mut crab_8::chip8::Chip8, rom: &str, slot: usize) {
+    let path = crab_8::state::path(rom, slot);
+
+    let result = fs::read(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| cpu.load_state(&bytes).map_err(|err| err.to_string()));
+
+    match result {
+        Ok(_) => eprintln!("loaded state from {}", path),
+        Err(err) => eprintln!("Could not load {}: {}", path, err),
+    }
+}
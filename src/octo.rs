@@ -0,0 +1,1250 @@
+// Compiler for Octo assembly language (.8o) sources.
+// Reference: https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+//
+// Octo is a higher level assembly language than the mnemonic syntax in asm.rs,
+// with statements such as `v0 += 1`, `if v1 == 3 then jump done` and
+// `loop ... again`. Programs start at 0x200 with a jump to the `main` label.
+//
+// Supported: labels, `:alias`, `:const`, `:calc`, `:macro`, `:next`, `:org`,
+// `:byte`, `:call`, `:unpack`, `loop`/`while`/`again`, `if ... then` and
+// `if ... begin/else/end`, the `<`, `>`, `<=` and `>=` pseudo comparisons
+// (which clobber vF, as they do in Octo) and every Chip-8, SUPER-CHIP and
+// XO-CHIP statement.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::asm::Error;
+use crate::chip8::MEMORY_SIZE;
+use crate::instruction::Instruction;
+
+const ORIGIN: usize = 0x200;
+
+// How deeply macros can expand inside each other, which stops a macro that
+// uses itself from expanding forever.
+const MAX_MACRO_DEPTH: usize = 64;
+
+// How deeply calc expressions can nest inside parentheses and unary
+// operators.
+const MAX_CALC_DEPTH: usize = 64;
+
+pub fn compile(source: &str) -> Result<Vec<u8>, Error> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source),
+        line: 1,
+        depth: 0,
+        rom: vec![0; MEMORY_SIZE],
+        here: ORIGIN,
+        end: ORIGIN,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        macro_calls: 0,
+        fixups: vec![],
+        flow: vec![],
+    };
+
+    // Every program begins with a jump to main, which is resolved like any
+    // other forward reference.
+    compiler.emit_reference(Instruction::Jp(0), "main".to_string())?;
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+
+    compiler.finish()
+}
+
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    // How many macro expansions the token came from.
+    depth: usize,
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let text = match text.find('#') {
+            Some(start) => &text[..start],
+            None => text,
+        };
+
+        for word in text.split_whitespace() {
+            // Braces and parentheses don't need to be separated by
+            // whitespace, so `{HERE+2}` is read the same as `{ HERE + 2 }`.
+            let mut current = String::new();
+            for c in word.chars() {
+                if "{}()".contains(c) {
+                    if !current.is_empty() {
+                        tokens.push_back(Token {
+                            text: current.clone(),
+                            line: index + 1,
+                            depth: 0,
+                        });
+                        current.clear();
+                    }
+                    tokens.push_back(Token {
+                        text: c.to_string(),
+                        line: index + 1,
+                        depth: 0,
+                    });
+                } else {
+                    current.push(c);
+                }
+            }
+
+            if !current.is_empty() {
+                tokens.push_back(Token {
+                    text: current,
+                    line: index + 1,
+                    depth: 0,
+                });
+            }
+        }
+    }
+
+    tokens
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A reference to a label that hadn't been defined when it was used, which is
+// patched into the code at `addr` once the label is known.
+struct Fixup {
+    addr: usize,
+    label: String,
+    line: usize,
+    kind: Reference,
+}
+
+enum Reference {
+    // The low 12 bits of the instruction.
+    Address,
+    // The pair of `v0 :=` and `v1 :=` instructions emitted by `:unpack`.
+    Unpack,
+    // The whole second word of `i := long`.
+    Long,
+}
+
+enum Flow {
+    // The start of the loop, and the jumps out of it emitted by `while`.
+    Loop(usize, Vec<usize>),
+    // The jump emitted by `begin` or `else`, to be patched at the next `else`
+    // or `end`.
+    If(usize),
+    Else(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    depth: usize,
+    rom: Vec<u8>,
+    here: usize,
+    end: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    macro_calls: usize,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+}
+
+impl Compiler {
+    fn error(&self, message: String) -> Error {
+        Error {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn next(&mut self) -> Result<String, Error> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                self.depth = token.depth;
+                Ok(token.text)
+            }
+            None => Err(self.error("unexpected end of file".to_string())),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Error> {
+        let token = self.next()?;
+
+        if token != expected {
+            return Err(self.error(format!("expected `{}` but found `{}`", expected, token)));
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), Error> {
+        let token = self.next()?;
+
+        if let Some(register) = self.register(&token) {
+            return self.register_statement(register);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(name, self.here)
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                match self.register(&token) {
+                    Some(register) => {
+                        self.aliases.insert(name, register);
+                        Ok(())
+                    }
+                    None => Err(self.error(format!("`{}` is not a register", token))),
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":next" => {
+                // The label points at the second byte of the next
+                // instruction, for self modifying code.
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)
+            }
+            ":org" => {
+                let addr = self.value()?;
+                if !(0.0..MEMORY_SIZE as f64).contains(&addr) {
+                    return Err(self.error(format!("{} is outside of memory", addr)));
+                }
+                self.here = addr as usize;
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_byte(value)
+            }
+            ":call" => self.address_statement(Instruction::Call(0)),
+            ":unpack" => {
+                // Loads a nibble and a 12-bit address into v0 and v1.
+                let nibble = self.value()? as u16;
+                let token = self.next()?;
+
+                if token != "{" && self.lookup(&token).is_none() && is_identifier(&token) {
+                    self.fixups.push(Fixup {
+                        addr: self.here,
+                        label: token,
+                        line: self.line,
+                        kind: Reference::Unpack,
+                    });
+                    self.emit(Instruction::LdByte(0, (nibble << 4) as u8))?;
+                    return self.emit(Instruction::LdByte(1, 0));
+                }
+
+                self.tokens.push_front(Token {
+                    text: token,
+                    line: self.line,
+                    depth: self.depth,
+                });
+                let addr = self.address()?;
+                self.emit(Instruction::LdByte(0, ((nibble << 4) | (addr >> 8)) as u8))?;
+                self.emit(Instruction::LdByte(1, addr as u8))
+            }
+            // Debugger hints, which have no effect on the program.
+            ":breakpoint" | ":proto" => self.name().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            "return" | ";" => self.emit(Instruction::Ret),
+            "clear" => self.emit(Instruction::Cls),
+            "hires" => self.emit(Instruction::High),
+            "lores" => self.emit(Instruction::Low),
+            "exit" => self.emit(Instruction::Exit),
+            "scroll-down" => {
+                let n = self.value()?;
+                if !(0.0..16.0).contains(&n) {
+                    return Err(
+                        self.error(format!("scroll distance {} doesn't fit in a nibble", n))
+                    );
+                }
+                self.emit(Instruction::Scd(n as u8))
+            }
+            "scroll-left" => self.emit(Instruction::Scl),
+            "scroll-right" => self.emit(Instruction::Scr),
+            "saveflags" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::LdRVx(x))
+            }
+            "loadflags" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::LdVxR(x))
+            }
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::LdB(x))
+            }
+            "save" => {
+                let x = self.expect_register()?;
+                match self.range_end()? {
+                    Some(y) => self.emit(Instruction::LdIRange(x, y)),
+                    None => self.emit(Instruction::LdIVx(x)),
+                }
+            }
+            "load" => {
+                let x = self.expect_register()?;
+                match self.range_end()? {
+                    Some(y) => self.emit(Instruction::LdRangeI(x, y)),
+                    None => self.emit(Instruction::LdVxI(x)),
+                }
+            }
+            "plane" => {
+                let n = self.value()?;
+                if !(0.0..4.0).contains(&n) {
+                    return Err(self.error(format!("plane {} must be from 0 to 3", n)));
+                }
+                self.emit(Instruction::Plane(n as u8))
+            }
+            "audio" => self.emit(Instruction::Audio),
+            "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                self.emit(Instruction::Pitch(x))
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.value()?;
+                if !(0.0..16.0).contains(&n) {
+                    return Err(self.error(format!("sprite height {} doesn't fit in a nibble", n)));
+                }
+                self.emit(Instruction::Drw(x, y, n as u8))
+            }
+            "jump" => self.address_statement(Instruction::Jp(0)),
+            "jump0" => self.address_statement(Instruction::JpV0(0)),
+            "native" => self.address_statement(Instruction::Sys(0)),
+            "delay" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                self.emit(Instruction::LdDtVx(x))
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                self.emit(Instruction::LdStVx(x))
+            }
+            "i" => self.i_statement(),
+            "loop" => {
+                self.flow.push(Flow::Loop(self.here, vec![]));
+                Ok(())
+            }
+            "while" => {
+                self.conditional(true)?;
+                let exit = self.here;
+                self.emit(Instruction::Jp(0))?;
+                for flow in self.flow.iter_mut().rev() {
+                    if let Flow::Loop(_, exits) = flow {
+                        exits.push(exit);
+                        return Ok(());
+                    }
+                }
+                Err(self.error("`while` must be inside a `loop`".to_string()))
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop(start, exits)) => {
+                    self.emit(Instruction::Jp(self.short_address(start)?))?;
+                    for exit in exits {
+                        self.patch(exit, self.here)?;
+                    }
+                    Ok(())
+                }
+                _ => Err(self.error("`again` without a matching `loop`".to_string())),
+            },
+            "if" => self.if_statement(),
+            "else" => match self.flow.pop() {
+                Some(Flow::If(jump)) => {
+                    let skip = self.here;
+                    self.emit(Instruction::Jp(0))?;
+                    self.patch(jump, self.here)?;
+                    self.flow.push(Flow::Else(skip));
+                    Ok(())
+                }
+                _ => Err(self.error("`else` without a matching `begin`".to_string())),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::If(jump)) | Some(Flow::Else(jump)) => self.patch(jump, self.here),
+                _ => Err(self.error("`end` without a matching `begin`".to_string())),
+            },
+            _ => {
+                if let Some(value) = parse_number(&token) {
+                    return self.emit_byte(self.to_byte(value)?);
+                }
+
+                if let Some(value) = self.constants.get(&token) {
+                    let value = *value;
+                    return self.emit_byte(self.to_byte(value)?);
+                }
+
+                if self.macros.contains_key(&token) {
+                    return self.expand_macro(&token);
+                }
+
+                if is_identifier(&token) {
+                    // A bare name is a call to a subroutine, which may not
+                    // have been defined yet.
+                    return match self.labels.get(&token) {
+                        Some(addr) => {
+                            let addr = self.short_address(*addr as usize)?;
+                            self.emit(Instruction::Call(addr))
+                        }
+                        None => self.emit_reference(Instruction::Call(0), token),
+                    };
+                }
+
+                Err(self.error(format!("`{}` is not a statement", token)))
+            }
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), Error> {
+        let op = self.next()?;
+
+        let instruction = match op.as_str() {
+            ":=" => {
+                let token = self.next()?;
+                match token.as_str() {
+                    "random" => Instruction::Rnd(x, self.byte()?),
+                    "delay" => Instruction::LdVxDt(x),
+                    "key" => Instruction::LdVxK(x),
+                    _ => match self.operand(token)? {
+                        Operand::Register(y) => Instruction::LdReg(x, y),
+                        Operand::Value(kk) => Instruction::LdByte(x, kk),
+                    },
+                }
+            }
+            "+=" => {
+                let token = self.next()?;
+                match self.operand(token)? {
+                    Operand::Register(y) => Instruction::AddReg(x, y),
+                    Operand::Value(kk) => Instruction::AddByte(x, kk),
+                }
+            }
+            "-=" => {
+                let token = self.next()?;
+                match self.operand(token)? {
+                    Operand::Register(y) => Instruction::Sub(x, y),
+                    Operand::Value(kk) => Instruction::AddByte(x, kk.wrapping_neg()),
+                }
+            }
+            "=-" => Instruction::Subn(x, self.expect_register()?),
+            "|=" => Instruction::Or(x, self.expect_register()?),
+            "&=" => Instruction::And(x, self.expect_register()?),
+            "^=" => Instruction::Xor(x, self.expect_register()?),
+            ">>=" => Instruction::Shr(x, self.expect_register()?),
+            "<<=" => Instruction::Shl(x, self.expect_register()?),
+            _ => return Err(self.error(format!("`{}` is not an operator for a register", op))),
+        };
+
+        self.emit(instruction)
+    }
+
+    fn i_statement(&mut self) -> Result<(), Error> {
+        let op = self.next()?;
+
+        match op.as_str() {
+            ":=" => {
+                if self.peek() == Some("hex") {
+                    self.next()?;
+                    let x = self.expect_register()?;
+                    return self.emit(Instruction::LdF(x));
+                }
+
+                if self.peek() == Some("long") {
+                    self.next()?;
+                    return self.address_statement(Instruction::LdILong(0));
+                }
+
+                if self.peek() == Some("bighex") {
+                    self.next()?;
+                    let x = self.expect_register()?;
+                    return self.emit(Instruction::LdHf(x));
+                }
+
+                self.address_statement(Instruction::LdI(0))
+            }
+            "+=" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::AddI(x))
+            }
+            _ => Err(self.error(format!("`{}` is not an operator for i", op))),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<(), Error> {
+        // Peek past the condition for `then` or `begin`, which decides which
+        // way round the skip instruction has to be.
+        match self.if_form() {
+            Some("then") => {
+                self.conditional(false)?;
+                self.expect("then")
+            }
+            Some("begin") => {
+                self.conditional(true)?;
+                self.expect("begin")?;
+                self.flow.push(Flow::If(self.here));
+                self.emit(Instruction::Jp(0))
+            }
+            _ => Err(self.error("`if` must be followed by `then` or `begin`".to_string())),
+        }
+    }
+
+    // The token after the condition of an `if`: a register, a comparison and
+    // then an operand, unless the comparison is `key` or `-key`. The operand
+    // can be a `{ calc expression }` of any length.
+    fn if_form(&self) -> Option<&str> {
+        let mut tokens = self.tokens.iter().map(|token| token.text.as_str());
+
+        tokens.next()?;
+        let op = tokens.next()?;
+        if op != "key" && op != "-key" && tokens.next()? == "{" {
+            let mut depth = 1;
+            while depth > 0 {
+                match tokens.next()? {
+                    "{" => depth += 1,
+                    "}" => depth -= 1,
+                    _ => {}
+                }
+            }
+        }
+
+        tokens.next()
+    }
+
+    // Emits a skip for a condition. With `skip_when` false, the following
+    // instruction only runs when the condition holds (`then`). With it true
+    // the following instruction is skipped when the condition holds, which is
+    // what `begin` and `while` need to jump around their blocks.
+    fn conditional(&mut self, skip_when: bool) -> Result<(), Error> {
+        let x = self.expect_register()?;
+        let op = self.next()?;
+
+        let comparison = match op.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            _ => return Err(self.error(format!("`{}` is not a comparison", op))),
+        };
+
+        let (x, comparison, operand) = match comparison {
+            Comparison::Key | Comparison::NotKey => (x, comparison, Operand::Value(0)),
+            Comparison::Equal | Comparison::NotEqual => {
+                let token = self.next()?;
+                (x, comparison, self.operand(token)?)
+            }
+            _ => {
+                // vF := y ; vF =- vx sets vF when vx >= y, and
+                // vF := y ; vF -= vx sets vF when vx <= y.
+                let token = self.next()?;
+                match self.operand(token)? {
+                    Operand::Register(y) => self.emit(Instruction::LdReg(0xF, y))?,
+                    Operand::Value(kk) => self.emit(Instruction::LdByte(0xF, kk))?,
+                }
+
+                let (subtract, flag) = match comparison {
+                    Comparison::GreaterEqual => (Instruction::Subn(0xF, x), 1),
+                    Comparison::Less => (Instruction::Subn(0xF, x), 0),
+                    Comparison::LessEqual => (Instruction::Sub(0xF, x), 1),
+                    _ => (Instruction::Sub(0xF, x), 0),
+                };
+                self.emit(subtract)?;
+
+                (0xF, Comparison::Equal, Operand::Value(flag))
+            }
+        };
+
+        // Skip instructions skip when their test passes. For `then` we want
+        // to skip when the condition fails, so the test is inverted.
+        let skip_on_equal = (comparison == Comparison::Equal) == skip_when;
+        let skip_on_key = (comparison == Comparison::Key) == skip_when;
+
+        let instruction = match (comparison, operand) {
+            (Comparison::Key | Comparison::NotKey, _) if skip_on_key => Instruction::Skp(x),
+            (Comparison::Key | Comparison::NotKey, _) => Instruction::Sknp(x),
+            (_, Operand::Register(y)) if skip_on_equal => Instruction::SeReg(x, y),
+            (_, Operand::Register(y)) => Instruction::SneReg(x, y),
+            (_, Operand::Value(kk)) if skip_on_equal => Instruction::SeByte(x, kk),
+            (_, Operand::Value(kk)) => Instruction::SneByte(x, kk),
+        };
+
+        self.emit(instruction)
+    }
+
+    fn define_macro(&mut self) -> Result<(), Error> {
+        let name = self.name()?;
+        let mut args = vec![];
+
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+
+        let mut body = vec![];
+        let mut depth = 1;
+
+        loop {
+            let text = self.next()?;
+
+            match text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+
+            body.push(Token {
+                text,
+                line: self.line,
+                depth: 0,
+            });
+        }
+
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), Error> {
+        let count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+
+        for index in 0..count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[index].clone(), value);
+        }
+
+        // CALLS counts how many times any macro has been expanded, which
+        // lets macros generate unique labels.
+        values.insert("CALLS".to_string(), self.macro_calls.to_string());
+        self.macro_calls += 1;
+
+        let (line, depth) = (self.line, self.depth + 1);
+        if depth > MAX_MACRO_DEPTH {
+            return Err(self.error(format!(
+                "`{}` expands more than {} macros deep, it may use itself",
+                name, MAX_MACRO_DEPTH
+            )));
+        }
+
+        for token in self.macros[name].body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text).clone();
+            self.tokens.push_front(Token { text, line, depth });
+        }
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), Error> {
+        if self.labels.contains_key(&name) {
+            return Err(self.error(format!("`{}` is defined more than once", name)));
+        }
+
+        self.labels.insert(name, addr as u16);
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let name = self.next()?;
+
+        if !is_identifier(&name) || self.register(&name).is_some() {
+            return Err(self.error(format!("`{}` can't be used as a name", name)));
+        }
+
+        Ok(name)
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        let mut chars = token.chars();
+
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|digit| digit as u8),
+            _ => self.aliases.get(token).copied(),
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u8, Error> {
+        let token = self.next()?;
+
+        match self.register(&token) {
+            Some(register) => Ok(register),
+            None => Err(self.error(format!("expected a register but found `{}`", token))),
+        }
+    }
+
+    // The `- vy` of XO-CHIP's `save vx - vy` and `load vx - vy`.
+    fn range_end(&mut self) -> Result<Option<u8>, Error> {
+        if self.peek() != Some("-") {
+            return Ok(None);
+        }
+
+        self.next()?;
+        self.expect_register().map(Some)
+    }
+
+    fn operand(&mut self, token: String) -> Result<Operand, Error> {
+        match self.register(&token) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => {
+                self.tokens.push_front(Token {
+                    text: token,
+                    line: self.line,
+                    depth: self.depth,
+                });
+                Ok(Operand::Value(self.byte()?))
+            }
+        }
+    }
+
+    // A number, constant, known label or `{ calc expression }`.
+    fn value(&mut self) -> Result<f64, Error> {
+        let token = self.next()?;
+
+        if token == "{" {
+            return self.calc();
+        }
+
+        self.lookup(&token)
+            .ok_or_else(|| self.error(format!("`{}` is not defined", token)))
+    }
+
+    fn lookup(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|addr| *addr as f64))
+    }
+
+    fn to_byte(&self, value: f64) -> Result<u8, Error> {
+        if !(-128.0..256.0).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in a byte", value)));
+        }
+
+        Ok(value as i64 as u8)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        let value = self.value()?;
+        self.to_byte(value)
+    }
+
+    fn address(&mut self) -> Result<u16, Error> {
+        let value = self.value()?;
+
+        if !(0.0..4096.0).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in a 12-bit address", value)));
+        }
+
+        Ok(value as u16)
+    }
+
+    fn long_address(&mut self) -> Result<u16, Error> {
+        let value = self.value()?;
+
+        if !(0.0..MEMORY_SIZE as f64).contains(&value) {
+            return Err(self.error(format!("{} doesn't fit in a 16-bit address", value)));
+        }
+
+        Ok(value as u16)
+    }
+
+    // Emits an instruction taking an address, which may be a label that is
+    // defined further down the file.
+    fn address_statement(&mut self, instruction: Instruction) -> Result<(), Error> {
+        let token = self.next()?;
+
+        if token != "{" && self.lookup(&token).is_none() && is_identifier(&token) {
+            return self.emit_reference(instruction, token);
+        }
+
+        self.tokens.push_front(Token {
+            text: token,
+            line: self.line,
+            depth: self.depth,
+        });
+        let addr = match instruction {
+            Instruction::LdILong(_) => self.long_address()?,
+            _ => self.address()?,
+        };
+
+        self.emit(with_address(instruction, addr))
+    }
+
+    fn emit_reference(&mut self, instruction: Instruction, label: String) -> Result<(), Error> {
+        self.fixups.push(Fixup {
+            addr: self.here,
+            label,
+            line: self.line,
+            kind: match instruction {
+                Instruction::LdILong(_) => Reference::Long,
+                _ => Reference::Address,
+            },
+        });
+
+        self.emit(instruction)
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), Error> {
+        for byte in instruction.to_bytes() {
+            self.emit_byte(byte)?;
+        }
+
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), Error> {
+        if self.here >= self.rom.len() {
+            return Err(self.error("the program is too big for memory".to_string()));
+        }
+
+        // The rom only holds what is compiled from 0x200 on.
+        if self.here < ORIGIN {
+            return Err(self.error(format!(
+                "{:#X} is below {:#X}, where the program starts",
+                self.here, ORIGIN
+            )));
+        }
+
+        self.rom[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+
+        Ok(())
+    }
+
+    fn patch(&mut self, addr: usize, target: usize) -> Result<(), Error> {
+        let target = self.short_address(target)?;
+        self.rom[addr] = (self.rom[addr] & 0xF0) | (target >> 8) as u8;
+        self.rom[addr + 1] = target as u8;
+
+        Ok(())
+    }
+
+    // Jumps, calls and `i :=` only have room for 12 bits of address, which
+    // XO-CHIP programs can outgrow with `:org`.
+    fn short_address(&self, addr: usize) -> Result<u16, Error> {
+        if addr > 0xFFF {
+            return Err(self.error(format!("{:#X} doesn't fit in a 12-bit address", addr)));
+        }
+
+        Ok(addr as u16)
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, Error> {
+        if let Some(flow) = self.flow.last() {
+            let message = match flow {
+                Flow::Loop(..) => "`loop` without a matching `again`",
+                _ => "`begin` without a matching `end`",
+            };
+            return Err(self.error(message.to_string()));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            match self.labels.get(&fixup.label) {
+                Some(addr) => {
+                    // Errors are reported where the label was used.
+                    self.line = fixup.line;
+
+                    match fixup.kind {
+                        Reference::Address => self.patch(fixup.addr, *addr as usize)?,
+                        Reference::Unpack => {
+                            let addr = self.short_address(*addr as usize)?;
+                            self.rom[fixup.addr + 1] |= (addr >> 8) as u8;
+                            self.rom[fixup.addr + 3] = addr as u8;
+                        }
+                        Reference::Long => {
+                            let [high, low] = addr.to_be_bytes();
+                            self.rom[fixup.addr + 2] = high;
+                            self.rom[fixup.addr + 3] = low;
+                        }
+                    }
+                }
+                None if fixup.label == "main" => {
+                    return Err(Error {
+                        line: 1,
+                        message: "the program is missing a `: main` label".to_string(),
+                    });
+                }
+                None => {
+                    return Err(Error {
+                        line: fixup.line,
+                        message: format!("`{}` is not defined", fixup.label),
+                    });
+                }
+            }
+        }
+
+        Ok(self.rom[ORIGIN..self.end].to_vec())
+    }
+
+    //
+    // Calc expressions
+    //
+    // Octo evaluates `{ ... }` expressions right to left with no operator
+    // precedence, so `{ 1 + 2 * 3 }` is 7 and `{ 2 * 3 + 1 }` is 8.
+    // Parentheses group as usual.
+    fn calc(&mut self) -> Result<f64, Error> {
+        let value = self.calc_expression(0)?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self, depth: usize) -> Result<f64, Error> {
+        let mut terms = vec![self.calc_term(depth)?];
+        let mut ops = vec![];
+
+        loop {
+            match self.peek() {
+                Some("}") | Some(")") | None => break,
+                Some(op) => ops.push(op.to_string()),
+            }
+
+            self.next()?;
+            terms.push(self.calc_term(depth)?);
+        }
+
+        // Fold from the right, as Octo does.
+        let mut right = terms.pop().unwrap_or_default();
+        while let (Some(op), Some(left)) = (ops.pop(), terms.pop()) {
+            right = self.calc_operator(&op, left, right)?;
+        }
+
+        Ok(right)
+    }
+
+    fn calc_operator(&self, op: &str, left: f64, right: f64) -> Result<f64, Error> {
+        let value = match op {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (left as i64 & right as i64) as f64,
+            "|" => (left as i64 | right as i64) as f64,
+            "^" => (left as i64 ^ right as i64) as f64,
+            "<<" => ((left as i64) << right as i64) as f64,
+            ">>" => ((left as i64) >> right as i64) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(self.error(format!("`{}` is not a calc operator", op))),
+        };
+
+        Ok(value)
+    }
+
+    fn calc_term(&mut self, depth: usize) -> Result<f64, Error> {
+        let token = self.next()?;
+
+        if depth >= MAX_CALC_DEPTH {
+            return Err(self.error(format!(
+                "calc expression nests more than {} deep",
+                MAX_CALC_DEPTH
+            )));
+        }
+
+        let unary = |value: f64| -> Option<f64> {
+            match token.as_str() {
+                "-" => Some(-value),
+                "~" => Some(!(value as i64) as f64),
+                "!" => Some((value == 0.0) as i64 as f64),
+                "sin" => Some(value.sin()),
+                "cos" => Some(value.cos()),
+                "tan" => Some(value.tan()),
+                "exp" => Some(value.exp()),
+                "log" => Some(value.ln()),
+                "abs" => Some(value.abs()),
+                "sqrt" => Some(value.sqrt()),
+                "sign" => Some(value.signum()),
+                "ceil" => Some(value.ceil()),
+                "floor" => Some(value.floor()),
+                _ => None,
+            }
+        };
+
+        if unary(0.0).is_some() {
+            let value = self.calc_term(depth + 1)?;
+            return Ok(unary(value).unwrap_or(value));
+        }
+
+        match token.as_str() {
+            "(" => {
+                let value = self.calc_expression(depth + 1)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            // The byte already compiled at an address.
+            "@" => {
+                let addr = self.calc_term(depth + 1)? as usize;
+                Ok(*self.rom.get(addr).unwrap_or(&0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => self
+                .lookup(&token)
+                .ok_or_else(|| self.error(format!("`{}` is not defined", token))),
+        }
+    }
+}
+
+fn with_address(instruction: Instruction, addr: u16) -> Instruction {
+    match instruction {
+        Instruction::Jp(_) => Instruction::Jp(addr),
+        Instruction::JpV0(_) => Instruction::JpV0(addr),
+        Instruction::Call(_) => Instruction::Call(addr),
+        Instruction::LdI(_) => Instruction::LdI(addr),
+        Instruction::LdILong(_) => Instruction::LdILong(addr),
+        Instruction::Sys(_) => Instruction::Sys(addr),
+        _ => instruction,
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        }
+        _ => false,
+    }
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> Error {
+        compile(source).expect_err("the source shouldn't compile")
+    }
+
+    #[test]
+    fn compiles_loops() {
+        let source = "
+            : main
+              loop
+                v0 += 1
+                while v0 != 5
+              again
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            [0x12, 0x02, 0x70, 0x01, 0x40, 0x05, 0x12, 0x0A, 0x12, 0x02]
+        );
+    }
+
+    #[test]
+    fn compiles_ifs() {
+        let source = "
+            : main
+              if v0 == 1 begin
+                v1 := 2
+              else
+                v1 := 3
+              end
+              if v2 key then v3 := 4
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            [
+                0x12, 0x02, 0x30, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x12, 0x0C, 0x61, 0x03, 0xE2, 0xA1,
+                0x63, 0x04
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_then_after_long_conditions() {
+        let terms = vec!["1"; 40].join(" + ");
+        let source = format!(": main if v0 == {{ {} - 39 }} then v1 := 1", terms);
+
+        assert_eq!(
+            compile(&source).unwrap(),
+            [0x12, 0x02, 0x40, 0x01, 0x61, 0x01]
+        );
+    }
+
+    #[test]
+    fn compiles_macros_calc_and_next() {
+        let source = "
+            :macro set reg value { reg := value }
+            :calc SIX { 2 * 3 }
+            : main
+              set v1 SIX
+              :next target v2 := 0
+              i := target
+              :byte { 1 + 2 * 3 }
+              :byte { 2 * 3 + 1 }
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            [0x12, 0x02, 0x61, 0x06, 0x62, 0x00, 0xA2, 0x05, 7, 8]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        let err = error(": main\n  v0 := 1\n  jump nowhere");
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "`nowhere` is not defined");
+
+        let err = error(": main\n  v0 := 300");
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "300 doesn't fit in a byte");
+
+        let err = error("clear");
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "the program is missing a `: main` label");
+
+        let err = error(": main\n  loop\n  v0 += 1");
+        assert_eq!(err.message, "`loop` without a matching `again`");
+    }
+
+    #[test]
+    fn reports_targets_past_12_bits() {
+        let err = error(": main\n  jump far\n:org 0x1000\n: far\n  clear");
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "0x1000 doesn't fit in a 12-bit address");
+
+        let err = error(": main\n:org 0x1000\n  loop\n  again");
+        assert_eq!(err.line, 4);
+
+        let err = error(":org 0x1000\n: far\n  return\n:org 0x202\n: main\n  far");
+        assert_eq!(err.line, 6);
+
+        let err = error(": main\n:org 0xFFE\n  if v0 == 1 begin\n  clear\n  end");
+        assert_eq!(err.line, 5);
+    }
+
+    #[test]
+    fn reports_code_below_the_program() {
+        let err = error(": main\n  clear\n:org 0x100\n  return");
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.message,
+            "0x100 is below 0x200, where the program starts"
+        );
+    }
+
+    #[test]
+    fn unpacks_labels_before_and_after_use() {
+        let source = "
+            : main
+              :unpack 0xA data
+              :unpack 0xB main
+            : data
+        ";
+
+        assert_eq!(
+            compile(source).unwrap(),
+            [0x12, 0x02, 0x60, 0xA2, 0x61, 0x0A, 0x60, 0xB2, 0x61, 0x02]
+        );
+    }
+
+    #[test]
+    fn evaluates_long_calc_expressions() {
+        let terms = vec!["1"; 100_000].join(" + ");
+        let source = format!(
+            ":calc MANY {{ {} }}\n: main v0 := {{ MANY - 99990 }}",
+            terms
+        );
+
+        assert_eq!(compile(&source).unwrap(), [0x12, 0x02, 0x60, 0x0A]);
+
+        let nested = format!("{}1{}", "( ".repeat(100), " )".repeat(100));
+        let err = error(&format!(": main v0 := {{ {} }}", nested));
+        assert_eq!(err.message, "calc expression nests more than 64 deep");
+    }
+
+    #[test]
+    fn reports_macros_that_use_themselves() {
+        let err = error(":macro forever { forever }\n: main\n  forever");
+        assert_eq!(err.line, 3);
+        assert!(err.message.starts_with("`forever` expands more than"));
+    }
+}
@@ -0,0 +1,225 @@
+// Reference: https://github.com/Timendus/chip8-test-suite#quirks-test
+//
+// The Chip-8 interpreters that followed the original COSMAC VIP one didn't
+// agree on how a handful of instructions behave, and roms were written against
+// whichever interpreter their author had. Each of those disagreements is a
+// quirk that can be switched on or off, and each platform is a set of quirks.
+
+use clap::ValueEnum;
+
+use crate::chip8::{CLASSIC_MEMORY_SIZE, MEMORY_SIZE};
+use crate::instruction::Instruction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy1, 8xy2 and 8xy3 reset VF to 0.
+    pub vf_reset: bool,
+
+    // Fx55 and Fx65 increment I by x + 1, leaving it just past the last
+    // register stored or loaded. Otherwise I is left unchanged.
+    pub memory: bool,
+
+    // With the memory quirk, Fx55 and Fx65 increment I by x rather than
+    // x + 1, as CHIP-48 did.
+    pub memory_by_x: bool,
+
+    // Dxyn waits for the next 60Hz display refresh before drawing, so at most
+    // one sprite is drawn per frame.
+    pub display_wait: bool,
+
+    // Sprites are clipped at the edges of the screen instead of wrapping
+    // around to the opposite side. Either way, the starting coordinates wrap.
+    pub clipping: bool,
+
+    // 8xy6 and 8xyE shift Vx in place and ignore Vy. Otherwise Vy is shifted
+    // and the result stored in Vx.
+    pub shifting: bool,
+
+    // Bnnn jumps to nnn plus Vx, where x is the highest nibble of nnn, rather
+    // than nnn plus V0.
+    pub jumping: bool,
+}
+
+impl Quirks {
+    // Sets a single quirk by name, as used by `--quirk name=value`.
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        match name {
+            "vf_reset" | "vf-reset" => self.vf_reset = value,
+            "memory" => self.memory = value,
+            "memory_by_x" | "memory-by-x" => self.memory_by_x = value,
+            "display_wait" | "display-wait" => self.display_wait = value,
+            "clipping" => self.clipping = value,
+            "shifting" => self.shifting = value,
+            "jumping" => self.jumping = value,
+            _ => {
+                return Err(format!(
+                    "unknown quirk `{}`, expected one of vf-reset, memory, memory-by-x, display-wait, clipping, shifting or jumping",
+                    name
+                ))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// NOTE: The default is how crab-8 behaved before quirks could be chosen,
+//       which isn't quite any of the platforms: sprites wrap, VF is left
+//       alone by the logic ops, shifts ignore Vy and Fx55/Fx65 leave I
+//       unchanged.
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            vf_reset: false,
+            memory: false,
+            memory_by_x: false,
+            display_wait: false,
+            clipping: false,
+            shifting: true,
+            jumping: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Platform {
+    // The original COSMAC VIP interpreter.
+    #[default]
+    Vip,
+    // CHIP-48 on the HP-48 calculators.
+    Chip48,
+    // SUPER-CHIP 1.1, as most SCHIP games expect it.
+    Schip,
+    // Octo's XO-CHIP.
+    Xochip,
+}
+
+impl Platform {
+    // Whether programs for the platform can use an instruction. SUPER-CHIP
+    // added instructions to the original set, and XO-CHIP added more to
+    // SUPER-CHIP's.
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        if instruction.is_xochip() {
+            return *self == Platform::Xochip;
+        }
+
+        self.has_schip() || !instruction.is_schip()
+    }
+
+    // How many bytes of memory programs can address.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Xochip => MEMORY_SIZE,
+            _ => CLASSIC_MEMORY_SIZE,
+        }
+    }
+
+    pub fn has_schip(&self) -> bool {
+        matches!(self, Platform::Schip | Platform::Xochip)
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks {
+                vf_reset: true,
+                memory: true,
+                memory_by_x: false,
+                display_wait: true,
+                clipping: true,
+                shifting: false,
+                jumping: false,
+            },
+            Platform::Chip48 => Quirks {
+                memory: true,
+                memory_by_x: true,
+                ..Platform::Schip.quirks()
+            },
+            Platform::Schip => Quirks {
+                vf_reset: false,
+                memory: false,
+                memory_by_x: false,
+                display_wait: false,
+                clipping: true,
+                shifting: true,
+                jumping: true,
+            },
+            Platform::Xochip => Quirks {
+                vf_reset: false,
+                memory: true,
+                memory_by_x: false,
+                display_wait: false,
+                clipping: false,
+                shifting: false,
+                jumping: false,
+            },
+        }
+    }
+}
+
+// Parses the value of a `--quirk` argument, e.g. `shifting`, `clipping=off`.
+pub fn parse_override(text: &str) -> Result<(String, bool), String> {
+    let (name, value) = match text.split_once('=') {
+        Some((name, value)) => (name, value),
+        None => (text, "on"),
+    };
+
+    let value = match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => true,
+        "off" | "false" | "no" | "0" => false,
+        _ => return Err(format!("`{}` should be on or off", value)),
+    };
+
+    // Check the name here, so that clap reports a bad name as a usage error.
+    Quirks::default().set(name, value)?;
+
+    Ok((name.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_match_the_quirks_test() {
+        let vip = Platform::Vip.quirks();
+        assert!(vip.vf_reset && vip.memory && vip.display_wait && vip.clipping);
+        assert!(!vip.memory_by_x && !vip.shifting && !vip.jumping);
+
+        let chip48 = Platform::Chip48.quirks();
+        assert!(chip48.memory && chip48.memory_by_x);
+        assert!(chip48.clipping && chip48.shifting && chip48.jumping);
+        assert!(!chip48.vf_reset && !chip48.display_wait);
+
+        let schip = Platform::Schip.quirks();
+        assert!(schip.clipping && schip.shifting && schip.jumping);
+        assert!(!schip.vf_reset && !schip.memory && !schip.display_wait);
+
+        let xochip = Platform::Xochip.quirks();
+        assert!(xochip.memory);
+        assert!(!xochip.vf_reset && !xochip.display_wait && !xochip.clipping);
+        assert!(!xochip.shifting && !xochip.jumping);
+    }
+
+    #[test]
+    fn defaults_to_the_old_behaviour() {
+        let quirks = Quirks::default();
+
+        assert!(quirks.shifting);
+        assert!(!quirks.vf_reset && !quirks.memory && !quirks.clipping);
+        assert!(!quirks.display_wait && !quirks.jumping);
+    }
+
+    #[test]
+    fn parses_overrides() {
+        assert_eq!(
+            parse_override("shifting"),
+            Ok(("shifting".to_string(), true))
+        );
+        assert_eq!(
+            parse_override("memory-by-x=off"),
+            Ok(("memory-by-x".to_string(), false))
+        );
+        assert!(parse_override("clipping=maybe").is_err());
+        assert!(parse_override("wobble=on").is_err());
+    }
+}
@@ -8,8 +8,9 @@ pub struct Renderer {
     width: u32,
     cell_size: u32,
     pub audio_subsystem: AudioSubsystem,
-    fg: Color,
-    bg: Color,
+    // Indexed by the planes a pixel is on in, so the background, the
+    // foreground, then XO-CHIP's second plane and both planes together.
+    palette: [Color; 4],
 }
 
 impl Renderer {
@@ -36,30 +37,42 @@ impl Renderer {
             width,
             cell_size,
             audio_subsystem,
-            fg: Color::RGB(255, 255, 255),
-            bg: Color::RGB(0, 0, 0),
+            palette: [
+                Color::RGB(0, 0, 0),
+                Color::RGB(255, 255, 255),
+                Color::RGB(170, 170, 170),
+                Color::RGB(85, 85, 85),
+            ],
         }
     }
 
     pub fn render(&mut self, display: &Display) {
-        self.canvas.set_draw_color(self.bg);
+        self.canvas.set_draw_color(self.palette[0]);
         self.canvas.clear();
 
-        self.canvas.set_draw_color(self.fg);
-        for (i, pixel) in display.memory.iter().enumerate() {
-            if !pixel {
+        // The window keeps its size when a SUPER-CHIP program switches
+        // resolution, so the pixels are scaled to fit.
+        let width = display.width() as u32;
+        let cell_size = self.width * self.cell_size / width;
+        let pixels = display.width() * display.height();
+
+        for (i, pixel) in display.memory[..pixels].iter().enumerate() {
+            if *pixel == 0 {
                 continue;
             }
 
-            let x = i as u32 % self.width;
-            let y = i as u32 / self.width;
+            self.canvas
+                .set_draw_color(self.palette[*pixel as usize & 0b11]);
+
+            let x = i as u32 % width;
+            let y = i as u32 / width;
 
             self.canvas
                 .fill_rect(Rect::new(
-                    (x * self.cell_size) as i32,
-                    (y * self.cell_size) as i32,
-                    self.cell_size,
-                    self.cell_size,
+                    (x * cell_size) as i32,
+                    (y * cell_size) as i32,
+                    cell_size,
+                    cell_size,
                 ))
                 .unwrap();
         }
@@ -67,15 +80,13 @@ impl Renderer {
         self.canvas.present();
     }
 
-    pub fn set_colors(&mut self, fg: Option<String>, bg: Option<String>) {
-        match fg {
-            Some(hex_string) => self.fg = Self::to_color(hex_string),
-            None => {}
-        };
-
-        match bg {
-            Some(hex_string) => self.bg = Self::to_color(hex_string),
-            None => {}
+    // Colors are given in palette order: background, foreground, second
+    // plane and both planes.
+    pub fn set_colors(&mut self, colors: [Option<String>; 4]) {
+        for (index, color) in colors.into_iter().enumerate() {
+            if let Some(hex_string) = color {
+                self.palette[index] = Self::to_color(hex_string);
+            }
         }
     }
 
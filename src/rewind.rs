@@ -0,0 +1,313 @@
+// Rewinding.
+//
+// The frontend records a snapshot of the machine as it runs, and while the
+// rewind key is held it steps back through them instead of running forwards.
+//
+// Snapshots are save states, but keeping a whole one per step would use a lot
+// of memory, mostly on copies of `ram` that hardly changes. Only the newest
+// snapshot is kept whole. Each older one is kept as the difference between it
+// and the snapshot after it, XORed together so unchanged bytes become runs of
+// zeros, which are then run length encoded. Stepping back applies the newest
+// difference to the newest snapshot, which recovers the one before it.
+//
+// The history is a ring buffer bounded both by a number of snapshots and by
+// the memory they use, with the oldest snapshots dropped first.
+
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+use crate::state;
+
+pub struct Rewind {
+    // The most recent snapshot, whole.
+    newest: Option<Vec<u8>>,
+    // Differences between consecutive snapshots, oldest first.
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+    memory_cap: usize,
+    memory_used: usize,
+}
+
+impl Rewind {
+    // Keeps up to `capacity` snapshots, using up to `memory_cap` bytes.
+    pub fn new(capacity: usize, memory_cap: usize) -> Self {
+        Self {
+            newest: None,
+            deltas: VecDeque::new(),
+            capacity,
+            memory_cap,
+            memory_used: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && self.memory_cap > 0
+    }
+
+    // The number of snapshots that can be stepped back through.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn record(&mut self, cpu: &Chip8) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let state = cpu.save_state();
+
+        if let Some(previous) = self.newest.take() {
+            let delta = encode(&previous, &state);
+            self.memory_used += delta.len();
+            self.deltas.push_back(delta);
+        }
+
+        self.newest = Some(state);
+
+        while self.deltas.len() >= self.capacity || self.memory_used() > self.memory_cap {
+            match self.deltas.pop_front() {
+                Some(delta) => self.memory_used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Restores the snapshot before the newest one, returning false when
+    // there is no more history. A snapshot that doesn't load, because the
+    // machine has changed platform since it was taken, is an error and the
+    // history is cleared.
+    pub fn step_back(&mut self, cpu: &mut Chip8) -> Result<bool, state::Error> {
+        let (newest, delta) = match (self.newest.take(), self.deltas.pop_back()) {
+            (Some(newest), Some(delta)) => (newest, delta),
+            (newest, _) => {
+                self.newest = newest;
+                return Ok(false);
+            }
+        };
+
+        self.memory_used -= delta.len();
+        let previous = decode(&newest, &delta);
+
+        if let Err(err) = cpu.load_state(&previous) {
+            self.clear();
+            return Err(err);
+        }
+        self.newest = Some(previous);
+
+        Ok(true)
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.memory_used = 0;
+    }
+
+    fn memory_used(&self) -> usize {
+        self.memory_used + self.newest.as_ref().map_or(0, |newest| newest.len())
+    }
+}
+
+// Encodes `older` relative to `newer`. Save states can differ in length, as
+// the thumbnail is smaller in low resolution, so the length of `older` comes
+// first. Then the XOR of the two, with missing bytes read as zero, as a list
+// of (zero run, literal count, literals), each count a variable length
+// integer.
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut delta = vec![];
+    write_length(&mut delta, older.len());
+
+    let xor: Vec<u8> = (0..older.len())
+        .map(|index| older[index] ^ newer.get(index).copied().unwrap_or(0))
+        .collect();
+
+    let mut index = 0;
+    while index < xor.len() {
+        let zeros = xor[index..].iter().take_while(|byte| **byte == 0).count();
+        index += zeros;
+
+        let literals = xor[index..].iter().take_while(|byte| **byte != 0).count();
+
+        write_length(&mut delta, zeros);
+        write_length(&mut delta, literals);
+        delta.extend_from_slice(&xor[index..index + literals]);
+        index += literals;
+    }
+
+    delta
+}
+
+fn decode(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_length(delta, &mut position);
+
+    let mut older: Vec<u8> = (0..length)
+        .map(|index| newer.get(index).copied().unwrap_or(0))
+        .collect();
+
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_length(delta, &mut position);
+        let literals = read_length(delta, &mut position);
+
+        for byte in &delta[position..position + literals] {
+            older[index] ^= byte;
+            index += 1;
+        }
+        position += literals;
+    }
+
+    older
+}
+
+// Lengths are stored 7 bits at a time, with the high bit set on every byte
+// but the last.
+fn write_length(bytes: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        bytes.push((length as u8 & 0x7F) | 0x80);
+        length >>= 7;
+    }
+    bytes.push(length as u8);
+}
+
+fn read_length(bytes: &[u8], position: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        length |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::LoadOptions;
+    use crate::input::Input;
+    use crate::quirks::Platform;
+
+    fn lengths(values: &[usize]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for value in values {
+            write_length(&mut bytes, *value);
+        }
+        bytes
+    }
+
+    #[test]
+    fn stores_lengths_7_bits_at_a_time() {
+        assert_eq!(lengths(&[0, 1, 0x7F]), [0x00, 0x01, 0x7F]);
+        assert_eq!(lengths(&[0x80]), [0x80, 0x01]);
+        assert_eq!(lengths(&[300]), [0xAC, 0x02]);
+
+        let values = [0, 0x7F, 0x80, 0x3FFF, 0x4000, 0x10000, usize::MAX >> 1];
+        let bytes = lengths(&values);
+        let mut position = 0;
+        for value in values {
+            assert_eq!(read_length(&bytes, &mut position), value);
+        }
+        assert_eq!(position, bytes.len());
+    }
+
+    #[test]
+    fn run_length_encodes_the_xor() {
+        let newer = [1, 2, 3, 4, 5, 6];
+        let older = [1, 2, 0, 4, 5, 7];
+
+        // The length, then 2 zeros and a literal, then 2 zeros and a literal.
+        let delta = encode(&older, &newer);
+        assert_eq!(delta, [6, 2, 1, 3, 2, 1, 1]);
+        assert_eq!(decode(&newer, &delta), older);
+    }
+
+    #[test]
+    fn decodes_snapshots_of_other_lengths() {
+        let newer = [9; 10];
+
+        for older in [vec![], vec![9, 9, 1], vec![7; 300]] {
+            assert_eq!(decode(&newer, &encode(&older, &newer)), older);
+        }
+    }
+
+    // Records `count` snapshots, one before each step, returning them.
+    fn snapshots(rewind: &mut Rewind, cpu: &mut Chip8, count: usize) -> Vec<Vec<u8>> {
+        let mut states = vec![];
+        for _ in 0..count {
+            rewind.record(cpu);
+            states.push(cpu.save_state());
+            cpu.step(&Input::new()).ok();
+        }
+        states
+    }
+
+    // A machine that counts up in V0 forever.
+    fn counter() -> Chip8 {
+        let mut cpu = Chip8::new();
+        // ADD V0, 1 then JP 0x200.
+        cpu.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00], LoadOptions::new())
+            .unwrap();
+        cpu
+    }
+
+    #[test]
+    fn steps_back_through_history() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::new(100, 1 << 20);
+        let states = snapshots(&mut rewind, &mut cpu, 6);
+
+        // The newest snapshot is where stepping back starts from.
+        assert_eq!(rewind.len(), 5);
+        for state in states.iter().rev().skip(1) {
+            assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+            assert_eq!(&cpu.save_state(), state);
+        }
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::new(4, 1 << 20);
+        let states = snapshots(&mut rewind, &mut cpu, 20);
+        assert_eq!(rewind.len(), 3);
+
+        while rewind.step_back(&mut cpu) == Ok(true) {}
+        assert_eq!(cpu.save_state(), states[16]);
+
+        // The newest snapshot is kept whole, so a cap just over one save
+        // state only leaves room for a few deltas.
+        let mut cpu = counter();
+        let cap = cpu.save_state().len() + 64;
+        let mut rewind = Rewind::new(100, cap);
+        snapshots(&mut rewind, &mut cpu, 50);
+        assert!(rewind.len() < 10);
+        assert!(rewind.memory_used <= cap);
+    }
+
+    #[test]
+    fn reports_snapshots_that_dont_load() {
+        let mut cpu = counter();
+        cpu.set_platform(Platform::Xochip);
+        let mut rewind = Rewind::new(100, 1 << 20);
+        snapshots(&mut rewind, &mut cpu, 3);
+
+        cpu.set_platform(Platform::Vip);
+        assert_eq!(
+            rewind.step_back(&mut cpu),
+            Err(state::Error::Invalid("memory size"))
+        );
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+// The random number generator behind Cxkk - RND Vx, byte.
+//
+// The generator is part of the machine's state, so that a run can be repeated
+// exactly from the same seed, and it is saved with everything else.
+//
+// Reference for the VIP algorithm:
+// https://laurencescotford.net/2020/07/19/chip-8-on-the-cosmac-vip-generating-random-numbers/
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Algorithm {
+    // A xorshift64* generator, which is fast and has no noticeable patterns.
+    #[default]
+    Xorshift,
+    // The arithmetic of the COSMAC VIP interpreter's routine, which has the
+    // VIP's patterns but not its exact numbers. See `next_vip`.
+    Vip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    algorithm: Algorithm,
+    // The xorshift state, which must never be zero.
+    state: u64,
+    // The VIP's R9 register.
+    r9: u16,
+}
+
+impl Rng {
+    pub fn new(seed: u64, algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            // NOTE: A zero state would only ever produce zeros, so the seed is
+            //       mixed first, and a zero result replaced.
+            state: match splitmix(seed) {
+                0 => 0x9E37_79B9_7F4A_7C15,
+                state => state,
+            },
+            r9: seed as u16,
+        }
+    }
+
+    // A seed from the operating system, for when runs don't need to repeat.
+    pub fn from_entropy(algorithm: Algorithm) -> Self {
+        Self::new(rand::random(), algorithm)
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn next(&mut self, ram: &[u8]) -> u8 {
+        match self.algorithm {
+            Algorithm::Xorshift => {
+                self.state ^= self.state >> 12;
+                self.state ^= self.state << 25;
+                self.state ^= self.state >> 27;
+                (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+            }
+            Algorithm::Vip => self.next_vip(ram),
+        }
+    }
+
+    // The VIP interpreter steps R9, then adds the byte at 0x100 plus its low
+    // byte to its high byte. That sum is shifted right through the carry and
+    // added to itself, giving both the random number and the new high byte.
+    // NOTE: 0x100 to 0x1FF is the interpreter's own code on a VIP. That code
+    //       isn't in memory here, so unless a rom writes there the bytes are
+    //       zero and the numbers differ from the hardware's.
+    fn next_vip(&mut self, ram: &[u8]) -> u8 {
+        self.r9 = self.r9.wrapping_add(1);
+
+        let [high, low] = self.r9.to_be_bytes();
+        let (sum, carry) = ram[0x100 + low as usize].overflowing_add(high);
+        let shifted = (sum >> 1) | ((carry as u8) << 7);
+        let result = shifted.wrapping_add(sum);
+
+        self.r9 = u16::from_be_bytes([result, low]);
+
+        result
+    }
+
+    // The VIP's display interrupt also steps R9, 60 times a second.
+    pub fn tick(&mut self) {
+        if self.algorithm == Algorithm::Vip {
+            self.r9 = self.r9.wrapping_add(1);
+        }
+    }
+
+    // The raw state, for save states.
+    pub fn to_parts(&self) -> (Algorithm, u64, u16) {
+        (self.algorithm, self.state, self.r9)
+    }
+
+    pub fn from_parts(algorithm: Algorithm, state: u64, r9: u16) -> Option<Self> {
+        if state == 0 {
+            return None;
+        }
+
+        Some(Self {
+            algorithm,
+            state,
+            r9,
+        })
+    }
+}
+
+fn splitmix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(rng: &mut Rng, ram: &[u8]) -> Vec<u8> {
+        (0..8).map(|_| rng.next(ram)).collect()
+    }
+
+    #[test]
+    fn repeats_from_the_same_seed() {
+        let ram = [0; 0x200];
+
+        let mut rng = Rng::new(1234, Algorithm::Xorshift);
+        assert_eq!(
+            sequence(&mut rng, &ram),
+            sequence(&mut Rng::new(1234, Algorithm::Xorshift), &ram)
+        );
+        assert_ne!(
+            sequence(&mut rng, &ram),
+            sequence(&mut Rng::new(1235, Algorithm::Xorshift), &ram)
+        );
+
+        // Zero is a valid seed too.
+        let mut rng = Rng::new(0, Algorithm::Xorshift);
+        assert_ne!(sequence(&mut rng, &ram), [0; 8]);
+    }
+
+    #[test]
+    fn follows_the_vip_routine() {
+        let mut ram = [0; 0x200];
+
+        // R9 = 0x0100 steps to 0x0101, then 0x01 + ram[0x101] = 0x03, and
+        // 0x03 + (0x03 >> 1) = 0x04.
+        ram[0x101] = 0x02;
+        let mut rng = Rng::new(0x0100, Algorithm::Vip);
+        assert_eq!(rng.next(&ram), 0x04);
+        assert_eq!(rng.to_parts().2, 0x0401);
+
+        // With the carry shifted back in: 0xF0 + 0x20 = 0x110, so the sum is
+        // 0x10 and the shift gives 0x88.
+        ram[0x101] = 0x20;
+        let mut rng = Rng::new(0xF000, Algorithm::Vip);
+        assert_eq!(rng.next(&ram), 0x98);
+
+        // The same seed and memory give the same numbers.
+        let mut rng = Rng::new(42, Algorithm::Vip);
+        assert_eq!(
+            sequence(&mut rng, &ram),
+            sequence(&mut Rng::new(42, Algorithm::Vip), &ram)
+        );
+    }
+
+    #[test]
+    fn restores_from_its_parts() {
+        let mut rng = Rng::new(7, Algorithm::Xorshift);
+        rng.next(&[]);
+
+        let (algorithm, state, r9) = rng.to_parts();
+        let mut copy = Rng::from_parts(algorithm, state, r9).unwrap();
+        assert_eq!(sequence(&mut copy, &[]), sequence(&mut rng, &[]));
+
+        assert_eq!(Rng::from_parts(Algorithm::Xorshift, 0, 0), None);
+    }
+}
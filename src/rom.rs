@@ -0,0 +1,232 @@
+// Reading roms to run from files.
+//
+// A path of `-` reads the rom from standard input, and Octo source files
+// (.8o) and cartridges (.gif, see cartridge.rs) are compiled into a rom
+// first. Roms can also be in zip and gzip archives, see archive.rs. `Chip8::load_rom` and the command line both read
+// roms this way, so a path means the same thing to either.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::archive;
+use crate::asm;
+use crate::cartridge::{self, Cartridge};
+use crate::database::Settings;
+use crate::octo;
+
+// A rom ready to be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    pub bytes: Vec<u8>,
+    // The settings an Octo cartridge was saved with.
+    pub settings: Option<Settings>,
+}
+
+impl Rom {
+    pub fn read(path: &str) -> Result<Self, Error> {
+        Self::read_choosing(path, |_| None)
+    }
+
+    // Reads a rom like `read`, but when a zip archive holds several roms and
+    // the path doesn't pick one, `choose` is given their names to pick from.
+    // Choosing none leaves it an error.
+    pub fn read_choosing(
+        path: &str,
+        choose: impl FnOnce(&[String]) -> Option<String>,
+    ) -> Result<Self, Error> {
+        if path.ends_with(".8o") {
+            let source = fs::read_to_string(path)?;
+            let bytes = octo::compile(&source).map_err(Error::Compile)?;
+
+            return Ok(Self {
+                bytes,
+                settings: None,
+            });
+        }
+
+        let (file, name) = archive::split_path(path);
+        let bytes = read_file(file)?;
+
+        let bytes = match archive::extract(&bytes, name) {
+            Err(archive::Error::Ambiguous(names)) => match choose(&names) {
+                Some(name) => archive::extract(&bytes, Some(&name)),
+                None => Err(archive::Error::Ambiguous(names)),
+            },
+            result => result,
+        }?;
+
+        if !cartridge::is_cartridge(&bytes) {
+            return Ok(Self {
+                bytes,
+                settings: None,
+            });
+        }
+
+        let cartridge = Cartridge::decode(&bytes)?;
+        let bytes = octo::compile(&cartridge.program).map_err(Error::Compile)?;
+
+        Ok(Self {
+            bytes,
+            settings: Some(cartridge.settings),
+        })
+    }
+}
+
+// Reads a file, or standard input when the path is `-`.
+pub fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut bytes = vec![];
+        io::stdin().read_to_end(&mut bytes)?;
+
+        return Ok(bytes);
+    }
+
+    fs::read(path)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // The Octo source didn't compile.
+    Compile(asm::Error),
+    // The rom couldn't be taken out of its archive.
+    Archive(archive::Error),
+    // The GIF isn't a cartridge Octo could read.
+    Cartridge(cartridge::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Compile(err) => write!(f, "line {}: {}", err.line, err.message),
+            Error::Archive(err) => write!(f, "{}", err),
+            Error::Cartridge(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Compile(_) => None,
+            Error::Archive(err) => Some(err),
+            Error::Cartridge(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<archive::Error> for Error {
+    fn from(error: archive::Error) -> Self {
+        Error::Archive(error)
+    }
+}
+
+impl From<cartridge::Error> for Error {
+    fn from(error: cartridge::Error) -> Self {
+        Error::Cartridge(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    // A file in the temporary directory, removed when the test is done.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("crab-8-{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn reads_roms() {
+        let file = TempFile::new("rom.ch8", &[0x00, 0xE0]);
+        assert_eq!(Rom::read(file.path()).unwrap().bytes, [0x00, 0xE0]);
+
+        let missing = file.path().replace("rom.ch8", "missing.ch8");
+        assert!(matches!(Rom::read(&missing), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn compiles_octo_source() {
+        let source = ": main\n  clear\n";
+        let file = TempFile::new("compiles.8o", source.as_bytes());
+        assert_eq!(
+            Rom::read(file.path()).unwrap().bytes,
+            octo::compile(source).unwrap()
+        );
+
+        let file = TempFile::new("fails.8o", b": main\n  clear\n  nonsense\n");
+        match Rom::read(file.path()) {
+            Err(Error::Compile(err)) => assert_eq!(err.line, 3),
+            result => panic!("expected a compile error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reads_cartridges_with_their_settings() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cartridges/face.gif");
+        let cartridge = Cartridge::decode(&fs::read(path).unwrap()).unwrap();
+
+        let rom = Rom::read(path).unwrap();
+        assert_eq!(rom.bytes, octo::compile(&cartridge.program).unwrap());
+        assert_eq!(rom.settings, Some(cartridge.settings));
+
+        let file = TempFile::new("broken.gif", b"GIF89a");
+        assert!(matches!(Rom::read(file.path()), Err(Error::Cartridge(_))));
+    }
+
+    #[test]
+    fn asks_which_rom_to_read_from_an_archive() {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, rom) in [("a.ch8", [0x00, 0xE0]), ("b.ch8", [0x00, 0xEE])] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(&rom).unwrap();
+        }
+        let file = TempFile::new("roms.zip", &zip.finish().unwrap().into_inner());
+
+        let mut offered = vec![];
+        let rom = Rom::read_choosing(file.path(), |names| {
+            offered = names.to_vec();
+            Some("b.ch8".to_string())
+        });
+        assert_eq!(rom.unwrap().bytes, [0x00, 0xEE]);
+        assert_eq!(offered, ["a.ch8", "b.ch8"]);
+
+        // Without a choice it stays ambiguous, unless the path picks one.
+        assert!(matches!(
+            Rom::read(file.path()),
+            Err(Error::Archive(archive::Error::Ambiguous(_)))
+        ));
+        let path = format!("{}:a.ch8", file.path());
+        assert_eq!(Rom::read(&path).unwrap().bytes, [0x00, 0xE0]);
+    }
+}
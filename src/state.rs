@@ -0,0 +1,325 @@
+// Save states.
+//
+// A save state is a snapshot of everything a running program can observe, so
+// that it can be resumed later exactly where it left off. The format is a
+// small fixed header followed by the machine state, all big endian:
+//
+//     magic      4 bytes   "C8ST"
+//     version    u16       FORMAT_VERSION
+//     thumbnail  u8 width, u8 height, then 2 bits per pixel, row by row
+//     state      see `Chip8::save_state`
+//
+// The thumbnail comes first so that a frontend can preview a slot without
+// understanding the rest of the state. Any change to the layout must bump
+// FORMAT_VERSION, and states from newer versions are rejected rather than
+// being misread.
+
+use std::fmt;
+
+use crate::archive;
+use crate::display::{Display, PIXEL_COUNT};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+// 1 - The first version.
+// 2 - Adds the random number generator.
+pub const FORMAT_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    NotASaveState,
+    UnsupportedVersion(u16),
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotASaveState => write!(f, "not a save state"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is newer than the supported version {}",
+                version, FORMAT_VERSION
+            ),
+            Error::Truncated => write!(f, "save state is truncated"),
+            Error::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// A preview of the display, with one palette index per pixel.
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+// Reads just the thumbnail of a save state.
+pub fn thumbnail(bytes: &[u8]) -> Result<Thumbnail, Error> {
+    let mut reader = Reader::new(bytes);
+    reader.header()?;
+    reader.thumbnail()
+}
+
+// Where a rom's states are saved: next to the rom, e.g. `game.ch8.3.state`
+// for slot 3. The states of a rom in an archive go next to the archive, with
+// the rom's name inside it flattened, e.g. `games.zip.schip_blinky.sc8.3.state`.
+// A rom read from standard input has no name, so its states are kept in the
+// current directory as `stdin.3.state`.
+pub fn path(rom: &str, slot: usize) -> String {
+    let (file, name) = archive::split_path(rom);
+    let file = if file == "-" { "stdin" } else { file };
+
+    match name {
+        Some(name) => format!(
+            "{}.{}.{}.state",
+            file,
+            name.replace(['/', '\\', ':'], "_"),
+            slot
+        ),
+        None => format!("{}.{}.state", file, slot),
+    }
+}
+
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    // Starts a state with the header and a thumbnail of the display.
+    pub fn new(display: &Display) -> Self {
+        let mut writer = Self { bytes: vec![] };
+
+        writer.bytes(MAGIC);
+        writer.u16(FORMAT_VERSION);
+
+        let (width, height) = (display.width(), display.height());
+        writer.u8(width as u8);
+        writer.u8(height as u8);
+
+        for pixels in display.memory[..width * height].chunks(4) {
+            let packed = pixels
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (n, pixel)| byte | (pixel & 0b11) << (6 - n * 2));
+            writer.u8(packed);
+        }
+
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    // Checks the magic and version, returning the version.
+    pub fn header(&mut self) -> Result<u16, Error> {
+        if self.bytes.get(..4) != Some(MAGIC) {
+            return Err(Error::NotASaveState);
+        }
+        self.position = 4;
+
+        let version = self.u16()?;
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        Ok(version)
+    }
+
+    pub fn thumbnail(&mut self) -> Result<Thumbnail, Error> {
+        let width = self.u8()? as usize;
+        let height = self.u8()? as usize;
+
+        if width * height > PIXEL_COUNT {
+            return Err(Error::Invalid("thumbnail size"));
+        }
+
+        let packed = self.bytes((width * height).div_ceil(4))?;
+        let pixels = (0..width * height)
+            .map(|n| (packed[n / 4] >> (6 - (n % 4) * 2)) & 0b11)
+            .collect();
+
+        Ok(Thumbnail {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid("flag")),
+        }
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or(Error::Truncated)?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    // Fails if anything is left over, which means the state isn't the layout
+    // we expect for its version.
+    pub fn finish(self) -> Result<(), Error> {
+        if self.position != self.bytes.len() {
+            return Err(Error::Invalid("length"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Vec<u8> {
+        let mut display = Display::new();
+        display.set(1, 0, 1, true);
+        display.set(2, 0, 2, true);
+        display.set(63, 31, 3, true);
+
+        let mut writer = Writer::new(&display);
+        writer.u16(0x1234);
+        writer.finish()
+    }
+
+    #[test]
+    fn reads_the_thumbnail() {
+        let thumbnail = thumbnail(&state()).unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (64, 32));
+        assert_eq!(thumbnail.pixels[..4], [0, 1, 2, 0]);
+        assert_eq!(thumbnail.pixels[64 * 32 - 1], 3);
+        assert_eq!(
+            thumbnail.pixels.iter().filter(|pixel| **pixel != 0).count(),
+            3
+        );
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let bytes = state();
+        let mut reader = Reader::new(&bytes);
+
+        assert_eq!(reader.header(), Ok(FORMAT_VERSION));
+        reader.thumbnail().unwrap();
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_other_files_and_newer_versions() {
+        assert!(matches!(thumbnail(b"GIF89a"), Err(Error::NotASaveState)));
+
+        let mut bytes = state();
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            thumbnail(&bytes),
+            Err(Error::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_and_overlong_states() {
+        let bytes = state();
+
+        let mut reader = Reader::new(&bytes[..bytes.len() - 1]);
+        reader.header().unwrap();
+        reader.thumbnail().unwrap();
+        assert_eq!(reader.u16(), Err(Error::Truncated));
+
+        assert!(matches!(thumbnail(&bytes[..20]), Err(Error::Truncated)));
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        let mut reader = Reader::new(&longer);
+        reader.header().unwrap();
+        reader.thumbnail().unwrap();
+        reader.u16().unwrap();
+        assert_eq!(reader.finish(), Err(Error::Invalid("length")));
+    }
+
+    #[test]
+    fn names_states_after_the_rom() {
+        assert_eq!(path("roms/game.ch8", 3), "roms/game.ch8.3.state");
+        assert_eq!(path("-", 1), "stdin.1.state");
+        assert_eq!(
+            path("-:schip/blinky.sc8", 1),
+            "stdin.schip_blinky.sc8.1.state"
+        );
+
+        let archive = std::env::temp_dir().join(format!("crab-8-{}-games.zip", std::process::id()));
+        std::fs::write(&archive, b"").unwrap();
+        let archive = archive.to_str().unwrap();
+
+        let state = path(&format!("{}:schip/a:b.sc8", archive), 2);
+        assert_eq!(state, format!("{}.schip_a_b.sc8.2.state", archive));
+
+        std::fs::remove_file(archive).ok();
+    }
+}
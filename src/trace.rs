@@ -0,0 +1,690 @@
+// Execution traces.
+//
+// `--trace <FILE>` writes one JSON object per line for every instruction the
+// program runs, e.g.
+//
+//     {"cycle":41,"pc":598,"opcode":53264,"mnemonic":"DRW V0, V1, 0","registers":{"VF":0}}
+//
+// `cycle` counts instructions from the start of the trace, and `registers`
+// holds the new values of the registers that changed since the previous
+// record. Changes made between instructions, such as the timers ticking down
+// at the end of a frame, show up in the next record. With `--trace-memory`,
+// each record also lists the bytes the instruction wrote to memory:
+//
+//     "memory":[{"addr":928,"bytes":[0,1,7]}]
+//
+// The records are meant for tools like jq, and for comparing runs with each
+// other or with other emulators.
+//
+// `crab-8 trace-diff` reads these traces, or traces in a simpler text format
+// that is easy to get other emulators to print. Each line is the address and
+// opcode of an instruction in hex, followed by the registers it set:
+//
+//     0206 F015 DT=07
+//
+// Register values are hex too. Registers keep their values until a line sets
+// them again, so a line can give every register or only the changed ones.
+// Blank lines and lines starting with `#` are ignored.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chip8::{Chip8, Register};
+use crate::instruction::{decode, Instruction};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    // The first two bytes of the instruction. XO-CHIP's F000 NNNN is four
+    // bytes long, and its address is only in the mnemonic.
+    pub opcode: u16,
+    pub mnemonic: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub registers: BTreeMap<String, u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<MemoryWrite>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+}
+
+// Every register except the program counter, which each record has anyway.
+const REGISTERS: [Register; 20] = [
+    Register::V(0x0),
+    Register::V(0x1),
+    Register::V(0x2),
+    Register::V(0x3),
+    Register::V(0x4),
+    Register::V(0x5),
+    Register::V(0x6),
+    Register::V(0x7),
+    Register::V(0x8),
+    Register::V(0x9),
+    Register::V(0xA),
+    Register::V(0xB),
+    Register::V(0xC),
+    Register::V(0xD),
+    Register::V(0xE),
+    Register::V(0xF),
+    Register::I,
+    Register::Sp,
+    Register::Dt,
+    Register::St,
+];
+
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    cycle: u64,
+    // The registers as of the previous record.
+    registers: [u16; 20],
+    // Whether to record memory writes, and those made by the instruction
+    // being executed.
+    trace_memory: bool,
+    writes: Vec<MemoryWrite>,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, trace_memory: bool) -> Self {
+        Self {
+            writer,
+            cycle: 0,
+            registers: [0; 20],
+            trace_memory,
+            writes: vec![],
+        }
+    }
+
+    pub fn create(path: &str, trace_memory: bool) -> io::Result<Self> {
+        let file = File::create(path)?;
+
+        Ok(Self::new(Box::new(BufWriter::new(file)), trace_memory))
+    }
+
+    // Takes the registers to compare the first record against.
+    pub(crate) fn start(&mut self, cpu: &Chip8) {
+        self.registers = Self::registers(cpu);
+    }
+
+    pub(crate) fn write(&mut self, addr: usize, bytes: &[u8]) {
+        if self.trace_memory {
+            self.writes.push(MemoryWrite {
+                addr: addr as u16,
+                bytes: bytes.to_vec(),
+            });
+        }
+    }
+
+    // Forgets the writes of an instruction that failed part way through.
+    pub(crate) fn discard(&mut self) {
+        self.writes.clear();
+    }
+
+    // Records the instruction at `pc`, which `cpu` has just executed.
+    pub(crate) fn record(
+        &mut self,
+        cpu: &Chip8,
+        pc: usize,
+        instruction: Instruction,
+    ) -> io::Result<()> {
+        let current = Self::registers(cpu);
+        let registers = REGISTERS
+            .iter()
+            .zip(current.iter().zip(self.registers.iter()))
+            .filter(|(_, (new, old))| new != old)
+            .map(|(register, (new, _))| (register.to_string(), *new))
+            .collect();
+        self.registers = current;
+
+        let bytes = instruction.to_bytes();
+        let record = Record {
+            cycle: self.cycle,
+            pc: pc as u16,
+            opcode: u16::from_be_bytes([bytes[0], bytes[1]]),
+            mnemonic: instruction.to_string(),
+            registers,
+            memory: std::mem::take(&mut self.writes),
+        };
+        self.cycle += 1;
+
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn registers(cpu: &Chip8) -> [u16; 20] {
+        REGISTERS.map(|register| {
+            cpu.register(register)
+                .expect("the traced registers all exist")
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// One instruction of a trace, with the registers as they were after it ran.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub pc: u16,
+    pub opcode: u16,
+    pub mnemonic: String,
+    pub registers: BTreeMap<String, u16>,
+}
+
+pub struct Trace {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Trace {
+    // Reads a trace in either format. The formats can't be mixed in one trace.
+    pub fn parse(name: &str, text: &str) -> Result<Self, Error> {
+        let mut steps: Vec<Step> = vec![];
+        let mut registers = BTreeMap::new();
+        let mut json = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| Error {
+                line: index + 1,
+                message,
+            };
+
+            // Traces written by `Tracer` start from a machine that was just
+            // reset, so the registers they don't mention are zero.
+            let is_json = line.starts_with('{');
+            if *json.get_or_insert(is_json) != is_json {
+                return Err(error("JSON and text records can't be mixed".to_string()));
+            }
+            if is_json && steps.is_empty() {
+                registers = REGISTERS
+                    .iter()
+                    .map(|register| (register.to_string(), 0))
+                    .collect();
+            }
+
+            let record = if is_json {
+                serde_json::from_str(line).map_err(|err| error(err.to_string()))?
+            } else {
+                parse_text(line, steps.len() as u64).map_err(error)?
+            };
+            registers.extend(record.registers);
+
+            steps.push(Step {
+                pc: record.pc,
+                opcode: record.opcode,
+                mnemonic: record.mnemonic,
+                registers: registers.clone(),
+            });
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            steps,
+        })
+    }
+}
+
+// Reads a line of the text format as a record.
+fn parse_text(line: &str, cycle: u64) -> Result<Record, String> {
+    let hex = |text: &str| {
+        u16::from_str_radix(text, 16).map_err(|_| format!("`{}` is not a hex number", text))
+    };
+
+    let mut fields = line.split_whitespace();
+    let pc = hex(fields.next().expect("blank lines are skipped"))?;
+    let opcode = hex(fields.next().ok_or("missing the opcode")?)?;
+
+    let registers = fields
+        .map(|field| {
+            let (name, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected NAME=VALUE, found `{}`", field))?;
+            let register = Register::from_str(name)
+                .ok()
+                .filter(|register| REGISTERS.contains(register))
+                .ok_or_else(|| format!("unknown register `{}`", name))?;
+
+            Ok((register.to_string(), hex(value)?))
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Record {
+        cycle,
+        pc,
+        opcode,
+        mnemonic: decode(opcode)
+            .map(|instruction| instruction.to_string())
+            .unwrap_or_else(|_| "?".to_string()),
+        registers,
+        memory: vec![],
+    })
+}
+
+// Where two traces first differ.
+pub struct Divergence<'a> {
+    // The index of the first instruction that differs. The traces are paired
+    // up by index, so this is the same in both.
+    pub index: usize,
+    // What differs about it, e.g. "the opcode differs".
+    pub reason: String,
+    traces: [&'a Trace; 2],
+    context: usize,
+}
+
+// Compares two traces instruction by instruction from the start, and finds
+// the first that ran at a different address, was a different opcode, or left
+// different values in the registers. Registers only one of the traces gives
+// aren't compared.
+pub fn diff<'a>(a: &'a Trace, b: &'a Trace, context: usize) -> Option<Divergence<'a>> {
+    let divergence = |index, reason: String| Divergence {
+        index,
+        reason,
+        traces: [a, b],
+        context,
+    };
+
+    for (index, (x, y)) in a.steps.iter().zip(&b.steps).enumerate() {
+        let reason = if x.pc != y.pc {
+            Some("the address differs".to_string())
+        } else if x.opcode != y.opcode {
+            Some("the opcode differs".to_string())
+        } else {
+            REGISTERS
+                .iter()
+                .map(|register| register.to_string())
+                .find(|name| {
+                    matches!(
+                        (x.registers.get(name), y.registers.get(name)),
+                        (Some(x), Some(y)) if x != y
+                    )
+                })
+                .map(|name| format!("{} differs", name))
+        };
+
+        if let Some(reason) = reason {
+            return Some(divergence(index, reason));
+        }
+    }
+
+    let (shorter, longer) = if a.steps.len() < b.steps.len() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    (shorter.steps.len() < longer.steps.len())
+        .then(|| divergence(shorter.steps.len(), format!("{} ends", shorter.name)))
+}
+
+impl fmt::Display for Divergence<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b] = self.traces;
+        let column = |trace: &Trace, index: usize| match trace.steps.get(index) {
+            Some(step) => format!("{:04X} {:04X}  {}", step.pc, step.opcode, step.mnemonic),
+            None if index == trace.steps.len() => "(end of trace)".to_string(),
+            None => String::new(),
+        };
+
+        writeln!(
+            f,
+            "Traces diverge at instruction {} (counting from the first line of each): {}",
+            self.index, self.reason
+        )?;
+        writeln!(f)?;
+
+        // The instructions either side, as far as the longer trace goes. The
+        // first trace's column is as wide as its widest line.
+        let last = a.steps.len().max(b.steps.len()).saturating_sub(1);
+        let lines = self.index.saturating_sub(self.context)
+            ..=self
+                .index
+                .saturating_add(self.context)
+                .min(last)
+                .max(self.index);
+        let width = lines
+            .clone()
+            .map(|index| column(a, index).len())
+            .chain([a.name.len()])
+            .max()
+            .unwrap_or(0);
+
+        writeln!(f, "  {:>8}  {:<width$}  {}", "", a.name, b.name)?;
+        for index in lines {
+            let marker = if index == self.index { '>' } else { ' ' };
+            let line = format!(
+                "{} {:>8}  {:<width$}  {}",
+                marker,
+                index,
+                column(a, index),
+                column(b, index)
+            );
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Registers after instruction {}:", self.index)?;
+        let width = a.name.len().max(4);
+        writeln!(f, "  {:<4}  {:<width$}  {}", "", a.name, b.name)?;
+
+        let value = |trace: &Trace, name: &str| {
+            trace
+                .steps
+                .get(self.index)
+                .and_then(|step| step.registers.get(name))
+                .copied()
+        };
+
+        for register in REGISTERS {
+            let name = register.to_string();
+            let (x, y) = (value(a, &name), value(b, &name));
+            let marker = match (x, y) {
+                (None, None) => continue,
+                (Some(x), Some(y)) if x != y => '>',
+                _ => ' ',
+            };
+
+            let digits = if register == Register::I { 4 } else { 2 };
+            let hex = |value: Option<u16>| match value {
+                Some(value) => format!("{:0digits$X}", value),
+                None => "-".to_string(),
+            };
+            writeln!(f, "{} {:<4}  {:<width$}  {}", marker, name, hex(x), hex(y))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::chip8::LoadOptions;
+    use crate::input::Input;
+
+    // A writer whose output the test can still read after handing it over.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_traced(rom: &[u8], trace_memory: bool, steps: usize) -> Vec<Record> {
+        let output = Shared::default();
+        let mut cpu = Chip8::new();
+        cpu.load_rom_bytes(rom, LoadOptions::new()).unwrap();
+        cpu.set_tracer(Tracer::new(Box::new(output.clone()), trace_memory));
+
+        for _ in 0..steps {
+            cpu.step(&Input::new()).ok();
+        }
+
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn registers(changes: &[(&str, u16)]) -> BTreeMap<String, u16> {
+        changes
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn records_the_registers_that_changed() {
+        // LD V0, 5, LD V0, 5, LD I, 0x300, then CALL 0x208 there.
+        let records = run_traced(&[0x60, 0x05, 0x60, 0x05, 0xA3, 0x00, 0x22, 0x08], false, 4);
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records[0],
+            Record {
+                cycle: 0,
+                pc: 0x200,
+                opcode: 0x6005,
+                mnemonic: "LD V0, 5".to_string(),
+                registers: registers(&[("V0", 5)]),
+                memory: vec![],
+            }
+        );
+        // Setting a register to the value it has isn't a change.
+        assert!(records[1].registers.is_empty());
+        assert_eq!(records[2].registers, registers(&[("I", 0x300)]));
+        assert_eq!(records[3].registers, registers(&[("SP", 1)]));
+        assert_eq!(records[3].cycle, 3);
+    }
+
+    #[test]
+    fn records_memory_writes() {
+        // LD V0, 123, LD I, 0x300, LD B, V0, then LD [I], V1.
+        let rom = [0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x55];
+
+        let records = run_traced(&rom, true, 4);
+        assert!(records[..2].iter().all(|record| record.memory.is_empty()));
+        assert_eq!(
+            records[2].memory,
+            [MemoryWrite {
+                addr: 0x300,
+                bytes: vec![1, 2, 3],
+            }]
+        );
+        assert_eq!(
+            records[3].memory,
+            [MemoryWrite {
+                addr: 0x300,
+                bytes: vec![123, 0],
+            }]
+        );
+
+        let records = run_traced(&rom, false, 4);
+        assert!(records.iter().all(|record| record.memory.is_empty()));
+    }
+
+    #[test]
+    fn skips_instructions_that_fail() {
+        // LD I, 0xFFE, LD [I], V3, which runs past the end of memory.
+        let records = run_traced(&[0xAF, 0xFE, 0xF3, 0x55], true, 2);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].mnemonic, "LD I, 0xFFE");
+    }
+
+    #[test]
+    fn writes_compact_lines() {
+        let output = Shared::default();
+        let mut tracer = Tracer::new(Box::new(output.clone()), false);
+        let mut cpu = Chip8::new();
+        cpu.load_rom_bytes(&[0x00, 0xE0], LoadOptions::new())
+            .unwrap();
+        tracer.start(&cpu);
+        tracer.record(&cpu, 0x200, Instruction::Cls).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "{\"cycle\":0,\"pc\":512,\"opcode\":224,\"mnemonic\":\"CLS\"}\n"
+        );
+    }
+
+    fn trace(name: &str, text: &str) -> Trace {
+        Trace::parse(name, text).unwrap_or_else(|err| panic!("{}: {}", name, err))
+    }
+
+    #[test]
+    fn parses_json() {
+        let trace = trace(
+            "a",
+            r#"{"cycle":0,"pc":512,"opcode":24583,"mnemonic":"LD V0, 7","registers":{"V0":7}}
+               {"cycle":1,"pc":514,"opcode":41888,"mnemonic":"LD I, 3A0","registers":{"I":928},"memory":[{"addr":928,"bytes":[1]}]}"#,
+        );
+
+        assert_eq!(trace.steps.len(), 2);
+        let step = &trace.steps[1];
+        assert_eq!((step.pc, step.opcode), (0x202, 0xA3A0));
+        assert_eq!(step.mnemonic, "LD I, 3A0");
+        // Registers carry over, and start at zero.
+        assert_eq!(step.registers["V0"], 7);
+        assert_eq!(step.registers["I"], 0x3A0);
+        assert_eq!(step.registers["VF"], 0);
+    }
+
+    #[test]
+    fn parses_text() {
+        let trace = trace(
+            "b",
+            "# comment\n\n0200 6007 V0=07\n0202 A3A0 i=3a0\n0204 F033\n0206 FFFF\n",
+        );
+
+        assert_eq!(trace.steps.len(), 4);
+        assert_eq!(trace.steps[0].mnemonic, "LD V0, 7");
+        assert_eq!(trace.steps[3].mnemonic, "?");
+        let registers = &trace.steps[2].registers;
+        assert_eq!(registers.get("V0"), Some(&7));
+        assert_eq!(registers.get("I"), Some(&0x3A0));
+        // Only the registers the text gives are known.
+        assert_eq!(registers.get("VF"), None);
+    }
+
+    #[test]
+    fn rejects_bad_traces() {
+        let error = |text| Trace::parse("bad", text).err().expect("the trace is bad");
+
+        let err = error(
+            "0200 6007\n# text, then\n{\"cycle\":1,\"pc\":514,\"opcode\":0,\"mnemonic\":\"\"}",
+        );
+        assert_eq!(err.line, 3);
+        assert_eq!(err.message, "JSON and text records can't be mixed");
+
+        let err = error("0200 6007\n0202 6107 PC=0204");
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "unknown register `PC`");
+
+        assert_eq!(error("0200").message, "missing the opcode");
+        assert_eq!(error("0200 60G7").message, "`60G7` is not a hex number");
+        assert_eq!(
+            error("0200 6007 V0").message,
+            "expected NAME=VALUE, found `V0`"
+        );
+        assert_eq!(error("{\"pc\":1}").line, 1);
+    }
+
+    #[test]
+    fn finds_where_registers_differ() {
+        let a = trace("a", "0200 6007 V0=07\n0202 7001 V0=08\n0204 7001 V0=09 I=0");
+        let b = trace("b", "0200 6007 V0=07\n0202 7001 V0=09\n0204 7001 V0=0A I=1");
+
+        let divergence = diff(&a, &b, 5).expect("the traces differ");
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.reason, "V0 differs");
+
+        // Registers only one trace gives aren't compared.
+        let c = trace("c", "0200 6007\n0202 7001 V1=01\n0204 7001");
+        assert!(diff(&a, &c, 5).is_none());
+    }
+
+    #[test]
+    fn finds_where_addresses_and_opcodes_differ() {
+        let a = trace("a", "0200 6007\n0202 1202\n0204 00E0");
+        let b = trace("b", "0200 6007\n0202 1204\n0204 00E0");
+        let c = trace("c", "0200 6007\n0202 1202\n0206 00E0");
+
+        let divergence = diff(&a, &b, 5).unwrap();
+        assert_eq!(
+            (divergence.index, divergence.reason.as_str()),
+            (1, "the opcode differs")
+        );
+        let divergence = diff(&a, &c, 5).unwrap();
+        assert_eq!(
+            (divergence.index, divergence.reason.as_str()),
+            (2, "the address differs")
+        );
+        assert!(diff(&a, &a, 5).is_none());
+    }
+
+    #[test]
+    fn finds_where_one_trace_ends() {
+        let a = trace("a", "0200 6007\n0202 1202");
+        let b = trace("b", "0200 6007\n0202 1202\n0202 1202\n0202 1202");
+
+        for (x, y) in [(&a, &b), (&b, &a)] {
+            let divergence = diff(x, y, 5).unwrap();
+            assert_eq!(divergence.index, 2);
+            assert_eq!(divergence.reason, "a ends");
+        }
+    }
+
+    #[test]
+    fn compares_instructions_by_position() {
+        // The second trace skips an instruction, and isn't realigned after it.
+        let a = trace("a", "0200 6000\n0202 6001\n0204 6002");
+        let b = trace("b", "0200 6000\n0204 6002");
+
+        let divergence = diff(&a, &b, 0).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.reason, "the address differs");
+        assert!(divergence
+            .to_string()
+            .starts_with("Traces diverge at instruction 1 (counting from the first line of each)"));
+    }
+
+    #[test]
+    fn shows_instructions_either_side() {
+        let a = trace("a", "0200 6000\n0202 6001\n0204 6002\n0206 6003\n0208 6004");
+        let b = trace("b", "0200 6000\n0202 6001\n0204 6102\n0206 6003\n0208 6004");
+
+        let text = diff(&a, &b, 1).unwrap().to_string();
+        let rows: Vec<&str> = text
+            .lines()
+            .skip_while(|line| !line.contains("0202"))
+            .take_while(|line| !line.is_empty())
+            .collect();
+        assert_eq!(rows.len(), 3, "{}", text);
+        assert!(rows[0].contains("LD V0, 1"));
+        assert!(rows[1].starts_with('>') && rows[1].contains("LD V1, 2"));
+        assert!(rows[2].contains("LD V0, 3"));
+
+        // After a trace ends, only the other one has instructions to show.
+        let c = trace("c", "0200 6000\n0202 6001");
+        let text = diff(&a, &c, 2).unwrap().to_string();
+        assert!(text.contains("(end of trace)"));
+        assert!(text.contains("LD V0, 4"));
+        assert_eq!(text.matches("(end of trace)").count(), 1);
+    }
+}